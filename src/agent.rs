@@ -0,0 +1,140 @@
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use futures::{StreamExt, stream::FuturesUnordered};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::sync::{mpsc, watch};
+use log::{info, warn};
+use crate::bluez;
+use crate::brightness;
+use crate::config::{load_config, Config};
+use crate::control::{self, Message, Request};
+use crate::daemon::SOCKET_PATH;
+use crate::dbus_service::{self, BacklightControl, Duo};
+use crate::monitor_handling;
+use crate::usb::{self, DeviceState};
+
+/// How long to wait before reconnecting after the daemon went away, e.g. while it restarts.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Where the agent passes on what the daemon sends.
+pub struct Outlets {
+    pub states: watch::Sender<Option<DeviceState>>,
+    /// The keyboard backlight level the daemon last set.
+    pub backlight: watch::Sender<Option<u8>>,
+    /// The keyboard's battery level the daemon last read.
+    pub battery: watch::Sender<Option<u8>>,
+    /// Layouts to apply on request.
+    pub layouts: mpsc::UnboundedSender<String>,
+    pub config: watch::Sender<Config>,
+}
+
+/// Run in the graphical session: apply the display layout whenever the keyboard is attached or
+/// removed, sync the panels' brightness, publish the state on the session bus, and notify when the
+/// keyboard's battery runs low.
+///
+/// The keyboard's state comes from the system daemon if it is running. Without one, the agent
+/// watches the keyboard itself and also handles the keyboard backlight and the bottom panel's
+/// touch, as far as the user may access those devices.
+pub async fn run(config: Config) {
+    let (config_sender, config) = watch::channel(config);
+    let (state_sender, states) = watch::channel(None);
+    let (layout_sender, layouts) = mpsc::unbounded_channel();
+    let watchers = FuturesUnordered::new();
+
+    watchers.push(tokio::task::spawn_local(usb::follow_keyboard(config.clone(), states.clone(), layouts)));
+    watchers.push(tokio::task::spawn_local(brightness::sync_brightness(config.clone())));
+
+    let (battery_sender, battery) = watch::channel(None);
+    let (backlight, backlight_control) = match connect(Path::new(SOCKET_PATH)).await {
+        Ok(stream) => {
+            info!("Connected to the daemon at {}", SOCKET_PATH);
+            let (backlight_sender, backlight) = watch::channel(None);
+            let outlets = Outlets {
+                states: state_sender,
+                backlight: backlight_sender,
+                battery: battery_sender,
+                layouts: layout_sender.clone(),
+                config: config_sender,
+            };
+            watchers.push(tokio::task::spawn_local(async move {
+                follow_daemon(stream, outlets).await;
+            }));
+            (backlight, BacklightControl::Daemon)
+        }
+        Err(e) => {
+            info!("No daemon at {} ({}), watching the keyboard ourselves", SOCKET_PATH, e);
+            watchers.push(tokio::task::spawn_local(usb::watch_keyboard(config.clone(), state_sender)));
+            watchers.push(tokio::task::spawn_local(usb::follow_devices(config.clone(), states.clone())));
+            watchers.push(tokio::task::spawn_local(usb::monitor_special_keys(config.clone())));
+            watchers.push(tokio::task::spawn_local(bluez::manage_keyboard(config.clone(), states.clone(), battery_sender)));
+            (usb::backlight::level(), BacklightControl::Direct)
+        }
+    };
+
+    watchers.push(tokio::task::spawn_local(bluez::notify_low_battery(config.clone(), battery)));
+
+    let duo = Duo {
+        config,
+        states,
+        backlight,
+        layout: monitor_handling::current_layout(),
+        layouts: layout_sender,
+        backlight_control,
+    };
+    watchers.push(tokio::task::spawn_local(dbus_service::run(duo)));
+
+    info!("Monitoring started...");
+    watchers.for_each(|_| async {}).await;
+}
+
+/// Connect to the daemon listening at `path` and register as the agent of our session.
+pub async fn connect(path: &Path) -> io::Result<UnixStream> {
+    let mut stream = UnixStream::connect(path).await?;
+    stream.write_all(control::encode(&Request::Agent).as_bytes()).await?;
+    Ok(stream)
+}
+
+/// Pass on what the daemon sends on `stream`, reconnecting whenever it goes away.
+async fn follow_daemon(stream: UnixStream, outlets: Outlets) {
+    let mut stream = Some(stream);
+    loop {
+        if let Some(stream) = stream.take() {
+            match receive(stream, &outlets).await {
+                Ok(()) => warn!("The daemon hung up, reconnecting"),
+                Err(e) => warn!("Lost the connection to the daemon ({}), reconnecting", e),
+            }
+        }
+        tokio::time::sleep(RECONNECT_INTERVAL).await;
+        stream = connect(Path::new(SOCKET_PATH)).await.ok();
+    }
+}
+
+/// Pass every message the daemon sends on `stream` to `outlets` until it hangs up.
+pub async fn receive(stream: UnixStream, outlets: &Outlets) -> io::Result<()> {
+    let mut lines = BufReader::new(stream).lines();
+    while let Some(line) = lines.next_line().await? {
+        match control::decode(&line) {
+            Ok(Message::Keyboard { state }) => {
+                outlets.states.send_if_modified(|current| current.replace(state) != Some(state));
+            }
+            Ok(Message::Backlight { level }) => {
+                outlets.backlight.send_replace(Some(level));
+            }
+            Ok(Message::Battery { level }) => {
+                outlets.battery.send_replace(level);
+            }
+            Ok(Message::ApplyLayout { name }) => {
+                let _ = outlets.layouts.send(name);
+            }
+            Ok(Message::ReloadConfig) => {
+                info!("Reloading config");
+                outlets.config.send_replace(load_config());
+            }
+            Ok(message) => warn!("Ignoring unexpected message from the daemon: {:?}", message),
+            Err(e) => warn!("Ignoring message from the daemon: {}", e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,466 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use log::{debug, error, info, warn};
+use tokio::sync::watch;
+use zbus::fdo::ObjectManagerProxy;
+use zbus::names::OwnedInterfaceName;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue, Value};
+use zbus::{proxy, Connection};
+use crate::config::{Config, DeviceConfig};
+use crate::usb::DeviceState;
+
+const SERVICE: &str = "org.bluez";
+const DEVICE_INTERFACE: &str = "org.bluez.Device1";
+const BATTERY_INTERFACE: &str = "org.bluez.Battery1";
+/// How often the keyboard's battery level is read.
+const BATTERY_INTERVAL: Duration = Duration::from_secs(60);
+/// How often connecting or disconnecting the keyboard is tried before giving up.
+const CONNECT_ATTEMPTS: u32 = 4;
+/// Wait before the first retry; doubled for every further one.
+const CONNECT_BACKOFF: Duration = Duration::from_secs(2);
+
+#[proxy(interface = "org.bluez.Device1", default_service = "org.bluez")]
+trait Device {
+    fn connect(&self) -> zbus::Result<()>;
+
+    fn disconnect(&self) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn address(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn connected(&self) -> zbus::Result<bool>;
+}
+
+/// Vendor and product from a modalias like `usb:v0B05p1BF2d0100` or `bluetooth:v0B05p1BF2d0100`.
+fn modalias_ids(modalias: &str) -> Option<(u32, u32)> {
+    let (_, ids) = modalias.split_once(':')?;
+    let (vendor, rest) = ids.strip_prefix('v')?.split_at_checked(4)?;
+    let product = rest.strip_prefix('p')?.get(..4)?;
+    Some((u32::from_str_radix(vendor, 16).ok()?, u32::from_str_radix(product, 16).ok()?))
+}
+
+/// Whether `modalias` names the keyboard, over USB or Bluetooth.
+fn is_keyboard(modalias: &str, device: &DeviceConfig) -> bool {
+    let id = |id: &str| u32::from_str_radix(id.trim(), 16).ok();
+    let Some((vendor, product)) = modalias_ids(modalias) else { return false };
+    id(&device.vendor_id) == Some(vendor)
+        && (id(&device.product_id) == Some(product)
+            || device.bluetooth_product_id.as_deref().and_then(id) == Some(product))
+}
+
+type Interfaces = HashMap<OwnedInterfaceName, HashMap<String, OwnedValue>>;
+
+/// The BlueZ object of the paired keyboard with its interfaces: the device with `address` if set,
+/// else the one whose modalias carries the keyboard's IDs.
+async fn keyboard_object(connection: &Connection, device: &DeviceConfig, address: Option<&str>) -> zbus::Result<Option<(OwnedObjectPath, Interfaces)>> {
+    let manager = ObjectManagerProxy::builder(connection).destination(SERVICE)?.path("/")?.build().await?;
+    for (path, interfaces) in manager.get_managed_objects().await? {
+        let Some(properties) = interface(&interfaces, DEVICE_INTERFACE) else {
+            continue;
+        };
+        let string = |name: &str| properties.get(name).and_then(|v| v.downcast_ref::<&str>().ok()).unwrap_or_default().to_string();
+        let paired = properties.get("Paired").and_then(|v| v.downcast_ref::<bool>().ok()).unwrap_or(false);
+
+        let matches = match address {
+            Some(address) => string("Address").eq_ignore_ascii_case(address),
+            None => is_keyboard(&string("Modalias"), device),
+        };
+        if matches && paired {
+            return Ok(Some((path, interfaces)));
+        }
+    }
+    Ok(None)
+}
+
+fn interface<'a>(interfaces: &'a Interfaces, name: &str) -> Option<&'a HashMap<String, OwnedValue>> {
+    interfaces.iter().find(|(interface, _)| interface.as_str() == name).map(|(_, properties)| properties)
+}
+
+/// The BlueZ object of the paired keyboard, see [`keyboard_object`].
+pub async fn find_keyboard(connection: &Connection, device: &DeviceConfig, address: Option<&str>) -> zbus::Result<Option<OwnedObjectPath>> {
+    Ok(keyboard_object(connection, device, address).await?.map(|(path, _)| path))
+}
+
+/// The keyboard's battery level in percent while it is connected over Bluetooth.
+pub async fn read_battery(connection: &Connection, config: &Config) -> zbus::Result<Option<u8>> {
+    let Some((_, interfaces)) = keyboard_object(connection, &config.device, config.bluetooth.address.as_deref()).await? else {
+        return Ok(None);
+    };
+    let property = |interface_name: &str, name: &str| interface(&interfaces, interface_name).and_then(|properties| properties.get(name));
+    let connected = property(DEVICE_INTERFACE, "Connected").and_then(|v| v.downcast_ref::<bool>().ok()).unwrap_or(false);
+    if !connected {
+        return Ok(None);
+    }
+    Ok(property(BATTERY_INTERFACE, "Percentage").and_then(|v| v.downcast_ref::<u8>().ok()))
+}
+
+/// The lowest of `thresholds` that the battery level fell to with `now`, coming from `before`;
+/// a first reading counts as coming from a full battery.
+///
+/// `before` is the last level known, also from before the keyboard was disconnected, so that a
+/// threshold is only warned about again once the level rose back above it.
+pub fn crossed_threshold(thresholds: &[u8], before: Option<u8>, now: u8) -> Option<u8> {
+    thresholds.iter()
+        .copied()
+        .filter(|&threshold| now <= threshold && before.is_none_or(|before| before > threshold))
+        .min()
+}
+
+/// Publish the keyboard's battery level on `levels`, read every [`BATTERY_INTERVAL`] while it is
+/// connected over Bluetooth, and warn whenever it falls to one of `battery_warnings`.
+pub async fn watch_battery(connection: &Connection, config: watch::Receiver<Config>, levels: &watch::Sender<Option<u8>>) {
+    let mut before = None;
+    loop {
+        let config = config.borrow().clone();
+        let level = read_battery(connection, &config).await.unwrap_or_else(|e| {
+            debug!("Failed to read the keyboard's battery level: {}", e);
+            None
+        });
+        levels.send_if_modified(|current| std::mem::replace(current, level) != level);
+        if let Some(level) = level
+            && crossed_threshold(&config.bluetooth.battery_warnings, before, level).is_some() {
+            warn!("The keyboard's battery is down to {}%", level);
+        }
+        before = level.or(before);
+        tokio::time::sleep(BATTERY_INTERVAL).await;
+    }
+}
+
+/// Connect the paired keyboard over Bluetooth, or disconnect it, unless it already is.
+async fn set_connected(connection: &Connection, config: &Config, connect: bool) -> zbus::Result<()> {
+    let Some(path) = find_keyboard(connection, &config.device, config.bluetooth.address.as_deref()).await? else {
+        debug!("The keyboard is not paired over Bluetooth");
+        return Ok(());
+    };
+    let device = DeviceProxy::builder(connection).path(path)?.build().await?;
+    if device.connected().await? == connect {
+        return Ok(());
+    }
+
+    let address = device.address().await?;
+    if connect {
+        info!("Connecting the keyboard over Bluetooth ({})", address);
+        device.connect().await
+    } else {
+        info!("Disconnecting the keyboard from Bluetooth ({})", address);
+        device.disconnect().await
+    }
+}
+
+/// [`set_connected`], retried with exponential backoff starting at `backoff`, as the keyboard takes
+/// a moment to start advertising once it is taken off. Failures are only logged.
+async fn set_connected_with_retries(connection: &Connection, config: &Config, connect: bool, backoff: Duration) {
+    let what = if connect { "connect" } else { "disconnect" };
+    let mut delay = backoff;
+    for attempt in 1..=CONNECT_ATTEMPTS {
+        match set_connected(connection, config, connect).await {
+            Ok(()) => return,
+            Err(e) if attempt < CONNECT_ATTEMPTS => {
+                warn!("Failed to {} the keyboard over Bluetooth (attempt {}/{}): {}, retrying in {:?}",
+                      what, attempt, CONNECT_ATTEMPTS, e, delay);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => warn!("Failed to {} the keyboard over Bluetooth: {}", what, e),
+        }
+    }
+}
+
+/// Connect the keyboard over Bluetooth whenever `states` says it was removed, and disconnect it
+/// once it is attached again, each as far as `[bluetooth]` asks for it.
+///
+/// Failures, e.g. while the keyboard is switched off, are retried a few times, until the keyboard
+/// state changes again, and then only logged.
+pub async fn follow_keyboard(connection: &Connection, config: watch::Receiver<Config>, mut states: watch::Receiver<Option<DeviceState>>) {
+    loop {
+        let state = *states.borrow_and_update();
+        let config = config.borrow().clone();
+        let connect = match state {
+            Some(DeviceState::Removed) if config.bluetooth.connect_on_detach => Some(true),
+            Some(DeviceState::Added) if config.bluetooth.disconnect_on_attach => Some(false),
+            _ => None,
+        };
+        if let Some(connect) = connect {
+            tokio::select! {
+                _ = set_connected_with_retries(connection, &config, connect, CONNECT_BACKOFF) => {}
+                // Attached or removed again meanwhile: stop trying and follow the new state.
+                changed = states.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+            }
+        }
+
+        if states.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Follow the keyboard through BlueZ on the system bus, see [`follow_keyboard`], and publish its
+/// battery level on `battery`, see [`watch_battery`].
+pub async fn manage_keyboard(
+    config: watch::Receiver<Config>,
+    states: watch::Receiver<Option<DeviceState>>,
+    battery: watch::Sender<Option<u8>>,
+) {
+    match Connection::system().await {
+        Ok(connection) => {
+            tokio::join!(
+                follow_keyboard(&connection, config.clone(), states),
+                watch_battery(&connection, config, &battery),
+            );
+        }
+        Err(e) => error!("Failed to connect to the system bus, not managing Bluetooth: {}", e),
+    }
+}
+
+#[proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+}
+
+/// Notify the desktop whenever the keyboard's battery level on `levels` falls to one of
+/// `battery_warnings`.
+pub async fn notify_low_battery(config: watch::Receiver<Config>, mut levels: watch::Receiver<Option<u8>>) {
+    let connection = match Connection::session().await {
+        Ok(connection) => connection,
+        Err(e) => {
+            error!("Failed to connect to the session bus, not notifying about the keyboard's battery: {}", e);
+            return;
+        }
+    };
+
+    let mut before = None;
+    loop {
+        let level = *levels.borrow_and_update();
+        let thresholds = config.borrow().bluetooth.battery_warnings.clone();
+        if let Some(level) = level
+            && crossed_threshold(&thresholds, before, level).is_some() {
+            let notified = match NotificationsProxy::new(&connection).await {
+                Ok(notifications) => notifications.notify(
+                    "Zenbook Duo",
+                    0,
+                    "battery-low",
+                    "Keyboard battery low",
+                    &format!("The keyboard's battery is down to {}%.", level),
+                    &[],
+                    HashMap::new(),
+                    -1,
+                ).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = notified {
+                warn!("Failed to notify about the keyboard's battery: {}", e);
+            }
+        }
+        // Disconnecting forgets nothing, see `crossed_threshold`.
+        before = level.or(before);
+
+        if levels.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::test_bus::test_bus_or_skip;
+
+    struct FakeDevice {
+        address: &'static str,
+        modalias: &'static str,
+        paired: bool,
+        connected: bool,
+        /// How many times connecting fails before it works.
+        failures: u32,
+    }
+
+    #[zbus::interface(name = "org.bluez.Device1")]
+    impl FakeDevice {
+        fn connect(&mut self) -> zbus::fdo::Result<()> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(zbus::fdo::Error::Failed("br-connection-page-timeout".to_string()));
+            }
+            self.connected = true;
+            Ok(())
+        }
+
+        fn disconnect(&mut self) {
+            self.connected = false;
+        }
+
+        #[zbus(property)]
+        fn address(&self) -> String {
+            self.address.to_string()
+        }
+
+        #[zbus(property)]
+        fn modalias(&self) -> String {
+            self.modalias.to_string()
+        }
+
+        #[zbus(property)]
+        fn paired(&self) -> bool {
+            self.paired
+        }
+
+        #[zbus(property)]
+        fn connected(&self) -> bool {
+            self.connected
+        }
+    }
+
+    struct FakeBattery {
+        percentage: u8,
+    }
+
+    #[zbus::interface(name = "org.bluez.Battery1")]
+    impl FakeBattery {
+        #[zbus(property)]
+        fn percentage(&self) -> u8 {
+            self.percentage
+        }
+    }
+
+    const KEYBOARD: &str = "/org/bluez/hci0/dev_AA_BB_CC_DD_EE_FF";
+    const MOUSE: &str = "/org/bluez/hci0/dev_11_22_33_44_55_66";
+
+    async fn connected(connection: &Connection, path: &str) -> bool {
+        let device = DeviceProxy::builder(connection)
+            .path(path).unwrap()
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build().await.unwrap();
+        device.connected().await.unwrap()
+    }
+
+    async fn wait_until_connected(connection: &Connection, path: &str, expected: bool) {
+        for _ in 0..50 {
+            if connected(connection, path).await == expected {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("{} did not become {}", path, if expected { "connected" } else { "disconnected" });
+    }
+
+    #[test]
+    fn recognizes_the_keyboard_by_modalias() {
+        let device = Config::default().device;
+        assert_eq!(modalias_ids("usb:v0B05p1BF2d0100"), Some((0xb05, 0x1bf2)));
+        assert!(is_keyboard("bluetooth:v0B05p1BF2d0100", &device));
+        assert!(!is_keyboard("usb:v046Dp4082d0100", &device));
+        assert!(!is_keyboard("usb:v0B05", &device));
+
+        let device = DeviceConfig { bluetooth_product_id: Some("1bf3".into()), ..device };
+        assert!(is_keyboard("usb:v0B05p1BF3d0100", &device));
+    }
+
+    #[test]
+    fn warns_once_per_threshold() {
+        let thresholds = [20, 10];
+        assert_eq!(crossed_threshold(&thresholds, None, 80), None);
+        assert_eq!(crossed_threshold(&thresholds, Some(21), 20), Some(20));
+        assert_eq!(crossed_threshold(&thresholds, Some(20), 15), None);
+        assert_eq!(crossed_threshold(&thresholds, Some(15), 9), Some(10));
+        assert_eq!(crossed_threshold(&thresholds, Some(9), 30), None);
+        // Coming from a full battery, only the lowest threshold reached counts.
+        assert_eq!(crossed_threshold(&thresholds, None, 5), Some(10));
+        assert_eq!(crossed_threshold(&[], None, 5), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn connects_while_detached() {
+        let bus = test_bus_or_skip!();
+        let _service = zbus::blocking::connection::Builder::address(bus.address()).unwrap()
+            .name("org.bluez").unwrap()
+            .serve_at("/", zbus::fdo::ObjectManager).unwrap()
+            .serve_at(MOUSE, FakeDevice { address: "11:22:33:44:55:66", modalias: "usb:v046Dp4082d0100", paired: true, connected: false, failures: 0 }).unwrap()
+            .serve_at(KEYBOARD, FakeDevice { address: "AA:BB:CC:DD:EE:FF", modalias: "usb:v0B05p1BF2d0100", paired: true, connected: false, failures: 0 }).unwrap()
+            .build()
+            .unwrap();
+
+        let mut config = Config::default();
+        config.bluetooth.disconnect_on_attach = true;
+        let (_config, config) = watch::channel(config);
+        let (states, state_receiver) = watch::channel(Some(DeviceState::Added));
+        let connection: Connection = bus.connect().into();
+        let follower = tokio::spawn({
+            let connection = connection.clone();
+            async move { follow_keyboard(&connection, config, state_receiver).await }
+        });
+
+        states.send_replace(Some(DeviceState::Removed));
+        wait_until_connected(&connection, KEYBOARD, true).await;
+        states.send_replace(Some(DeviceState::Added));
+        wait_until_connected(&connection, KEYBOARD, false).await;
+        assert!(!connected(&connection, MOUSE).await);
+
+        let found = find_keyboard(&connection, &Config::default().device, Some("11:22:33:44:55:66")).await.unwrap();
+        assert_eq!(found.as_deref().map(|path| path.as_str()), Some(MOUSE));
+
+        follower.abort();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn retries_connecting() {
+        let bus = test_bus_or_skip!();
+        let _service = zbus::blocking::connection::Builder::address(bus.address()).unwrap()
+            .name("org.bluez").unwrap()
+            .serve_at("/", zbus::fdo::ObjectManager).unwrap()
+            .serve_at(KEYBOARD, FakeDevice { address: "AA:BB:CC:DD:EE:FF", modalias: "usb:v0B05p1BF2d0100", paired: true, connected: false, failures: 2 }).unwrap()
+            .serve_at(MOUSE, FakeDevice { address: "11:22:33:44:55:66", modalias: "usb:v0B05p1BF2d0100", paired: true, connected: false, failures: CONNECT_ATTEMPTS }).unwrap()
+            .build()
+            .unwrap();
+
+        let mut config = Config::default();
+        let connection: Connection = bus.connect().into();
+        config.bluetooth.address = Some("AA:BB:CC:DD:EE:FF".to_string());
+        set_connected_with_retries(&connection, &config, true, Duration::from_millis(1)).await;
+        assert!(connected(&connection, KEYBOARD).await);
+
+        // Gives up after the last attempt.
+        config.bluetooth.address = Some("11:22:33:44:55:66".to_string());
+        set_connected_with_retries(&connection, &config, true, Duration::from_millis(1)).await;
+        assert!(!connected(&connection, MOUSE).await);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reads_the_battery_while_connected() {
+        let bus = test_bus_or_skip!();
+        let _service = zbus::blocking::connection::Builder::address(bus.address()).unwrap()
+            .name("org.bluez").unwrap()
+            .serve_at("/", zbus::fdo::ObjectManager).unwrap()
+            .serve_at(KEYBOARD, FakeDevice { address: "AA:BB:CC:DD:EE:FF", modalias: "usb:v0B05p1BF2d0100", paired: true, connected: false, failures: 0 }).unwrap()
+            .serve_at(KEYBOARD, FakeBattery { percentage: 42 }).unwrap()
+            .build()
+            .unwrap();
+
+        let config = Config::default();
+        let connection: Connection = bus.connect().into();
+        assert_eq!(read_battery(&connection, &config).await.unwrap(), None);
+        set_connected(&connection, &config, true).await.unwrap();
+        assert_eq!(read_battery(&connection, &config).await.unwrap(), Some(42));
+    }
+}
@@ -0,0 +1,363 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use lazy_static::lazy_static;
+use tokio::sync::watch;
+use zbus::blocking::Connection;
+use log::{debug, error, info, warn};
+use crate::config::{BrightnessSyncConfig, Config};
+use crate::drm::{self, Panels};
+use crate::logind::SessionProxyBlocking;
+
+pub const BACKLIGHT_SYSFS: &str = "/sys/class/backlight";
+
+/// `FB_BLANK_POWERDOWN` from `linux/fb.h`, as written to `bl_power`.
+const BL_POWER_OFF: u32 = 4;
+
+lazy_static! {
+    /// The bottom panel's backlight while [`power_off_bottom`] keeps it off, with what to restore.
+    static ref POWERED_OFF: Mutex<Option<(Backlight, SavedPower)>> = Mutex::new(None);
+}
+
+/// How often the top panel's backlight is read; sysfs sends no notification when it changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A backlight device under `/sys/class/backlight`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backlight {
+    pub name: String,
+    pub path: PathBuf,
+    pub max: u32,
+}
+
+impl Backlight {
+    fn open(root: &Path, name: &str) -> Option<Backlight> {
+        let path = root.join(name);
+        let max = read_number(&path.join("max_brightness")).filter(|max| *max > 0)?;
+        Some(Backlight { name: name.to_string(), path, max })
+    }
+
+    pub fn brightness(&self) -> Option<u32> {
+        read_number(&self.path.join("brightness"))
+    }
+
+    /// Set the brightness through sysfs if we may write it, else through logind, which lets the
+    /// user of the active session change backlights.
+    fn set_brightness(&self, value: u32) -> Result<(), String> {
+        match fs::write(self.path.join("brightness"), value.to_string()) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                debug!("No permission to write {:?}, asking logind", self.path);
+                Connection::system()
+                    .and_then(|connection| SessionProxyBlocking::new(&connection))
+                    .and_then(|session| session.set_brightness("backlight", &self.name, value))
+                    .map_err(|e| format!("logind refused: {}", e))
+            }
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+/// The state of a backlight before [`power_off`] switched it off.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SavedPower {
+    pub brightness: u32,
+    /// `None` if the driver has no `bl_power`.
+    pub bl_power: Option<u32>,
+}
+
+/// Switch `backlight` off through `bl_power` and brightness 0, returning what to restore.
+pub fn power_off(backlight: &Backlight) -> Result<SavedPower, String> {
+    let saved = SavedPower {
+        brightness: backlight.brightness().ok_or("brightness unreadable")?,
+        bl_power: read_number(&backlight.path.join("bl_power")),
+    };
+    if saved.bl_power.is_some()
+        && let Err(e) = fs::write(backlight.path.join("bl_power"), BL_POWER_OFF.to_string()) {
+        // Only root may write bl_power, which the session's agent is not; brightness 0 has to do.
+        warn!("Failed to power down {} through bl_power ({}), only setting its brightness to 0", backlight.name, e);
+    }
+    backlight.set_brightness(0)?;
+    Ok(saved)
+}
+
+/// Undo [`power_off`].
+pub fn power_on(backlight: &Backlight, saved: &SavedPower) -> Result<(), String> {
+    if let Some(bl_power) = saved.bl_power
+        && let Err(e) = fs::write(backlight.path.join("bl_power"), bl_power.to_string()) {
+        warn!("Failed to power up {} through bl_power: {}", backlight.name, e);
+    }
+    backlight.set_brightness(saved.brightness)
+}
+
+/// Switch the bottom panel's backlight off until [`power_on_bottom`], for compositors that keep it
+/// lit after the panel was disabled. Errors are logged.
+pub fn power_off_bottom(panels: &Panels, config: &BrightnessSyncConfig) {
+    let mut powered_off = POWERED_OFF.lock().unwrap();
+    if powered_off.is_some() {
+        return;
+    }
+    let Some(backlight) = bottom_backlight(Path::new(BACKLIGHT_SYSFS), panels, config) else {
+        warn!("Could not find the bottom panel's backlight to switch it off");
+        return;
+    };
+    match power_off(&backlight) {
+        Ok(saved) => {
+            info!("Switched off backlight {}", backlight.name);
+            *powered_off = Some((backlight, saved));
+        }
+        Err(e) => error!("Failed to switch off backlight {}: {}", backlight.name, e),
+    }
+}
+
+/// Restore the bottom panel's backlight switched off by [`power_off_bottom`], if it was.
+pub fn power_on_bottom() {
+    let Some((backlight, saved)) = POWERED_OFF.lock().unwrap().take() else { return };
+    match power_on(&backlight, &saved) {
+        Ok(()) => info!("Restored backlight {} to {}", backlight.name, saved.brightness),
+        Err(e) => error!("Failed to restore backlight {}: {}", backlight.name, e),
+    }
+}
+
+fn is_bottom_powered_off() -> bool {
+    POWERED_OFF.lock().unwrap().is_some()
+}
+
+fn read_number(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Find the backlights of the top and bottom panel below `root`.
+///
+/// Names from `[brightness_sync]` win. Otherwise a backlight whose name contains a panel's
+/// connector (`card1-eDP-2-backlight`) belongs to that panel, and of the remaining ones (e.g.
+/// `intel_backlight`, which the desktop controls) the first goes to the top panel.
+pub fn discover(root: &Path, panels: &Panels, config: &BrightnessSyncConfig) -> Option<(Backlight, Backlight)> {
+    let names = backlight_names(root);
+    let bottom = bottom_name(&names, panels, config);
+    let top = config.top.clone()
+        .or_else(|| mentioning(&names, &panels.top))
+        .or_else(|| names.iter().find(|n| Some(*n) != bottom.as_ref()).cloned());
+
+    let (top, bottom) = (Backlight::open(root, &top?)?, Backlight::open(root, &bottom?)?);
+    (top != bottom).then_some((top, bottom))
+}
+
+/// The bottom panel's backlight below `root`, found as by [`discover`].
+pub fn bottom_backlight(root: &Path, panels: &Panels, config: &BrightnessSyncConfig) -> Option<Backlight> {
+    Backlight::open(root, &bottom_name(&backlight_names(root), panels, config)?)
+}
+
+fn backlight_names(root: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(root)
+        .map(|entries| entries.flatten().map(|entry| entry.file_name().to_string_lossy().into_owned()).collect())
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+fn bottom_name(names: &[String], panels: &Panels, config: &BrightnessSyncConfig) -> Option<String> {
+    config.bottom.clone().or_else(|| mentioning(names, &panels.bottom))
+}
+
+/// The backlight whose name contains `connector`, like `card1-eDP-2-backlight`.
+fn mentioning(names: &[String], connector: &str) -> Option<String> {
+    names.iter().find(|n| n.contains(&format!("-{}-", connector))).cloned()
+}
+
+/// Brightness for the bottom panel mirroring `top` out of `top_max`, on a scale up to `bottom_max`.
+///
+/// The top panel's relative brightness is raised to `gamma`, shifted by `offset` and clamped.
+pub fn mirrored(top: u32, top_max: u32, bottom_max: u32, config: &BrightnessSyncConfig) -> u32 {
+    let fraction = (top as f64 / top_max as f64).powf(config.gamma) + config.offset;
+    (fraction.clamp(0.0, 1.0) * bottom_max as f64).round() as u32
+}
+
+/// Mirrors the top panel's backlight to the bottom panel while the bottom panel is on.
+pub struct BrightnessSync {
+    top: Backlight,
+    bottom: Backlight,
+    drm_root: PathBuf,
+    bottom_connector: String,
+    config: BrightnessSyncConfig,
+    /// The top panel's brightness that was last mirrored.
+    last: Option<u32>,
+}
+
+impl BrightnessSync {
+    pub fn new(backlight_root: &Path, drm_root: &Path, panels: &Panels, config: &BrightnessSyncConfig) -> Option<Self> {
+        let (top, bottom) = discover(backlight_root, panels, config)?;
+        info!("Mirroring brightness of {} to {}", top.name, bottom.name);
+        Some(BrightnessSync {
+            top,
+            bottom,
+            drm_root: drm_root.to_path_buf(),
+            bottom_connector: panels.bottom.clone(),
+            config: config.clone(),
+            last: None,
+        })
+    }
+
+    /// Mirror the top panel's brightness if it changed or the bottom panel just came on.
+    pub fn step(&mut self) {
+        let bottom_on = drm::connectors(&self.drm_root)
+            .iter()
+            .any(|c| c.name == self.bottom_connector && c.enabled);
+        // Mirroring would light up a backlight that was switched off on purpose.
+        if !bottom_on || is_bottom_powered_off() {
+            self.last = None;
+            return;
+        }
+
+        let Some(top) = self.top.brightness() else { return };
+        if self.last == Some(top) {
+            return;
+        }
+        self.last = Some(top);
+
+        let target = mirrored(top, self.top.max, self.bottom.max, &self.config);
+        if self.bottom.brightness() == Some(target) {
+            return;
+        }
+        debug!("Setting {} to {} ({} at {}/{})", self.bottom.name, target, self.top.name, top, self.top.max);
+        if let Err(e) = self.bottom.set_brightness(target) {
+            error!("Failed to set brightness of {}: {}", self.bottom.name, e);
+        }
+    }
+}
+
+/// Keep the bottom panel's backlight in line with the top panel's, see [`BrightnessSync`], starting
+/// over whenever `config` changes.
+pub async fn sync_brightness(mut config: watch::Receiver<Config>) {
+    loop {
+        let current = config.borrow_and_update().clone();
+        let sync = if current.brightness_sync.enabled {
+            let panels = drm::discover_panels(Path::new(drm::DRM_SYSFS), &current.panels);
+            let sync = BrightnessSync::new(
+                Path::new(BACKLIGHT_SYSFS), Path::new(drm::DRM_SYSFS), &panels, &current.brightness_sync,
+            );
+            if sync.is_none() {
+                warn!("Could not find the backlights of both panels, not syncing brightness");
+            }
+            sync
+        } else {
+            None
+        };
+
+        tokio::select! {
+            changed = config.changed() => if changed.is_err() { return },
+            _ = run_sync(sync) => {}
+        }
+    }
+}
+
+async fn run_sync(sync: Option<BrightnessSync>) {
+    let Some(mut sync) = sync else { return std::future::pending().await };
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        sync.step();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use crate::drm::tests::add_connector;
+
+    fn add_backlight(root: &Path, name: &str, brightness: u32, max: u32) {
+        let path = root.join(name);
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("brightness"), format!("{}\n", brightness)).unwrap();
+        fs::write(path.join("max_brightness"), format!("{}\n", max)).unwrap();
+    }
+
+    fn fake_backlights() -> TempDir {
+        let root = TempDir::new().unwrap();
+        add_backlight(root.path(), "intel_backlight", 200, 400);
+        add_backlight(root.path(), "card1-eDP-2-backlight", 10, 100);
+        root
+    }
+
+    #[test]
+    fn discovers_backlights_by_connector() {
+        let root = fake_backlights();
+        let (top, bottom) = discover(root.path(), &Panels::default(), &BrightnessSyncConfig::default()).unwrap();
+        assert_eq!((top.name.as_str(), top.max), ("intel_backlight", 400));
+        assert_eq!((bottom.name.as_str(), bottom.max), ("card1-eDP-2-backlight", 100));
+
+        let swapped = BrightnessSyncConfig {
+            top: Some("card1-eDP-2-backlight".to_string()),
+            bottom: Some("intel_backlight".to_string()),
+            ..BrightnessSyncConfig::default()
+        };
+        let (top, _) = discover(root.path(), &Panels::default(), &swapped).unwrap();
+        assert_eq!(top.name, "card1-eDP-2-backlight");
+
+        let single = TempDir::new().unwrap();
+        add_backlight(single.path(), "intel_backlight", 1, 10);
+        assert!(discover(single.path(), &Panels::default(), &BrightnessSyncConfig::default()).is_none());
+    }
+
+    #[test]
+    fn powers_off_and_restores() {
+        let root = fake_backlights();
+        let backlight = bottom_backlight(root.path(), &Panels::default(), &BrightnessSyncConfig::default()).unwrap();
+        assert_eq!(backlight.name, "card1-eDP-2-backlight");
+
+        let saved = power_off(&backlight).unwrap();
+        assert_eq!(saved, SavedPower { brightness: 10, bl_power: None });
+        assert_eq!(backlight.brightness(), Some(0));
+        power_on(&backlight, &saved).unwrap();
+        assert_eq!(backlight.brightness(), Some(10));
+
+        fs::write(backlight.path.join("bl_power"), "0\n").unwrap();
+        let saved = power_off(&backlight).unwrap();
+        assert_eq!(saved.bl_power, Some(0));
+        assert_eq!(fs::read_to_string(backlight.path.join("bl_power")).unwrap(), "4");
+        power_on(&backlight, &saved).unwrap();
+        assert_eq!(fs::read_to_string(backlight.path.join("bl_power")).unwrap(), "0");
+    }
+
+    #[test]
+    fn applies_offset_and_curve() {
+        let linear = BrightnessSyncConfig::default();
+        assert_eq!(mirrored(200, 400, 100, &linear), 50);
+        assert_eq!(mirrored(0, 400, 100, &linear), 0);
+
+        let brighter = BrightnessSyncConfig { offset: 0.1, ..BrightnessSyncConfig::default() };
+        assert_eq!(mirrored(200, 400, 100, &brighter), 60);
+        assert_eq!(mirrored(400, 400, 100, &brighter), 100);
+
+        let curved = BrightnessSyncConfig { gamma: 2.0, ..BrightnessSyncConfig::default() };
+        assert_eq!(mirrored(200, 400, 100, &curved), 25);
+    }
+
+    #[test]
+    fn mirrors_only_while_bottom_panel_is_on() {
+        let backlights = fake_backlights();
+        let drm_root = TempDir::new().unwrap();
+        add_connector(drm_root.path(), "card1-eDP-1", true);
+        add_connector(drm_root.path(), "card1-eDP-2", true);
+        let bottom = backlights.path().join("card1-eDP-2-backlight").join("brightness");
+        let top = backlights.path().join("intel_backlight").join("brightness");
+
+        let mut sync = BrightnessSync::new(
+            backlights.path(), drm_root.path(), &Panels::default(), &BrightnessSyncConfig::default(),
+        ).unwrap();
+        sync.step();
+        assert_eq!(fs::read_to_string(&bottom).unwrap(), "50");
+
+        fs::write(drm_root.path().join("card1-eDP-2").join("enabled"), "disabled\n").unwrap();
+        fs::write(&top, "100\n").unwrap();
+        sync.step();
+        assert_eq!(fs::read_to_string(&bottom).unwrap(), "50");
+
+        fs::write(drm_root.path().join("card1-eDP-2").join("enabled"), "enabled\n").unwrap();
+        sync.step();
+        assert_eq!(fs::read_to_string(&bottom).unwrap(), "25");
+    }
+}
@@ -0,0 +1,116 @@
+use std::path::Path;
+use crate::config::load_config;
+use crate::control::{self, Message, Request, Status};
+use crate::daemon::SOCKET_PATH;
+use crate::monitor_handling::{apply_layout_named, get_display_manager};
+use crate::touch;
+use crate::usb::{check_initial_state, DeviceState};
+
+/// Send `request` to the running daemon; `None` if there is none, exiting on any other failure.
+pub fn ask_daemon(request: &Request) -> Option<Message> {
+    match control::call(Path::new(SOCKET_PATH), request) {
+        Ok(Message::Error { message }) => {
+            eprintln!("Error: {}", message);
+            std::process::exit(1);
+        }
+        Ok(message) => Some(message),
+        Err(e) if control::is_not_running(&e) => None,
+        Err(e) => {
+            eprintln!("Failed to talk to the daemon at {}: {}", SOCKET_PATH, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `--status`: what the daemon knows, if it runs, then the state of the panels' digitizers.
+pub fn status() {
+    let config = load_config();
+    let status = match ask_daemon(&Request::Status) {
+        Some(Message::Status(status)) => {
+            print_daemon_status(&status);
+            Some(status)
+        }
+        Some(message) => {
+            eprintln!("Unexpected answer from the daemon: {:?}", message);
+            None
+        }
+        None => {
+            println!("Daemon: not running");
+            // Without the daemon, look at the keyboard ourselves.
+            print_keyboard(check_initial_state(&config).0);
+            None
+        }
+    };
+    touch::print_status(&config, status.as_ref().map(|status| status.inhibited.as_slice()));
+}
+
+fn print_daemon_status(status: &Status) {
+    let or_none = |value: Option<String>| value.unwrap_or_else(|| "none".to_string());
+    println!("Daemon: running");
+    print_keyboard(status.keyboard);
+    println!("Backlight level: {}", or_none(status.backlight.map(|level| level.to_string())));
+    println!("Keyboard battery: {}", status.battery.map_or_else(|| "unknown".to_string(), |level| format!("{}%", level)));
+    println!("Active session: {}", or_none(status.active_user.map(|uid| format!("user {}", uid))));
+    let agents: Vec<String> = status.agents.iter().map(|uid| format!("user {}", uid)).collect();
+    println!("Agents: {}", or_none((!agents.is_empty()).then(|| agents.join(", "))));
+}
+
+fn print_keyboard(state: Option<DeviceState>) {
+    let state = match state {
+        Some(DeviceState::Added) => "attached",
+        Some(DeviceState::Removed) => "detached",
+        None => "unknown",
+    };
+    println!("Keyboard: {}", state);
+}
+
+/// `--apply-layout <name>`: through the agent of the active session if the daemon runs, else in
+/// this process, which then has to run in the graphical session.
+pub async fn apply_layout(args: &[String]) {
+    let Some(name) = args.get(2) else {
+        eprintln!("Usage: zenbook-duo --apply-layout <name>");
+        std::process::exit(1);
+    };
+
+    if ask_daemon(&Request::ApplyLayout { name: name.clone() }).is_some() {
+        println!("Layout '{}' sent to the active session", name);
+        return;
+    }
+
+    let config = load_config();
+    let (state, _) = check_initial_state(&config);
+    if !apply_layout_named(name, &state, None, &config, &get_display_manager()).await {
+        eprintln!("Failed to apply layout '{}'", name);
+        std::process::exit(1);
+    }
+}
+
+/// `--reload`: have the daemon and the agents reload their config.
+pub fn reload() {
+    if ask_daemon(&Request::ReloadConfig).is_some() {
+        println!("Config reloaded");
+    } else {
+        eprintln!("The daemon is not running; restart the service instead: systemctl --user restart zenbook-duo");
+        std::process::exit(1);
+    }
+}
+
+/// `--watch`: print the daemon's events, one JSON object per line, until interrupted.
+pub fn watch() {
+    let result = control::call_with(Path::new(SOCKET_PATH), &Request::Subscribe, |message| {
+        print!("{}", control::encode(&message));
+        true
+    });
+    match result {
+        Ok(()) => {}
+        Err(e) if control::is_not_running(&e) => {
+            eprintln!("The daemon is not running");
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Failed to talk to the daemon at {}: {}", SOCKET_PATH, e);
+            std::process::exit(1);
+        }
+    }
+}
+
@@ -1,106 +1,578 @@
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::io::{self, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
 use directories::ProjectDirs;
+use crate::layout::{LayoutDefinition, Position};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Config {
     pub brightness: i8,
-    pub scale: f64,
+    pub scale: ScaleConfig,
     pub verbose: bool,
+    /// Follow the accelerometer while the keyboard is detached.
+    #[serde(default = "default_auto_rotate")]
+    pub auto_rotate: bool,
+    /// How long to wait for the panels to be connected before applying a layout, in milliseconds.
+    #[serde(default = "default_output_timeout_ms")]
+    pub output_timeout_ms: u64,
+    /// When to switch the bottom panel's backlight off while the keyboard is attached.
+    #[serde(default)]
+    pub bottom_backlight_off: BacklightOff,
     pub device: DeviceConfig,
+    #[serde(default)]
+    pub panels: PanelConfig,
+    #[serde(default)]
+    pub layouts: LayoutConfig,
+    #[serde(default)]
+    pub touch: TouchConfig,
+    #[serde(default)]
+    pub external: ExternalConfig,
+    #[serde(default)]
+    pub brightness_sync: BrightnessSyncConfig,
+    #[serde(default)]
+    pub bluetooth: BluetoothConfig,
+    #[serde(default)]
+    pub backlight_idle: BacklightIdleConfig,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct DeviceConfig {
     pub vendor_id: String,
     pub product_id: String,
+    /// The product ID the keyboard reports over Bluetooth, if it differs from `product_id`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bluetooth_product_id: Option<String>,
+}
+
+/// Overrides for which DRM connector drives which panel; unset entries are auto-discovered.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct PanelConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bottom: Option<String>,
+}
+
+fn default_auto_rotate() -> bool {
+    true
+}
+
+fn default_output_timeout_ms() -> u64 {
+    3000
+}
+
+/// When the bottom panel's backlight is switched off directly, for compositors that leave it
+/// powered after the panel was disabled.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum BacklightOff {
+    /// Leave the backlight to the compositor.
+    #[default]
+    Never,
+    /// Only if the attached layout switches the bottom panel off but could not be applied.
+    Fallback,
+    /// Whenever the attached layout switches the bottom panel off.
+    Always,
+}
+
+/// Overrides for which digitizer belongs to which panel, matched against the device name
+/// (e.g. `ELAN9008`); unset entries are assigned in device order.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct TouchConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bottom: Option<String>,
+    /// Grab the bottom panel's digitizer while the keyboard is attached, so the keyboard lying on
+    /// the panel does not cause ghost touches.
+    #[serde(default = "default_inhibit_bottom")]
+    pub inhibit_bottom: bool,
+}
+
+impl Default for TouchConfig {
+    fn default() -> Self {
+        Self { top: None, bottom: None, inhibit_bottom: default_inhibit_bottom() }
+    }
+}
+
+fn default_inhibit_bottom() -> bool {
+    true
+}
+
+/// Mirroring the top panel's backlight to the bottom panel while both are on.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct BrightnessSyncConfig {
+    #[serde(default = "default_sync_enabled")]
+    pub enabled: bool,
+    /// Added to the top panel's relative brightness (0 to 1) before it is applied to the bottom panel.
+    #[serde(default)]
+    pub offset: f64,
+    /// Exponent for the top panel's relative brightness; above 1 dims the bottom panel more at low
+    /// brightness, below 1 less.
+    #[serde(default = "default_gamma")]
+    pub gamma: f64,
+    /// Backlight device of the top panel, e.g. `intel_backlight`; discovered if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top: Option<String>,
+    /// Backlight device of the bottom panel, e.g. `card1-eDP-2-backlight`; discovered if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bottom: Option<String>,
+}
+
+impl Default for BrightnessSyncConfig {
+    fn default() -> Self {
+        Self { enabled: default_sync_enabled(), offset: 0.0, gamma: default_gamma(), top: None, bottom: None }
+    }
+}
+
+fn default_sync_enabled() -> bool {
+    true
+}
+
+fn default_gamma() -> f64 {
+    1.0
+}
+
+/// Connecting the keyboard over Bluetooth while it is detached, through BlueZ.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct BluetoothConfig {
+    /// Connect the paired keyboard when it is removed.
+    #[serde(default = "default_connect_on_detach")]
+    pub connect_on_detach: bool,
+    /// Disconnect it when it is attached again, sparing its battery.
+    #[serde(default)]
+    pub disconnect_on_attach: bool,
+    /// The keyboard's Bluetooth address, e.g. `AA:BB:CC:DD:EE:FF`; found by its IDs if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Battery levels in percent at which to warn, once each time the keyboard's level falls to them.
+    #[serde(default = "default_battery_warnings")]
+    pub battery_warnings: Vec<u8>,
+}
+
+impl Default for BluetoothConfig {
+    fn default() -> Self {
+        Self {
+            connect_on_detach: default_connect_on_detach(),
+            disconnect_on_attach: false,
+            address: None,
+            battery_warnings: default_battery_warnings(),
+        }
+    }
+}
+
+fn default_connect_on_detach() -> bool {
+    true
+}
+
+fn default_battery_warnings() -> Vec<u8> {
+    vec![20, 10]
+}
+
+/// Switching the keyboard backlight off while the keyboard is not used.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct BacklightIdleConfig {
+    /// Seconds without a key event before the backlight goes off; 0 keeps it on.
+    #[serde(default)]
+    pub timeout_secs: u64,
+    /// Whether the timeout applies while the laptop runs on AC.
+    #[serde(default = "default_idle_on_power")]
+    pub on_ac: bool,
+    /// Whether the timeout applies while the laptop runs on battery.
+    #[serde(default = "default_idle_on_power")]
+    pub on_battery: bool,
+}
+
+impl Default for BacklightIdleConfig {
+    fn default() -> Self {
+        Self { timeout_secs: 0, on_ac: default_idle_on_power(), on_battery: default_idle_on_power() }
+    }
+}
+
+fn default_idle_on_power() -> bool {
+    true
+}
+
+/// What happens to external monitors when a layout is applied.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExternalPolicy {
+    /// Leave external monitors where they are.
+    #[default]
+    Keep,
+    /// Place external monitors next to the primary panel, at `[external] position`.
+    Relative,
+    /// Switch the panels off while an external monitor is connected.
+    InternalOff,
+}
+
+/// How external (non-eDP) monitors are handled, see [`ExternalPolicy`].
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ExternalConfig {
+    #[serde(default)]
+    pub policy: ExternalPolicy,
+    /// Where the first external monitor goes relative to the primary panel with the `relative`
+    /// policy; further monitors are lined up to its right.
+    #[serde(default = "default_external_position")]
+    pub position: Position,
+    /// Scale of the external monitors; 1 if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<f64>,
+}
+
+impl Default for ExternalConfig {
+    fn default() -> Self {
+        Self { policy: ExternalPolicy::default(), position: default_external_position(), scale: None }
+    }
+}
+
+fn default_external_position() -> Position {
+    Position::Above
+}
+
+fn default_scale() -> f64 {
+    1.5
+}
+
+/// Smallest and largest scale accepted in the config.
+pub const MIN_SCALE: f64 = 0.5;
+pub const MAX_SCALE: f64 = 4.0;
+
+/// Scale of each panel, optionally overridden while the keyboard is attached or detached.
+///
+/// A `scale` set in a custom layout takes precedence over these. Configs written by older versions
+/// have a single `scale = 1.5`, which is read as the same scale for both panels.
+///
+/// Only the range is checked here. Each backend then adjusts the scale to what its compositor
+/// supports, with a warning in the log: Mutter snaps to the closest scale of the mode, wlroots and
+/// KScreen round to multiples of 1/120, and X11 passes it to xrandr unchanged.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(from = "ScaleSetting")]
+pub struct ScaleConfig {
+    pub top: f64,
+    pub bottom: f64,
+    #[serde(default, skip_serializing_if = "PanelScales::is_empty")]
+    pub attached: PanelScales,
+    #[serde(default, skip_serializing_if = "PanelScales::is_empty")]
+    pub detached: PanelScales,
+}
+
+/// Per-panel scale overrides for one keyboard state.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+pub struct PanelScales {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bottom: Option<f64>,
+}
+
+impl PanelScales {
+    fn is_empty(&self) -> bool {
+        self.top.is_none() && self.bottom.is_none()
+    }
+}
+
+/// The on-disk forms of `scale`: the legacy flat number or the `[scale]` table.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScaleSetting {
+    Uniform(f64),
+    PerPanel {
+        #[serde(default = "default_scale")]
+        top: f64,
+        #[serde(default = "default_scale")]
+        bottom: f64,
+        #[serde(default)]
+        attached: PanelScales,
+        #[serde(default)]
+        detached: PanelScales,
+    },
+}
+
+impl From<ScaleSetting> for ScaleConfig {
+    fn from(setting: ScaleSetting) -> Self {
+        match setting {
+            ScaleSetting::Uniform(scale) => ScaleConfig::uniform(scale),
+            ScaleSetting::PerPanel { top, bottom, attached, detached } => ScaleConfig { top, bottom, attached, detached },
+        }
+    }
+}
+
+impl ScaleConfig {
+    pub fn uniform(scale: f64) -> Self {
+        ScaleConfig { top: scale, bottom: scale, attached: PanelScales::default(), detached: PanelScales::default() }
+    }
+
+    /// The `(top, bottom)` scales to use while the keyboard is attached or detached.
+    pub fn for_state(&self, attached: bool) -> (f64, f64) {
+        let overrides = if attached { &self.attached } else { &self.detached };
+        (overrides.top.unwrap_or(self.top), overrides.bottom.unwrap_or(self.bottom))
+    }
+
+    /// Replace scales outside `MIN_SCALE..=MAX_SCALE` with the default and log them.
+    fn validated(mut self) -> Self {
+        let default = Config::default().scale.top;
+        let check = |name: &str, scale: f64| {
+            if is_valid_scale(scale) {
+                scale
+            } else {
+                log::error!("Ignoring invalid scale {} for {}, using {}", scale, name, default);
+                default
+            }
+        };
+        let check_override = |name: &str, scale: Option<f64>| {
+            scale.filter(|s| {
+                let valid = is_valid_scale(*s);
+                if !valid {
+                    log::error!("Ignoring invalid scale {} for {}", s, name);
+                }
+                valid
+            })
+        };
+
+        self.top = check("top", self.top);
+        self.bottom = check("bottom", self.bottom);
+        self.attached.top = check_override("attached.top", self.attached.top);
+        self.attached.bottom = check_override("attached.bottom", self.attached.bottom);
+        self.detached.top = check_override("detached.top", self.detached.top);
+        self.detached.bottom = check_override("detached.bottom", self.detached.bottom);
+        self
+    }
+}
+
+pub fn is_valid_scale(scale: f64) -> bool {
+    scale.is_finite() && (MIN_SCALE..=MAX_SCALE).contains(&scale)
+}
+
+/// Which layout to apply for each keyboard state, plus user-defined layouts.
+///
+/// Layout names refer to `custom` entries first, then to the built-in layouts
+/// (`below`, `side-by-side`, `mirror`, `top-only`, `bottom-only`).
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct LayoutConfig {
+    /// Layout while the keyboard lies on the bottom panel.
+    #[serde(default = "default_attached_layout")]
+    pub attached: String,
+    /// Layout while the keyboard is detached.
+    #[serde(default = "default_detached_layout")]
+    pub detached: String,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub custom: HashMap<String, LayoutDefinition>,
+}
+
+fn default_attached_layout() -> String {
+    "top-only".to_string()
+}
+
+fn default_detached_layout() -> String {
+    "below".to_string()
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self {
+            attached: default_attached_layout(),
+            detached: default_detached_layout(),
+            custom: HashMap::new(),
+        }
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             brightness: 1,
-            scale: 1.5,
+            scale: ScaleConfig::uniform(default_scale()),
             verbose: false,
+            auto_rotate: default_auto_rotate(),
+            output_timeout_ms: default_output_timeout_ms(),
+            bottom_backlight_off: BacklightOff::default(),
             device: DeviceConfig {
                 vendor_id: "b05".to_string(),
                 product_id: "1bf2".to_string(),
+                bluetooth_product_id: None,
             },
+            panels: PanelConfig::default(),
+            layouts: LayoutConfig::default(),
+            touch: TouchConfig::default(),
+            external: ExternalConfig::default(),
+            brightness_sync: BrightnessSyncConfig::default(),
+            bluetooth: BluetoothConfig::default(),
+            backlight_idle: BacklightIdleConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// `brightness` as a keyboard backlight level, clamped to the levels 0 to 3 the keyboard has.
+    pub fn backlight_level(&self) -> u8 {
+        let level = self.brightness.clamp(0, 3);
+        if level != self.brightness {
+            log::warn!("Ignoring invalid brightness {}, using {}", self.brightness, level);
         }
+        level as u8
     }
 }
 
+/// Config of the system daemon, which has no user to take one from.
+pub const SYSTEM_CONFIG_PATH: &str = "/etc/zenbook-duo/config.toml";
+
 pub fn save_config(config: &Config) {
     if let Some(path) = get_config_path() {
-        let config_dir = path.parent().expect("Config path has no parent");
-        if !config_dir.exists() {
-            fs::create_dir_all(config_dir).expect("Failed to create config dir");
-        }
+        save_config_to(config, &path);
+    }
+}
 
-        let content = toml::to_string(config).expect("Failed to serialize config");
-        if let Err(e) = fs::write(&path, content) {
-            log::error!("Failed to write config file to {:?}: {}", path, e);
-        }
+fn save_config_to<T: Serialize>(config: &T, path: &Path) {
+    let config_dir = path.parent().expect("Config path has no parent");
+    if !config_dir.exists() {
+        fs::create_dir_all(config_dir).expect("Failed to create config dir");
+    }
+
+    let content = toml::to_string(config).expect("Failed to serialize config");
+    if let Err(e) = fs::write(path, content) {
+        log::error!("Failed to write config file to {:?}: {}", path, e);
     }
 }
 
 pub fn load_config() -> Config {
-    load_config_internal(false)
+    load_config_internal(get_config_path(), false)
 }
 
 pub fn load_config_interactive() -> Config {
-    load_config_internal(true)
+    load_config_internal(get_config_path(), true)
 }
 
-fn load_config_internal(interactive: bool) -> Config {
-    let config_path = get_config_path();
+/// Load the system daemon's config from [`SYSTEM_CONFIG_PATH`], or the defaults if there is none.
+pub fn load_system_config() -> Config {
+    load_config_internal(Some(PathBuf::from(SYSTEM_CONFIG_PATH)), false)
+}
 
-    if let Some(path) = config_path {
-        if path.exists() {
-            if let Ok(contents) = fs::read_to_string(&path) {
-                // Parse as generic value to check for missing fields
-                let table: toml::Value = contents.parse().unwrap_or(toml::Value::Table(Default::default()));
+fn load_config_internal(config_path: Option<PathBuf>, interactive: bool) -> Config {
+    if let Some(path) = config_path
+        && path.exists()
+        && let Ok(contents) = fs::read_to_string(&path) {
+        // Parse as generic value to check for missing fields
+        let table: toml::Value = contents.parse().unwrap_or(toml::Value::Table(Default::default()));
 
-                let mut config = Config::default();
-                let mut needs_save = false;
+        let mut config = Config::default();
+        let mut needs_save = false;
+        let mut migrated = false;
 
-                // Scale
-                if let Some(s) = table.get("scale").and_then(|v| v.as_float()) {
-                    config.scale = s;
-                } else if interactive {
-                    config.scale = prompt_scale();
-                    needs_save = true;
+        // Scale, either the legacy flat number or a [scale] table
+        if let Some(scale) = table.get("scale") {
+            match scale.clone().try_into::<ScaleConfig>() {
+                Ok(s) => {
+                    migrated = !scale.is_table();
+                    config.scale = s.validated();
                 }
+                Err(e) => log::error!("Ignoring invalid [scale] section: {}", e),
+            }
+        } else if interactive {
+            config.scale = ScaleConfig::uniform(prompt_scale());
+            needs_save = true;
+        }
 
-                // Brightness
-                if let Some(b) = table.get("brightness").and_then(|v| v.as_integer()) {
-                    config.brightness = b as i8;
-                } else if interactive {
-                    config.brightness = prompt_brightness();
-                    needs_save = true;
-                }
+        // Brightness
+        if let Some(b) = table.get("brightness").and_then(|v| v.as_integer()) {
+            config.brightness = b as i8;
+        } else if interactive {
+            config.brightness = prompt_brightness();
+            needs_save = true;
+        }
 
-                // Verbose
-                if let Some(v) = table.get("verbose").and_then(|v| v.as_bool()) {
-                    config.verbose = v;
-                }
+        // Verbose
+        if let Some(v) = table.get("verbose").and_then(|v| v.as_bool()) {
+            config.verbose = v;
+        }
 
-                // Device
-                if let Some(device) = table.get("device") {
-                    if let Ok(d) = device.clone().try_into() {
-                        config.device = d;
-                    }
-                }
+        // Auto-rotate
+        if let Some(r) = table.get("auto_rotate").and_then(|v| v.as_bool()) {
+            config.auto_rotate = r;
+        }
 
-                if needs_save && interactive {
-                    save_config(&config);
-                }
+        // Output timeout
+        if let Some(t) = table.get("output_timeout_ms").and_then(|v| v.as_integer()) {
+            config.output_timeout_ms = t.max(0) as u64;
+        }
+
+        // Bottom backlight
+        if let Some(off) = table.get("bottom_backlight_off") {
+            match off.clone().try_into() {
+                Ok(o) => config.bottom_backlight_off = o,
+                Err(e) => log::error!("Ignoring invalid bottom_backlight_off: {}", e),
+            }
+        }
+
+        // Device
+        if let Some(device) = table.get("device")
+            && let Ok(d) = device.clone().try_into() {
+            config.device = d;
+        }
+
+        // Panels
+        if let Some(panels) = table.get("panels")
+            && let Ok(p) = panels.clone().try_into() {
+            config.panels = p;
+        }
+
+        // Touch
+        if let Some(touch) = table.get("touch")
+            && let Ok(t) = touch.clone().try_into() {
+            config.touch = t;
+        }
+
+        // External monitors
+        if let Some(external) = table.get("external") {
+            match external.clone().try_into() {
+                Ok(e) => config.external = e,
+                Err(e) => log::error!("Ignoring invalid [external] section: {}", e),
+            }
+        }
+
+        // Brightness sync
+        if let Some(sync) = table.get("brightness_sync") {
+            match sync.clone().try_into() {
+                Ok(s) => config.brightness_sync = s,
+                Err(e) => log::error!("Ignoring invalid [brightness_sync] section: {}", e),
+            }
+        }
+
+        // Bluetooth
+        if let Some(bluetooth) = table.get("bluetooth") {
+            match bluetooth.clone().try_into() {
+                Ok(b) => config.bluetooth = b,
+                Err(e) => log::error!("Ignoring invalid [bluetooth] section: {}", e),
+            }
+        }
+
+        // Keyboard backlight idle timeout
+        if let Some(idle) = table.get("backlight_idle") {
+            match idle.clone().try_into() {
+                Ok(i) => config.backlight_idle = i,
+                Err(e) => log::error!("Ignoring invalid [backlight_idle] section: {}", e),
+            }
+        }
 
-                return config;
+        // Layouts
+        if let Some(layouts) = table.get("layouts") {
+            match layouts.clone().try_into() {
+                Ok(l) => config.layouts = l,
+                Err(e) => log::error!("Ignoring invalid [layouts] section: {}", e),
             }
         }
+
+        // Only rewrite the file when asked to, and then keep whatever else is in it as it is.
+        if interactive && needs_save {
+            save_config_to(&config, &path);
+        } else if interactive && migrated {
+            log::info!("Migrating flat 'scale' setting to a [scale] section");
+            save_config_to(&with_scale(table, &config.scale), &path);
+        }
+
+        return config;
     }
 
     let default_cfg = Config::default();
@@ -110,13 +582,21 @@ fn load_config_internal(interactive: bool) -> Config {
     default_cfg
 }
 
+/// `table` with its `scale` replaced by `scale`.
+fn with_scale(mut table: toml::Value, scale: &ScaleConfig) -> toml::Value {
+    if let Some(t) = table.as_table_mut() {
+        t.insert("scale".to_string(), toml::Value::try_from(scale).expect("Failed to serialize scale"));
+    }
+    table
+}
+
 fn prompt_scale() -> f64 {
     let mut input = String::new();
     print!("Missing config: What monitor scale (1.5 = 150%) [Default: 1.5]? ");
     io::stdout().flush().unwrap();
     let _ = io::stdin().read_line(&mut input);
     let val = input.trim();
-    if val.is_empty() { 1.5 } else { val.parse().unwrap_or(1.5) }
+    if val.is_empty() { 1.5 } else { val.parse().ok().filter(|s| is_valid_scale(*s)).unwrap_or(1.5) }
 }
 
 fn prompt_brightness() -> i8 {
@@ -161,14 +641,61 @@ pub fn get_config_path_for_home(home_override: Option<PathBuf>) -> Option<PathBu
 mod tests {
     use super::*;
 
+    #[test]
+    fn backlight_level_is_clamped() {
+        let with = |brightness| Config { brightness, ..Config::default() };
+        assert_eq!(with(2).backlight_level(), 2);
+        assert_eq!(with(-1).backlight_level(), 0);
+        assert_eq!(with(7).backlight_level(), 3);
+    }
+
     #[test]
     fn default_values_are_as_expected() {
         let cfg = Config::default();
         assert_eq!(cfg.brightness, 1);
-        assert_eq!(cfg.scale, 1.5);
-        assert_eq!(cfg.verbose, false);
+        assert_eq!(cfg.scale, ScaleConfig::uniform(1.5));
+        assert_eq!(cfg.scale.for_state(true), (1.5, 1.5));
+        assert!(!cfg.verbose);
+        assert!(cfg.auto_rotate);
+        assert_eq!(cfg.output_timeout_ms, 3000);
+        assert_eq!(cfg.bottom_backlight_off, BacklightOff::Never);
         assert_eq!(cfg.device.vendor_id, "b05");
         assert_eq!(cfg.device.product_id, "1bf2");
+        assert_eq!(cfg.panels, PanelConfig::default());
+        assert_eq!(cfg.layouts.attached, "top-only");
+        assert_eq!(cfg.layouts.detached, "below");
+        assert!(cfg.touch.inhibit_bottom);
+        assert_eq!(cfg.external.policy, ExternalPolicy::Keep);
+        assert!(cfg.brightness_sync.enabled);
+        assert_eq!((cfg.brightness_sync.offset, cfg.brightness_sync.gamma), (0.0, 1.0));
+        assert!(cfg.bluetooth.connect_on_detach && !cfg.bluetooth.disconnect_on_attach);
+        assert_eq!(cfg.bluetooth.battery_warnings, [20, 10]);
+    }
+
+    #[test]
+    fn parses_external_policy() {
+        let external: ExternalConfig = toml::from_str("policy = \"internal-off\"").expect("deserialize");
+        assert_eq!(external.policy, ExternalPolicy::InternalOff);
+        assert_eq!(external.position, Position::Above);
+
+        let external: ExternalConfig = toml::from_str("policy = \"relative\"\nposition = \"right-of\"\nscale = 1.25")
+            .expect("deserialize");
+        assert_eq!((external.policy, external.position, external.scale), (ExternalPolicy::Relative, Position::RightOf, Some(1.25)));
+        assert!(toml::from_str::<ExternalConfig>("policy = \"docked\"").is_err());
+    }
+
+    #[test]
+    fn backlight_idle_applies_on_ac_and_battery_by_default() {
+        assert_eq!(Config::default().backlight_idle, BacklightIdleConfig { timeout_secs: 0, on_ac: true, on_battery: true });
+        let idle: BacklightIdleConfig = toml::from_str("timeout_secs = 30\non_ac = false").expect("deserialize");
+        assert_eq!((idle.timeout_secs, idle.on_ac, idle.on_battery), (30, false, true));
+    }
+
+    #[test]
+    fn touch_section_without_inhibit_keeps_default() {
+        let touch: TouchConfig = toml::from_str("bottom = \"ELAN9009\"").expect("deserialize");
+        assert_eq!(touch.bottom.as_deref(), Some("ELAN9009"));
+        assert!(touch.inhibit_bottom);
     }
 
     #[test]
@@ -187,7 +714,104 @@ mod tests {
         assert_eq!(de.brightness, cfg.brightness);
         assert_eq!(de.scale, cfg.scale);
         assert_eq!(de.verbose, cfg.verbose);
+        assert_eq!(de.auto_rotate, cfg.auto_rotate);
+        assert_eq!(de.output_timeout_ms, cfg.output_timeout_ms);
+        assert_eq!(de.bottom_backlight_off, cfg.bottom_backlight_off);
         assert_eq!(de.device.vendor_id, cfg.device.vendor_id);
         assert_eq!(de.device.product_id, cfg.device.product_id);
+        assert_eq!(de.panels, cfg.panels);
+        assert_eq!(de.layouts, cfg.layouts);
+        assert_eq!(de.touch, cfg.touch);
+        assert_eq!(de.external, cfg.external);
+        assert_eq!(de.brightness_sync, cfg.brightness_sync);
+        assert_eq!(de.bluetooth, cfg.bluetooth);
+        assert_eq!(de.backlight_idle, cfg.backlight_idle);
+    }
+
+    #[test]
+    fn panel_overrides_round_trip() {
+        let mut cfg = Config::default();
+        cfg.panels.bottom = Some("eDP-3".to_string());
+        let s = toml::to_string(&cfg).expect("serialize");
+        let de: Config = toml::from_str(&s).expect("deserialize");
+        assert_eq!(de.panels.top, None);
+        assert_eq!(de.panels.bottom.as_deref(), Some("eDP-3"));
+    }
+
+    #[test]
+    fn custom_layouts_parse() {
+        let cfg: Config = toml::from_str(r#"
+            brightness = 1
+            scale = 1.5
+            verbose = false
+
+            [device]
+            vendor_id = "b05"
+            product_id = "1bf2"
+
+            [layouts]
+            detached = "presentation"
+
+            [layouts.custom.presentation]
+            top = { position = "origin" }
+            bottom = { position = "above", rotation = "inverted", scale = 1.25 }
+        "#).expect("deserialize");
+
+        assert_eq!(cfg.layouts.attached, "top-only");
+        assert_eq!(cfg.layouts.detached, "presentation");
+        assert_eq!(cfg.scale, ScaleConfig::uniform(1.5));
+        let presentation = &cfg.layouts.custom["presentation"];
+        assert!(presentation.top.enabled);
+        assert_eq!(presentation.bottom.scale, Some(1.25));
+
+        let s = toml::to_string(&cfg).expect("serialize");
+        let de: Config = toml::from_str(&s).expect("deserialize");
+        assert_eq!(de.layouts, cfg.layouts);
+    }
+
+    #[test]
+    fn per_panel_and_per_state_scales() {
+        let mut cfg = Config::default();
+        cfg.scale.bottom = 1.25;
+        cfg.scale.detached.top = Some(2.0);
+
+        let s = toml::to_string(&cfg).expect("serialize");
+        assert!(s.contains("[scale]"));
+        let de: Config = toml::from_str(&s).expect("deserialize");
+        assert_eq!(de.scale, cfg.scale);
+        assert_eq!(de.scale.for_state(true), (1.5, 1.25));
+        assert_eq!(de.scale.for_state(false), (2.0, 1.25));
+    }
+
+    #[test]
+    fn legacy_flat_scale_is_migrated() {
+        let value: toml::Value = "scale = 2".parse().unwrap();
+        let scale: ScaleConfig = value.get("scale").unwrap().clone().try_into().expect("migrate");
+        assert_eq!(scale, ScaleConfig::uniform(2.0));
+
+        let migrated = with_scale("scale = 2\nbrightness = 1\n[touch]\ninhibit_bottom = \"yes\"".parse().unwrap(), &scale);
+        assert_eq!(migrated.get("scale").unwrap().get("top").and_then(|v| v.as_float()), Some(2.0));
+        assert_eq!(migrated.get("touch").unwrap().get("inhibit_bottom").and_then(|v| v.as_str()), Some("yes"));
+    }
+
+    #[test]
+    fn scale_table_defaults_each_panel() {
+        let value: toml::Value = "[scale]\nbottom = 1.25".parse().unwrap();
+        let scale: ScaleConfig = value.get("scale").unwrap().clone().try_into().unwrap();
+        assert_eq!((scale.top, scale.bottom), (1.5, 1.25));
+    }
+
+    #[test]
+    fn invalid_scales_are_replaced() {
+        let scale = ScaleConfig {
+            top: 0.0,
+            bottom: 1.25,
+            attached: PanelScales { top: Some(f64::NAN), bottom: Some(9.0) },
+            detached: PanelScales { top: Some(1.75), bottom: None },
+        }.validated();
+        assert_eq!(scale.top, 1.5);
+        assert_eq!(scale.bottom, 1.25);
+        assert_eq!(scale.attached, PanelScales::default());
+        assert_eq!(scale.detached.top, Some(1.75));
     }
 }
@@ -0,0 +1,166 @@
+//! The protocol spoken on the daemon's socket, by the CLI as well as by the session agents.
+//!
+//! Every line is one JSON object carrying the protocol `version` next to a request or message, e.g.
+//! `{"version":2,"request":"set-backlight","level":2}` answered by `{"version":2,"type":"done"}`.
+//! A connection takes any number of requests, each answered by exactly one message, until it
+//! sends `subscribe` or `agent`; from then on the daemon only sends.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use crate::usb::DeviceState;
+
+/// Bumped whenever a request or message changes incompatibly; 2 added the keyboard's battery.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "kebab-case")]
+pub enum Request {
+    /// Answered with [`Message::Status`].
+    Status,
+    /// Set the keyboard backlight to `level` (0 to 3).
+    SetBacklight { level: u8 },
+    /// Have the agent of the active session apply the layout called `name`.
+    ApplyLayout { name: String },
+    /// Reload the daemon's config and have every agent reload its user's config.
+    ReloadConfig,
+    /// Receive the current state and then every change as `keyboard`, `backlight`, `battery` and
+    /// `session` messages.
+    Subscribe,
+    /// Register as the agent of the caller's graphical session: receive `keyboard` messages while
+    /// that session is active, every `backlight` and `battery` level, and `apply-layout` and
+    /// `reload-config` to act on.
+    Agent,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Message {
+    Status(Status),
+    /// The request was carried out.
+    Done,
+    Error { message: String },
+    Keyboard { state: DeviceState },
+    Backlight { level: u8 },
+    /// The keyboard's battery level in percent, `None` while it is not connected over Bluetooth.
+    Battery { level: Option<u8> },
+    /// The user of the active graphical session changed.
+    Session { user: Option<u32> },
+    ApplyLayout { name: String },
+    ReloadConfig,
+}
+
+/// What the daemon knows, answering [`Request::Status`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    pub keyboard: Option<DeviceState>,
+    /// The keyboard backlight level last set, if any.
+    pub backlight: Option<u8>,
+    /// The keyboard's battery level while it is connected over Bluetooth.
+    #[serde(default)]
+    pub battery: Option<u8>,
+    /// The user of the active graphical session.
+    pub active_user: Option<u32>,
+    /// The users of the connected agents.
+    pub agents: Vec<u32>,
+    /// The digitizer nodes inhibited while the keyboard lies on the bottom panel.
+    pub inhibited: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Versioned<T> {
+    version: u32,
+    #[serde(flatten)]
+    body: T,
+}
+
+/// `body` as one line of the protocol, including the newline.
+pub fn encode<T: Serialize>(body: &T) -> String {
+    let mut line = serde_json::to_string(&Versioned { version: PROTOCOL_VERSION, body })
+        .expect("Failed to serialize control message");
+    line.push('\n');
+    line
+}
+
+/// Parse one line of the protocol, refusing other protocol versions.
+pub fn decode<T: DeserializeOwned>(line: &str) -> Result<T, String> {
+    #[derive(Deserialize)]
+    struct Version {
+        version: u32,
+    }
+
+    let Version { version } = serde_json::from_str(line).map_err(|e| format!("invalid message: {}", e))?;
+    if version != PROTOCOL_VERSION {
+        return Err(format!("protocol version {} is not supported, expected {}", version, PROTOCOL_VERSION));
+    }
+    serde_json::from_str::<Versioned<T>>(line)
+        .map(|versioned| versioned.body)
+        .map_err(|e| format!("invalid message: {}", e))
+}
+
+/// Send `request` to the daemon listening at `path` and call `each` with every message it sends
+/// back until `each` returns `false` or the daemon hangs up.
+///
+/// Fails with `NotFound` or `ConnectionRefused` if no daemon is running.
+pub fn call_with(path: &Path, request: &Request, mut each: impl FnMut(Message) -> bool) -> io::Result<()> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.write_all(encode(request).as_bytes())?;
+
+    for line in BufReader::new(stream).lines() {
+        let message = decode(&line?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if !each(message) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Send `request` to the daemon listening at `path` and return its answer, see [`call_with`].
+pub fn call(path: &Path, request: &Request) -> io::Result<Message> {
+    let mut answer = None;
+    call_with(path, request, |message| {
+        answer = Some(message);
+        false
+    })?;
+    answer.ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "the daemon hung up without answering"))
+}
+
+/// Whether `e` means that no daemon is listening, as opposed to a failing one.
+pub fn is_not_running(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_versioned_lines() {
+        let line = encode(&Request::SetBacklight { level: 2 });
+        assert_eq!(line, "{\"version\":2,\"request\":\"set-backlight\",\"level\":2}\n");
+        assert_eq!(decode::<Request>(line.trim()), Ok(Request::SetBacklight { level: 2 }));
+
+        let line = encode(&Message::Keyboard { state: DeviceState::Added });
+        assert_eq!(line, "{\"version\":2,\"type\":\"keyboard\",\"state\":\"added\"}\n");
+        assert_eq!(decode::<Message>(&line), Ok(Message::Keyboard { state: DeviceState::Added }));
+
+        let status = Message::Status(Status {
+            keyboard: Some(DeviceState::Removed),
+            backlight: Some(1),
+            battery: Some(80),
+            active_user: Some(1000),
+            agents: vec![1000],
+            inhibited: vec![],
+        });
+        assert_eq!(decode::<Message>(&encode(&status)), Ok(status));
+    }
+
+    #[test]
+    fn refuses_other_versions() {
+        assert!(decode::<Request>(r#"{"version":1,"request":"status"}"#).unwrap_err().contains("version 1"));
+        assert!(decode::<Request>(r#"{"request":"status"}"#).is_err());
+        assert!(decode::<Request>(r#"{"version":2,"request":"reboot"}"#).is_err());
+    }
+}
@@ -0,0 +1,462 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{broadcast, watch};
+use log::{debug, error, info, warn};
+use crate::bluez;
+use crate::config::{load_system_config, Config};
+use crate::control::{self, Message, Request, Status};
+use crate::logind;
+use crate::touch;
+use crate::usb::{self, backlight, DeviceState};
+
+/// Where the system daemon listens for the CLI and the agents of graphical sessions.
+pub const SOCKET_PATH: &str = "/run/zenbook-duo/daemon.sock";
+
+/// Longest request accepted, in bytes; real ones are a few dozen.
+const MAX_REQUEST_LEN: u64 = 4096;
+
+/// What every connection to the daemon shares.
+pub struct Shared {
+    pub config: watch::Sender<Config>,
+    pub keyboard: watch::Receiver<Option<DeviceState>>,
+    /// The keyboard's battery level while it is connected over Bluetooth.
+    pub battery: watch::Receiver<Option<u8>>,
+    /// The user of the active graphical session.
+    pub users: watch::Receiver<Option<u32>>,
+    /// Messages for the agents, for one user or for all (`None`).
+    agent_messages: broadcast::Sender<(Option<u32>, Message)>,
+    /// The users of the connected agents, once per agent.
+    agents: Mutex<Vec<u32>>,
+}
+
+impl Shared {
+    pub fn new(
+        config: watch::Sender<Config>,
+        keyboard: watch::Receiver<Option<DeviceState>>,
+        battery: watch::Receiver<Option<u8>>,
+        users: watch::Receiver<Option<u32>>,
+    ) -> Self {
+        Shared { config, keyboard, battery, users, agent_messages: broadcast::channel(16).0, agents: Mutex::new(Vec::new()) }
+    }
+
+    /// Root and the user of the active graphical session may change things; anyone may look.
+    fn may_control(&self, uid: u32) -> bool {
+        uid == 0 || *self.users.borrow() == Some(uid)
+    }
+}
+
+/// Run as the system daemon: own the keyboard and the input devices, tell the agent of the active
+/// graphical session when the keyboard is attached or removed so it can apply the layout, and
+/// answer requests on the control socket (see `control`).
+pub async fn run(config: Config) {
+    let listener = match bind(Path::new(SOCKET_PATH)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to listen on {}: {}", SOCKET_PATH, e);
+            return;
+        }
+    };
+
+    let (config_sender, config) = watch::channel(config);
+    let (keyboard_sender, keyboard) = watch::channel(None);
+    let (battery_sender, battery) = watch::channel(None);
+    let (user_sender, users) = watch::channel(None);
+
+    tokio::task::spawn_local(usb::watch_keyboard(config.clone(), keyboard_sender));
+    tokio::task::spawn_local(usb::follow_devices(config.clone(), keyboard.clone()));
+    tokio::task::spawn_local(usb::monitor_special_keys(config.clone()));
+    tokio::task::spawn_local(usb::led::expose_as_led(config.clone()));
+    tokio::task::spawn_local(bluez::manage_keyboard(config, keyboard.clone(), battery_sender));
+    tokio::task::spawn_local(logind::monitor_active_user(user_sender));
+
+    info!("Daemon listening on {}", SOCKET_PATH);
+    serve(listener, Arc::new(Shared::new(config_sender, keyboard, battery, users))).await;
+}
+
+/// Listen on `path`, replacing a socket left behind by an earlier run.
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    if let Err(e) = fs::remove_file(path)
+        && e.kind() != io::ErrorKind::NotFound {
+        return Err(e);
+    }
+    let listener = UnixListener::bind(path)?;
+    // Any user may connect; what they may do depends on who they are, see `Shared::may_control`.
+    fs::set_permissions(path, fs::Permissions::from_mode(0o666))?;
+    Ok(listener)
+}
+
+/// Accept connections on `listener` and serve each, see [`serve_client`].
+pub async fn serve(listener: UnixListener, shared: Arc<Shared>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(serve_client(stream, shared.clone()));
+            }
+            Err(e) => error!("Failed to accept a connection: {}", e),
+        }
+    }
+}
+
+/// Answer the requests on `stream` until the client hangs up or turns the connection into a
+/// stream with `subscribe` or `agent`.
+///
+/// The user is taken from the socket's peer credentials, so a client cannot pose as someone else.
+async fn serve_client(stream: UnixStream, shared: Arc<Shared>) {
+    let uid = match stream.peer_cred() {
+        Ok(credentials) => credentials.uid(),
+        Err(e) => {
+            warn!("Rejecting client without credentials: {}", e);
+            return;
+        }
+    };
+
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    while let Some(line) = next_request(&mut reader, uid).await {
+        let answer = match control::decode::<Request>(&line) {
+            Ok(Request::Subscribe) => return stream_events(reader, writer, &shared).await,
+            Ok(Request::Agent) => return serve_agent(uid, reader, writer, &shared).await,
+            Ok(request) => {
+                debug!("Request from user {}: {:?}", uid, request);
+                answer(request, uid, &shared).await
+            }
+            Err(message) => Message::Error { message },
+        };
+        if send(&mut writer, &answer).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Read the next request line from the client of `uid`; `None` once it hung up, or if the line is
+/// longer than [`MAX_REQUEST_LEN`], which ends the connection as the socket is open to everyone.
+async fn next_request(reader: &mut BufReader<OwnedReadHalf>, uid: u32) -> Option<String> {
+    let mut line = String::new();
+    match reader.take(MAX_REQUEST_LEN + 1).read_line(&mut line).await {
+        Ok(0) | Err(_) => None,
+        Ok(_) if line.len() as u64 > MAX_REQUEST_LEN => {
+            warn!("Dropping client of user {} for a request longer than {} bytes", uid, MAX_REQUEST_LEN);
+            None
+        }
+        Ok(_) => Some(line),
+    }
+}
+
+async fn answer(request: Request, uid: u32, shared: &Shared) -> Message {
+    let error = |message: &str| Message::Error { message: message.to_string() };
+    match request {
+        Request::Status => Message::Status(Status {
+            keyboard: *shared.keyboard.borrow(),
+            backlight: *backlight::level().borrow(),
+            battery: *shared.battery.borrow(),
+            active_user: *shared.users.borrow(),
+            agents: shared.agents.lock().unwrap().clone(),
+            inhibited: touch::inhibited(),
+        }),
+        _ if !shared.may_control(uid) => error("only root and the user of the active session may do that"),
+        Request::SetBacklight { level } if level > 3 => error("the backlight level must be between 0 and 3"),
+        Request::SetBacklight { level } => {
+            let config = shared.config.borrow().clone();
+            match tokio::task::spawn_blocking(move || backlight::set_backlight_level(level, &config)).await {
+                Ok(Ok(())) => {
+                    info!("Backlight set to level {} on request", level);
+                    Message::Done
+                }
+                Ok(Err(e)) => Message::Error { message: format!("failed to set the backlight: {}", e) },
+                Err(e) => Message::Error { message: e.to_string() },
+            }
+        }
+        Request::ApplyLayout { name } => {
+            let Some(user) = *shared.users.borrow() else { return error("no graphical session is active") };
+            if !shared.agents.lock().unwrap().contains(&user) {
+                return error("no agent is running in the active session");
+            }
+            let _ = shared.agent_messages.send((Some(user), Message::ApplyLayout { name }));
+            Message::Done
+        }
+        Request::ReloadConfig => {
+            info!("Reloading config from {}", crate::config::SYSTEM_CONFIG_PATH);
+            shared.config.send_replace(load_system_config());
+            let _ = shared.agent_messages.send((None, Message::ReloadConfig));
+            Message::Done
+        }
+        Request::Subscribe | Request::Agent => error("not a request with an answer"),
+    }
+}
+
+async fn send(writer: &mut OwnedWriteHalf, message: &Message) -> io::Result<()> {
+    writer.write_all(control::encode(message).as_bytes()).await
+}
+
+/// Wait until the client on `reader` hangs up; it has nothing more to say, so anything it sends
+/// ends the stream as well.
+async fn hung_up(reader: &mut BufReader<OwnedReadHalf>) {
+    let _ = reader.fill_buf().await;
+}
+
+/// Send the keyboard state, backlight level, battery level and active user, then every change to
+/// them.
+async fn stream_events(mut reader: BufReader<OwnedReadHalf>, mut writer: OwnedWriteHalf, shared: &Shared) {
+    let mut keyboard = shared.keyboard.clone();
+    let mut users = shared.users.clone();
+    let mut level = backlight::level();
+    let mut battery = shared.battery.clone();
+    keyboard.mark_changed();
+    users.mark_changed();
+    level.mark_changed();
+    if battery.borrow().is_some() {
+        battery.mark_changed();
+    }
+
+    loop {
+        let message = tokio::select! {
+            Ok(()) = keyboard.changed() => match *keyboard.borrow_and_update() {
+                Some(state) => Message::Keyboard { state },
+                None => continue,
+            },
+            Ok(()) = users.changed() => Message::Session { user: *users.borrow_and_update() },
+            Ok(()) = level.changed() => match *level.borrow_and_update() {
+                Some(level) => Message::Backlight { level },
+                None => continue,
+            },
+            Ok(()) = battery.changed() => Message::Battery { level: *battery.borrow_and_update() },
+            _ = hung_up(&mut reader) => return,
+        };
+        if send(&mut writer, &message).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Keep the agent of `uid` up to date on the keyboard: send its state whenever it changes while the
+/// agent's user owns the active graphical session, and once more when that session becomes active.
+/// Send every backlight and battery level, and pass on the messages meant for the agent.
+async fn serve_agent(uid: u32, mut reader: BufReader<OwnedReadHalf>, mut writer: OwnedWriteHalf, shared: &Shared) {
+    info!("Agent of user {} connected", uid);
+    shared.agents.lock().unwrap().push(uid);
+
+    let mut keyboard = shared.keyboard.clone();
+    let mut users = shared.users.clone();
+    let mut level = backlight::level();
+    let mut battery = shared.battery.clone();
+    let mut messages = shared.agent_messages.subscribe();
+    level.mark_changed();
+    if battery.borrow().is_some() {
+        battery.mark_changed();
+    }
+    let mut resend = true;
+    loop {
+        let active = *users.borrow_and_update() == Some(uid);
+        let state = *keyboard.borrow_and_update();
+        if resend
+            && active
+            && let Some(state) = state {
+            debug!("Telling agent of user {} the keyboard is {:?}", uid, state);
+            if send(&mut writer, &Message::Keyboard { state }).await.is_err() {
+                break;
+            }
+        }
+
+        resend = false;
+        let message = tokio::select! {
+            Ok(()) = keyboard.changed() => {
+                resend = true;
+                None
+            }
+            Ok(()) = users.changed() => {
+                resend = true;
+                None
+            }
+            Ok(()) = level.changed() => level.borrow_and_update().map(|level| Message::Backlight { level }),
+            Ok(()) = battery.changed() => Some(Message::Battery { level: *battery.borrow_and_update() }),
+            message = messages.recv() => match message {
+                Ok((to, message)) if to.is_none_or(|to| to == uid) => Some(message),
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => None,
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = hung_up(&mut reader) => break,
+        };
+        if let Some(message) = message
+            && send(&mut writer, &message).await.is_err() {
+            break;
+        }
+    }
+
+    let mut agents = shared.agents.lock().unwrap();
+    if let Some(index) = agents.iter().position(|agent| *agent == uid) {
+        agents.remove(index);
+    }
+    info!("Agent of user {} disconnected", uid);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use tempfile::TempDir;
+    use tokio::sync::mpsc;
+    use crate::agent::{self, Outlets};
+
+    struct Fixture {
+        _dir: TempDir,
+        path: PathBuf,
+        uid: u32,
+        keyboard: watch::Sender<Option<DeviceState>>,
+        battery: watch::Sender<Option<u8>>,
+        users: watch::Sender<Option<u32>>,
+        daemon: tokio::task::JoinHandle<()>,
+    }
+
+    /// A daemon on a socket in a temporary directory, with the keyboard attached and another user's
+    /// session active.
+    fn start() -> Fixture {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("run").join("daemon.sock");
+        let uid = fs::metadata(dir.path()).unwrap().uid();
+
+        // A stale socket does not stop the daemon from starting.
+        drop(bind(&path).unwrap());
+        let listener = bind(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o666);
+
+        let (keyboard, keyboard_receiver) = watch::channel(Some(DeviceState::Added));
+        let (battery, battery_receiver) = watch::channel(None);
+        let (users, user_receiver) = watch::channel(Some(uid + 1));
+        let shared = Shared::new(watch::channel(Config::default()).0, keyboard_receiver, battery_receiver, user_receiver);
+        let daemon = tokio::spawn(serve(listener, Arc::new(shared)));
+        Fixture { _dir: dir, path, uid, keyboard, battery, users, daemon }
+    }
+
+    async fn call(path: &Path, request: Request) -> Message {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || control::call(&path, &request)).await.unwrap().expect("call")
+    }
+
+    async fn next<T: Clone>(receiver: &mut watch::Receiver<T>) -> T {
+        tokio::time::timeout(Duration::from_secs(5), receiver.changed()).await.expect("timeout").expect("closed");
+        receiver.borrow_and_update().clone()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn notifies_only_the_active_user() {
+        let daemon = start();
+        let (states, mut state_receiver) = watch::channel(None);
+        let (backlight, _backlight_receiver) = watch::channel(None);
+        let (battery, _battery_receiver) = watch::channel(None);
+        let (layouts, mut layout_receiver) = mpsc::unbounded_channel();
+        let (config, mut config_receiver) = watch::channel(Config::default());
+        let outlets = Outlets { states, backlight, battery, layouts, config };
+        let stream = agent::connect(&daemon.path).await.unwrap();
+        let agent = tokio::spawn(async move { agent::receive(stream, &outlets).await });
+
+        // Another user's session is active, so nothing is sent and nothing may be applied.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!state_receiver.has_changed().unwrap());
+        let refused = call(&daemon.path, Request::ApplyLayout { name: "below".to_string() }).await;
+        assert!(matches!(refused, Message::Error { .. }));
+
+        daemon.users.send_replace(Some(daemon.uid));
+        assert_eq!(next(&mut state_receiver).await, Some(DeviceState::Added));
+        daemon.keyboard.send_replace(Some(DeviceState::Removed));
+        assert_eq!(next(&mut state_receiver).await, Some(DeviceState::Removed));
+
+        // Switching away and back resends the state, which the agent already has.
+        daemon.users.send_replace(None);
+        daemon.keyboard.send_replace(Some(DeviceState::Added));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!state_receiver.has_changed().unwrap());
+        daemon.users.send_replace(Some(daemon.uid));
+        assert_eq!(next(&mut state_receiver).await, Some(DeviceState::Added));
+
+        assert_eq!(call(&daemon.path, Request::ApplyLayout { name: "below".to_string() }).await, Message::Done);
+        assert_eq!(layout_receiver.recv().await.as_deref(), Some("below"));
+        assert_eq!(call(&daemon.path, Request::ReloadConfig).await, Message::Done);
+        next(&mut config_receiver).await;
+
+        daemon.daemon.abort();
+        agent.abort();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn answers_requests() {
+        let daemon = start();
+        let Message::Status(status) = call(&daemon.path, Request::Status).await else { panic!("no status") };
+        assert_eq!(status.keyboard, Some(DeviceState::Added));
+        assert_eq!(status.battery, None);
+        assert_eq!(status.active_user, Some(daemon.uid + 1));
+        assert!(status.agents.is_empty());
+
+        // Only root and the active session's user may change the backlight, and only to a valid level.
+        // Root may do anything, so the refusal only shows for other users.
+        if daemon.uid != 0 {
+            let refused = call(&daemon.path, Request::SetBacklight { level: 2 }).await;
+            assert!(matches!(refused, Message::Error { message } if message.contains("active session")));
+        }
+        daemon.users.send_replace(Some(daemon.uid));
+        let invalid = call(&daemon.path, Request::SetBacklight { level: 4 }).await;
+        assert!(matches!(invalid, Message::Error { message } if message.contains("between 0 and 3")));
+
+        // Other protocol versions are refused without dropping the connection.
+        let mut stream = std::os::unix::net::UnixStream::connect(&daemon.path).unwrap();
+        let answers = tokio::task::spawn_blocking(move || {
+            use std::io::{BufRead, Write};
+            stream.write_all(b"{\"version\":1,\"request\":\"status\"}\n").unwrap();
+            stream.write_all(control::encode(&Request::Status).as_bytes()).unwrap();
+            let mut lines = std::io::BufReader::new(stream).lines();
+            (lines.next().unwrap().unwrap(), lines.next().unwrap().unwrap())
+        }).await.unwrap();
+        assert!(matches!(control::decode(&answers.0), Ok(Message::Error { .. })));
+        assert!(matches!(control::decode(&answers.1), Ok(Message::Status(_))));
+
+        // An endless request gets the client dropped instead of buffered.
+        let mut stream = std::os::unix::net::UnixStream::connect(&daemon.path).unwrap();
+        let answer = tokio::task::spawn_blocking(move || {
+            use std::io::{Read, Write};
+            let _ = stream.write_all(&[b'x'; 2 * MAX_REQUEST_LEN as usize]);
+            let mut answer = Vec::new();
+            // Closing with the rest unread may reset the connection instead.
+            let _ = stream.read_to_end(&mut answer);
+            answer
+        }).await.unwrap();
+        assert!(answer.is_empty());
+
+        daemon.daemon.abort();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn streams_events() {
+        let daemon = start();
+        let (sender, mut events) = mpsc::unbounded_channel();
+        let path = daemon.path.clone();
+        std::thread::spawn(move || {
+            control::call_with(&path, &Request::Subscribe, |message| sender.send(message).is_ok())
+        });
+
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            received.push(tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap());
+        }
+        assert!(received.contains(&Message::Keyboard { state: DeviceState::Added }));
+        assert!(received.contains(&Message::Session { user: Some(daemon.uid + 1) }));
+
+        daemon.keyboard.send_replace(Some(DeviceState::Removed));
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap();
+        assert_eq!(event, Some(Message::Keyboard { state: DeviceState::Removed }));
+        daemon.battery.send_replace(Some(15));
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap();
+        assert_eq!(event, Some(Message::Battery { level: Some(15) }));
+
+        daemon.daemon.abort();
+    }
+}
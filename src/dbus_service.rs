@@ -0,0 +1,242 @@
+use std::path::Path;
+use log::{error, info};
+use tokio::sync::{mpsc, watch};
+use zbus::object_server::SignalEmitter;
+use zbus::{fdo, interface, Connection};
+use crate::config::Config;
+use crate::control::{self, Message, Request};
+use crate::daemon::SOCKET_PATH;
+use crate::layout;
+use crate::usb::{backlight, DeviceState};
+
+pub const BUS_NAME: &str = "io.github.TheFehr.ZenbookDuo";
+pub const OBJECT_PATH: &str = "/io/github/TheFehr/ZenbookDuo";
+
+/// How [`Duo`] changes the keyboard backlight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BacklightControl {
+    /// Ask the system daemon, which owns the keyboard.
+    Daemon,
+    /// Set it from this process, without a daemon.
+    Direct,
+}
+
+/// The Duo as seen from the graphical session, for desktop widgets and scripts.
+pub struct Duo {
+    pub config: watch::Receiver<Config>,
+    pub states: watch::Receiver<Option<DeviceState>>,
+    pub backlight: watch::Receiver<Option<u8>>,
+    pub layout: watch::Receiver<Option<String>>,
+    /// Layouts to apply on request.
+    pub layouts: mpsc::UnboundedSender<String>,
+    pub backlight_control: BacklightControl,
+}
+
+#[interface(name = "io.github.TheFehr.ZenbookDuo")]
+impl Duo {
+    /// `added` while the keyboard is attached, `removed` while it is not, empty until known.
+    #[zbus(property)]
+    fn device_state(&self) -> String {
+        match *self.states.borrow() {
+            Some(DeviceState::Added) => "added",
+            Some(DeviceState::Removed) => "removed",
+            None => "",
+        }.to_string()
+    }
+
+    /// The keyboard backlight level from 0 to 3, or -1 until it is first set.
+    #[zbus(property)]
+    fn backlight(&self) -> i32 {
+        self.backlight.borrow().map_or(-1, i32::from)
+    }
+
+    /// The layout last applied; empty before the first one and after the user's own arrangement
+    /// was restored.
+    #[zbus(property)]
+    fn layout(&self) -> String {
+        self.layout.borrow().clone().unwrap_or_default()
+    }
+
+    async fn set_backlight(&self, level: u8) -> fdo::Result<()> {
+        if level > 3 {
+            return Err(fdo::Error::InvalidArgs("the backlight level must be between 0 and 3".to_string()));
+        }
+        let config = self.config.borrow().clone();
+        let backlight_control = self.backlight_control;
+        let result = tokio::task::spawn_blocking(move || match backlight_control {
+            BacklightControl::Daemon => match control::call(Path::new(SOCKET_PATH), &Request::SetBacklight { level }) {
+                Ok(Message::Error { message }) => Err(message),
+                Ok(_) => Ok(()),
+                Err(e) => Err(format!("failed to talk to the daemon: {}", e)),
+            },
+            BacklightControl::Direct => backlight::set_backlight_level(level, &config)
+                .map_err(|e| format!("failed to set the backlight: {}", e)),
+        }).await;
+        match result {
+            Ok(result) => result.map_err(fdo::Error::Failed),
+            Err(e) => Err(fdo::Error::Failed(e.to_string())),
+        }
+    }
+
+    /// Apply the layout called `name`, turned to how the device is held while the keyboard is
+    /// detached. Returns once the layout is queued; whether it could be applied shows in `Layout`.
+    fn apply_layout(&self, name: &str) -> fdo::Result<()> {
+        if layout::definition(name, &self.config.borrow().layouts).is_none() {
+            return Err(fdo::Error::InvalidArgs(format!("unknown layout '{}'", name)));
+        }
+        self.layouts.send(name.to_string()).map_err(|_| fdo::Error::Failed("layouts are not being applied".to_string()))
+    }
+
+    #[zbus(signal)]
+    async fn keyboard_attached(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn keyboard_detached(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn backlight_level_changed(emitter: &SignalEmitter<'_>, level: u8) -> zbus::Result<()>;
+}
+
+/// Serve `duo` at [`OBJECT_PATH`] on `connection` under [`BUS_NAME`], and signal every change to
+/// the keyboard, the backlight and the layout until their senders are gone.
+pub async fn serve(connection: &Connection, duo: Duo) -> zbus::Result<()> {
+    let mut states = duo.states.clone();
+    let mut backlight = duo.backlight.clone();
+    let mut layout = duo.layout.clone();
+    states.mark_unchanged();
+    backlight.mark_unchanged();
+    layout.mark_unchanged();
+
+    connection.object_server().at(OBJECT_PATH, duo).await?;
+    connection.request_name(BUS_NAME).await?;
+    let duo = connection.object_server().interface::<_, Duo>(OBJECT_PATH).await?;
+    let emitter = duo.signal_emitter();
+
+    loop {
+        tokio::select! {
+            Ok(()) = states.changed() => {
+                let state = *states.borrow_and_update();
+                duo.get().await.device_state_changed(emitter).await?;
+                match state {
+                    Some(DeviceState::Added) => Duo::keyboard_attached(emitter).await?,
+                    Some(DeviceState::Removed) => Duo::keyboard_detached(emitter).await?,
+                    None => {}
+                }
+            }
+            Ok(()) = backlight.changed() => {
+                let level = *backlight.borrow_and_update();
+                duo.get().await.backlight_changed(emitter).await?;
+                if let Some(level) = level {
+                    Duo::backlight_level_changed(emitter, level).await?;
+                }
+            }
+            Ok(()) = layout.changed() => {
+                layout.mark_unchanged();
+                duo.get().await.layout_changed(emitter).await?;
+            }
+            else => return Ok(()),
+        }
+    }
+}
+
+/// Publish `duo` on the session bus, see [`serve`].
+///
+/// Does nothing but log if the session bus is not available or the name is taken.
+pub async fn run(duo: Duo) {
+    let connection = match Connection::session().await {
+        Ok(connection) => connection,
+        Err(e) => {
+            error!("Failed to connect to the session bus, not publishing {}: {}", BUS_NAME, e);
+            return;
+        }
+    };
+
+    info!("Publishing {} on the session bus", BUS_NAME);
+    if let Err(e) = serve(&connection, duo).await {
+        error!("Failed to publish {}: {}", BUS_NAME, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use futures::StreamExt;
+    use zbus::proxy;
+    use zbus::proxy::CacheProperties;
+    use crate::test_bus::test_bus_or_skip;
+
+    #[proxy(
+        interface = "io.github.TheFehr.ZenbookDuo",
+        default_service = "io.github.TheFehr.ZenbookDuo",
+        default_path = "/io/github/TheFehr/ZenbookDuo"
+    )]
+    trait Duo {
+        fn set_backlight(&self, level: u8) -> zbus::Result<()>;
+
+        fn apply_layout(&self, name: &str) -> zbus::Result<()>;
+
+        #[zbus(property)]
+        fn device_state(&self) -> zbus::Result<String>;
+
+        #[zbus(property)]
+        fn backlight(&self) -> zbus::Result<i32>;
+
+        #[zbus(property)]
+        fn layout(&self) -> zbus::Result<String>;
+
+        #[zbus(signal)]
+        fn keyboard_detached(&self) -> zbus::Result<()>;
+
+        #[zbus(signal)]
+        fn backlight_level_changed(&self, level: u8) -> zbus::Result<()>;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn publishes_state_and_takes_requests() {
+        let bus = test_bus_or_skip!();
+        let (_config, config) = watch::channel(Config::default());
+        let (state_sender, states) = watch::channel(Some(DeviceState::Added));
+        let (backlight_sender, backlight) = watch::channel(Some(2));
+        let (_layout_sender, layout) = watch::channel(Some("top-only".to_string()));
+        let (layouts, mut layout_requests) = mpsc::unbounded_channel();
+        let duo = Duo { config, states, backlight, layout, layouts, backlight_control: BacklightControl::Direct };
+
+        let service: Connection = bus.connect().into();
+        let server = tokio::spawn(async move { serve(&service, duo).await });
+
+        let client: Connection = bus.connect().into();
+        let proxy = DuoProxy::builder(&client).cache_properties(CacheProperties::No).build().await.unwrap();
+        let mut detached = proxy.receive_keyboard_detached().await.unwrap();
+        let mut levels = proxy.receive_backlight_level_changed().await.unwrap();
+
+        // The name is only taken once the object is served.
+        let mut state = Err(zbus::Error::Failure("not yet".to_string()));
+        for _ in 0..50 {
+            state = proxy.device_state().await;
+            if state.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(state.unwrap(), "added");
+        assert_eq!(proxy.backlight().await.unwrap(), 2);
+        assert_eq!(proxy.layout().await.unwrap(), "top-only");
+
+        proxy.apply_layout("below").await.unwrap();
+        assert_eq!(layout_requests.recv().await.as_deref(), Some("below"));
+        assert!(proxy.apply_layout("sideways").await.is_err());
+        assert!(proxy.set_backlight(4).await.is_err());
+
+        state_sender.send_replace(Some(DeviceState::Removed));
+        tokio::time::timeout(Duration::from_secs(5), detached.next()).await.unwrap().unwrap();
+        assert_eq!(proxy.device_state().await.unwrap(), "removed");
+
+        backlight_sender.send_replace(Some(0));
+        let signal = tokio::time::timeout(Duration::from_secs(5), levels.next()).await.unwrap().unwrap();
+        assert_eq!(signal.args().unwrap().level, 0);
+        assert_eq!(proxy.backlight().await.unwrap(), 0);
+
+        server.abort();
+    }
+}
@@ -0,0 +1,304 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::unix::AsyncFd;
+use tokio::time::Instant;
+use udev::MonitorBuilder;
+use log::{debug, warn};
+use crate::config::PanelConfig;
+
+pub const DRM_SYSFS: &str = "/sys/class/drm";
+
+/// How often connector status is re-read while waiting, in case a hotplug event was missed.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A DRM connector as exposed under `/sys/class/drm/card*-<name>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Connector {
+    /// Connector name as used by compositors, e.g. `eDP-1`.
+    pub name: String,
+    /// The DRM card the connector belongs to, e.g. `card1`.
+    pub card: String,
+    pub path: PathBuf,
+    pub connected: bool,
+    pub enabled: bool,
+    /// Physical size in millimetres, taken from the EDID.
+    pub size_mm: Option<(u32, u32)>,
+}
+
+impl Connector {
+    pub fn is_internal(&self) -> bool {
+        self.name.starts_with("eDP-")
+    }
+}
+
+/// The connectors of the two internal panels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Panels {
+    pub top: String,
+    pub bottom: String,
+}
+
+impl Default for Panels {
+    fn default() -> Self {
+        Panels { top: "eDP-1".to_string(), bottom: "eDP-2".to_string() }
+    }
+}
+
+/// List all connectors below `root` (normally [`DRM_SYSFS`]), sorted by card and connector number.
+pub fn connectors(root: &Path) -> Vec<Connector> {
+    let Ok(entries) = fs::read_dir(root) else {
+        warn!("Failed to read {:?}", root);
+        return Vec::new();
+    };
+
+    let mut connectors: Vec<Connector> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let (card, name) = file_name.split_once('-')?;
+            if !card.starts_with("card") {
+                return None;
+            }
+            let path = entry.path();
+            let status = read_trimmed(&path.join("status"))?;
+            let enabled = read_trimmed(&path.join("enabled")).is_some_and(|e| e == "enabled");
+            let size_mm = fs::read(path.join("edid")).ok().and_then(|edid| edid_size_mm(&edid));
+
+            Some(Connector {
+                name: name.to_string(),
+                card: card.to_string(),
+                path,
+                connected: status == "connected",
+                enabled,
+                size_mm,
+            })
+        })
+        .collect();
+
+    connectors.sort_by_key(|c| (c.card.clone(), connector_index(&c.name), c.name.clone()));
+    connectors
+}
+
+/// Work out which eDP connector drives the top and which the bottom panel.
+///
+/// Connectors set in `config` win; the remaining panel is the other connected eDP connector.
+/// Without overrides the lower-numbered connector is the top panel. Falls back to `eDP-1`/`eDP-2`
+/// when fewer than two internal panels are found.
+pub fn discover_panels(root: &Path, config: &PanelConfig) -> Panels {
+    let internal: Vec<String> = connectors(root)
+        .into_iter()
+        .filter(|c| c.is_internal() && c.connected)
+        .map(|c| {
+            debug!("Found internal panel {} on {} (size: {:?} mm)", c.name, c.card, c.size_mm);
+            c.name
+        })
+        .collect();
+
+    let other_than = |taken: &str| internal.iter().find(|n| n.as_str() != taken).cloned();
+    let defaults = Panels::default();
+
+    let panels = match (&config.top, &config.bottom) {
+        (Some(top), Some(bottom)) => Panels { top: top.clone(), bottom: bottom.clone() },
+        (Some(top), None) => Panels { bottom: other_than(top).unwrap_or(defaults.bottom), top: top.clone() },
+        (None, Some(bottom)) => Panels { top: other_than(bottom).unwrap_or(defaults.top), bottom: bottom.clone() },
+        (None, None) if internal.len() >= 2 => Panels { top: internal[0].clone(), bottom: internal[1].clone() },
+        (None, None) => {
+            warn!("Found {} internal panel(s), assuming eDP-1 (top) and eDP-2 (bottom)", internal.len());
+            defaults
+        }
+    };
+    debug!("Using {} as top and {} as bottom panel", panels.top, panels.bottom);
+    panels
+}
+
+/// Names of the connected external monitors below `root`: every connector that is neither internal
+/// nor one of the `panels`.
+pub fn discover_externals(root: &Path, panels: &Panels) -> Vec<String> {
+    connectors(root)
+        .into_iter()
+        .filter(|c| c.connected && !c.is_internal() && c.name != panels.top && c.name != panels.bottom)
+        .map(|c| {
+            debug!("Found external monitor on {} (size: {:?} mm)", c.name, c.size_mm);
+            c.name
+        })
+        .collect()
+}
+
+/// Wait until every connector in `names` reports `connected` below `root`, or `timeout` passes.
+///
+/// Wakes up on DRM hotplug events from udev and also re-reads the status every `POLL_INTERVAL`, in
+/// case an event was missed or udev could not be watched. Returns whether all of them are connected.
+pub async fn wait_for_connectors(root: &Path, names: &[&str], timeout: Duration) -> bool {
+    let all_connected = || {
+        let connectors = connectors(root);
+        names.iter().all(|name| connectors.iter().any(|c| c.name == *name && c.connected))
+    };
+
+    // Listen before the first check, so a change right after it is not lost.
+    let mut hotplug = MonitorBuilder::new()
+        .and_then(|builder| builder.match_subsystem("drm"))
+        .and_then(|builder| builder.listen())
+        .and_then(AsyncFd::new)
+        .map_err(|e| warn!("Cannot watch DRM hotplug events, polling instead: {}", e))
+        .ok();
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if all_connected() {
+            return true;
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return false;
+        }
+        let tick = remaining.min(POLL_INTERVAL);
+
+        let Some(monitor) = hotplug.as_mut() else {
+            tokio::time::sleep(tick).await;
+            continue;
+        };
+        tokio::select! {
+            guard = monitor.readable_mut() => match guard {
+                Ok(mut guard) => {
+                    for event in guard.get_inner_mut().iter() {
+                        debug!("DRM {:?} event for {:?}", event.event_type(), event.sysname());
+                    }
+                    guard.clear_ready();
+                }
+                Err(e) => {
+                    warn!("Stopped watching DRM hotplug events: {}", e);
+                    hotplug = None;
+                }
+            },
+            _ = tokio::time::sleep(tick) => {}
+        }
+    }
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+/// `eDP-12` → 12, so that `eDP-10` sorts after `eDP-2`.
+fn connector_index(name: &str) -> u32 {
+    name.rsplit('-').next().and_then(|n| n.parse().ok()).unwrap_or(u32::MAX)
+}
+
+/// Screen size from the EDID base block (bytes 21/22 hold it in centimetres).
+fn edid_size_mm(edid: &[u8]) -> Option<(u32, u32)> {
+    const HEADER: [u8; 8] = [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+    if edid.len() < 128 || edid[..8] != HEADER || edid[21] == 0 || edid[22] == 0 {
+        return None;
+    }
+    Some((edid[21] as u32 * 10, edid[22] as u32 * 10))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Create `<root>/<dir>` with a `status` file and, for connected panels, a minimal EDID.
+    pub(crate) fn add_connector(root: &Path, dir: &str, connected: bool) {
+        let path = root.join(dir);
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("status"), if connected { "connected\n" } else { "disconnected\n" }).unwrap();
+        fs::write(path.join("enabled"), "enabled\n").unwrap();
+        if connected {
+            let mut edid = vec![0u8; 128];
+            edid[..8].copy_from_slice(&[0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00]);
+            edid[21] = 31;
+            edid[22] = 19;
+            fs::write(path.join("edid"), edid).unwrap();
+        }
+    }
+
+    fn fake_sysfs(dirs: &[(&str, bool)]) -> TempDir {
+        let root = TempDir::new().unwrap();
+        for (dir, connected) in dirs {
+            add_connector(root.path(), dir, *connected);
+        }
+        // Non-connector entries living next to the connectors.
+        fs::create_dir_all(root.path().join("card1")).unwrap();
+        fs::write(root.path().join("version"), "drm 1.1.0\n").unwrap();
+        root
+    }
+
+    #[test]
+    fn lists_connectors_with_status_and_size() {
+        let root = fake_sysfs(&[("card1-eDP-2", true), ("card1-eDP-1", true), ("card1-DP-1", false)]);
+        let connectors = connectors(root.path());
+
+        let names: Vec<&str> = connectors.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["DP-1", "eDP-1", "eDP-2"]);
+        assert!(!connectors[0].connected);
+        assert!(connectors[1].is_internal());
+        assert_eq!(connectors[1].card, "card1");
+        assert_eq!(connectors[1].size_mm, Some((310, 190)));
+        assert_eq!(connectors[0].size_mm, None);
+    }
+
+    #[test]
+    fn discovers_shifted_panel_names() {
+        let root = fake_sysfs(&[("card2-eDP-3", true), ("card2-eDP-2", true)]);
+        let panels = discover_panels(root.path(), &PanelConfig::default());
+        assert_eq!(panels, Panels { top: "eDP-2".into(), bottom: "eDP-3".into() });
+    }
+
+    #[test]
+    fn config_overrides_mapping() {
+        let root = fake_sysfs(&[("card1-eDP-1", true), ("card1-eDP-2", true)]);
+
+        let swapped = PanelConfig { top: Some("eDP-2".into()), bottom: None };
+        assert_eq!(discover_panels(root.path(), &swapped), Panels { top: "eDP-2".into(), bottom: "eDP-1".into() });
+
+        let explicit = PanelConfig { top: Some("eDP-5".into()), bottom: Some("eDP-6".into()) };
+        assert_eq!(discover_panels(root.path(), &explicit), Panels { top: "eDP-5".into(), bottom: "eDP-6".into() });
+    }
+
+    #[test]
+    fn falls_back_to_defaults_without_two_panels() {
+        let root = fake_sysfs(&[("card1-eDP-1", true), ("card1-eDP-2", false)]);
+        assert_eq!(discover_panels(root.path(), &PanelConfig::default()), Panels::default());
+        assert_eq!(discover_panels(Path::new("/nonexistent"), &PanelConfig::default()), Panels::default());
+    }
+
+    #[test]
+    fn discovers_connected_externals() {
+        let root = fake_sysfs(&[
+            ("card1-eDP-1", true), ("card1-eDP-2", true), ("card1-DP-1", false), ("card1-DP-2", true), ("card1-HDMI-A-1", true),
+        ]);
+        assert_eq!(discover_externals(root.path(), &Panels::default()), vec!["HDMI-A-1", "DP-2"]);
+    }
+
+    #[tokio::test]
+    async fn waits_for_connectors_to_connect() {
+        let root = fake_sysfs(&[("card1-eDP-1", true), ("card1-eDP-2", false)]);
+        let timeout = Duration::from_secs(5);
+        assert!(wait_for_connectors(root.path(), &["eDP-1"], timeout).await);
+
+        let path = root.path().to_path_buf();
+        let plug = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            add_connector(&path, "card1-eDP-2", true);
+        });
+        assert!(wait_for_connectors(root.path(), &["eDP-1", "eDP-2"], timeout).await);
+        plug.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_timeout() {
+        let root = fake_sysfs(&[("card1-eDP-1", true), ("card1-eDP-2", false)]);
+        let start = Instant::now();
+        assert!(!wait_for_connectors(root.path(), &["eDP-2"], Duration::from_millis(300)).await);
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert!(!wait_for_connectors(root.path(), &["eDP-3"], Duration::ZERO).await);
+    }
+
+    #[test]
+    fn rejects_invalid_edid() {
+        assert_eq!(edid_size_mm(&[0u8; 128]), None);
+        assert_eq!(edid_size_mm(&[0u8; 4]), None);
+    }
+}
@@ -5,13 +5,14 @@ use std::path::{Path};
 use std::process::Command;
 use directories::BaseDirs;
 use crate::config;
+use crate::monitor_handling::WLROOTS_DESKTOPS;
 
-/// Install the Zenbook Duo CLI, create a per-user systemd service for it, and enable/start that service.
+/// Install the Zenbook Duo CLI, the system daemon and a per-user systemd service, and enable/start both.
 ///
 /// This performs interactive preflight checks (distribution and desktop environment), optionally prompts
 /// the user if running as root, loads or creates configuration interactively, installs the current
 /// executable to /usr/local/bin/zenbook-duo (using a temporary file and sudo for copying/replacing),
-/// writes a systemd user unit under ~/.config/systemd/user/zenbook-duo.service configured with the
+/// installs the system daemon (see `install_daemon`), writes a systemd user unit under ~/.config/systemd/user/zenbook-duo.service configured with the
 /// detected desktop environment, reloads the user systemd daemon, and enables/starts the service.
 ///
 /// # Examples
@@ -58,7 +59,7 @@ pub fn install() {
     let copy_status = Command::new("sudo")
         .arg("cp")
         .arg(&current_exe)
-        .arg(&temp_install_path)
+        .arg(temp_install_path)
         .status();
 
     match copy_status {
@@ -75,14 +76,14 @@ pub fn install() {
     }
 
     // Ensure temp file is executable (cp usually preserves, but be safe)
-    let _ = Command::new("sudo").arg("chmod").arg("+x").arg(&temp_install_path).status();
+    let _ = Command::new("sudo").arg("chmod").arg("+x").arg(temp_install_path).status();
 
     // Step 2: atomically replace the destination
     let mv_status = Command::new("sudo")
         .arg("mv")
         .arg("-f")
-        .arg(&temp_install_path)
-        .arg(&install_path)
+        .arg(temp_install_path)
+        .arg(install_path)
         .status();
 
     match mv_status {
@@ -100,7 +101,10 @@ pub fn install() {
     }
 
 
-    // 4. Create Systemd User Service
+    // 4. Install the system daemon, which owns the keyboard and input devices
+    install_daemon(install_path);
+
+    // 5. Create Systemd User Service
     // We install it to ~/.config/systemd/user/ so no sudo needed
     // Use BaseDirs to get the standard config location
     let base_dirs = BaseDirs::new().expect("Could not determine base directories");
@@ -111,6 +115,7 @@ pub fn install() {
     }
 
     let service_path = service_dir.join("zenbook-duo.service");
+    let session_type = env::var("XDG_SESSION_TYPE").unwrap_or_else(|_| "wayland".to_string());
 
     let service_content = format!(
         r#"[Unit]
@@ -122,11 +127,12 @@ ExecStart={}
 Restart=always
 RestartSec=5
 Environment=XDG_CURRENT_DESKTOP={}
+Environment=XDG_SESSION_TYPE={}
 
 [Install]
 WantedBy=default.target
 "#,
-        install_path.display(), desktop_env
+        install_path.display(), desktop_env, session_type
     );
 
     match fs::write(&service_path, service_content) {
@@ -137,16 +143,16 @@ WantedBy=default.target
         }
     }
 
-    // 5. Reload Daemon & Enable
+    // 6. Reload Daemon & Enable
     println!("Reloading systemd user daemon...");
 
     // No sudo needed for user services
     let _ = Command::new("systemctl")
-        .args(&["--user", "daemon-reload"])
+        .args(["--user", "daemon-reload"])
         .status();
 
     let status = Command::new("systemctl")
-        .args(&["--user", "enable", "--now", "zenbook-duo.service"])
+        .args(["--user", "enable", "--now", "zenbook-duo.service"])
         .status();
 
     match status {
@@ -160,41 +166,114 @@ WantedBy=default.target
     println!("Installation complete!");
 }
 
+/// Install the system daemon: copy the user's config to `/etc/zenbook-duo/config.toml`, write the
+/// `zenbook-duo-daemon.service` system unit running `zenbook-duo --daemon` as root, and enable/start it.
+///
+/// Failures are reported but not fatal; without the daemon the user service watches the keyboard itself.
+fn install_daemon(install_path: &Path) {
+    println!("Installing system daemon...");
+
+    if let Some(config_path) = config::get_config_path() {
+        sudo_install(&config_path, Path::new(config::SYSTEM_CONFIG_PATH));
+    }
+
+    let unit = format!(
+        r#"[Unit]
+Description=Zenbook Duo Keyboard Daemon
+After=systemd-logind.service
+
+[Service]
+# Provides /dev/uleds for the keyboard backlight LED; not fatal if built in or missing.
+ExecStartPre=-/sbin/modprobe uleds
+ExecStart={} --daemon
+Restart=always
+RestartSec=5
+RuntimeDirectory=zenbook-duo
+
+[Install]
+WantedBy=multi-user.target
+"#,
+        install_path.display()
+    );
+    let temp_unit = env::temp_dir().join("zenbook-duo-daemon.service");
+    if let Err(e) = fs::write(&temp_unit, unit) {
+        eprintln!("Failed to write daemon service file to {:?}: {}", temp_unit, e);
+        return;
+    }
+    let installed = sudo_install(&temp_unit, Path::new("/etc/systemd/system/zenbook-duo-daemon.service"));
+    let _ = fs::remove_file(&temp_unit);
+    if !installed {
+        return;
+    }
+
+    let _ = Command::new("sudo").args(["systemctl", "daemon-reload"]).status();
+    match Command::new("sudo").args(["systemctl", "enable", "--now", "zenbook-duo-daemon.service"]).status() {
+        Ok(s) if s.success() => println!("Daemon enabled and started."),
+        Ok(s) => eprintln!("Failed to enable daemon. Exit status: {}", s),
+        Err(e) => eprintln!("Failed to execute systemctl: {}", e),
+    }
+}
+
+/// Copy `source` to `target` as root, readable by everyone; returns whether that worked.
+fn sudo_install(source: &Path, target: &Path) -> bool {
+    let status = Command::new("sudo")
+        .args(["install", "-D", "-m", "644"])
+        .arg(source)
+        .arg(target)
+        .status();
+
+    match status {
+        Ok(s) if s.success() => {
+            println!("Installed {:?}", target);
+            true
+        }
+        Ok(s) => {
+            eprintln!("Failed to install {:?} (exit code {}).", target, s);
+            false
+        }
+        Err(e) => {
+            eprintln!("Failed to execute sudo install: {}", e);
+            false
+        }
+    }
+}
+
 /// Detects the desktop environment and performs a Fedora release compatibility check.
 ///
 /// Reads /etc/os-release and warns (with a prompt) if the host is not Fedora Linux 43.
 /// Determines the desktop environment from the `XDG_CURRENT_DESKTOP` environment variable
-/// (case-insensitive). If that variable is not decisive, falls back to checking for the
-/// presence of `/usr/bin/gdctl` or `/usr/bin/kscreen-doctor`. Exits the process
-/// with status 1 when the user declines to continue after the distro warning or when no
-/// supported desktop environment can be detected.
+/// (case-insensitive), then `XDG_SESSION_TYPE` for other Xorg desktops. If that is not decisive,
+/// falls back to checking for the presence of `/usr/bin/gnome-shell`, `/usr/bin/kscreen-doctor` or
+/// `/usr/bin/wlr-randr`. Exits the process with status 1 when the user declines to continue after
+/// the distro warning or when no supported desktop environment can be detected.
 ///
 /// # Returns
 ///
-/// `"GNOME"` if a GNOME desktop environment is detected, `"KDE"` if a KDE Plasma environment is detected.
+/// `"GNOME"` if a GNOME desktop environment is detected, `"KDE"` if a KDE Plasma environment is detected,
+/// the upper-cased compositor name (e.g. `"SWAY"`, or `"WLROOTS"` as a fallback) for wlroots compositors,
+/// or `"X11"` for any other Xorg session where `xrandr` is available.
 ///
 /// # Examples
 ///
 /// ```
-/// // The return value will be either "GNOME" or "KDE" depending on the environment.
+/// // The return value will be "GNOME", "KDE", a wlroots compositor or "X11" depending on the environment.
 /// let env = check_requirements();
-/// assert!(env == "GNOME" || env == "KDE");
+/// assert!(!env.is_empty());
 /// ```
 fn check_requirements() -> &'static str {
     // Check Distro
-    if let Ok(os_release) = fs::read_to_string("/etc/os-release") {
-        if !os_release.contains("Fedora Linux 43") {
-            println!("Warning: This tool is designed for Fedora Linux 43.");
-            println!("If you are on Fedora 42 or older, please use the original script:");
-            println!("https://github.com/jaydio/zenbook-duo-linux-fedora");
-
-            print!("Do you want to continue anyway? [y/N] ");
-            io::stdout().flush().unwrap();
-            let mut answer = String::new();
-            io::stdin().read_line(&mut answer).unwrap();
-            if !answer.trim().eq_ignore_ascii_case("y") {
-                std::process::exit(1);
-            }
+    if let Ok(os_release) = fs::read_to_string("/etc/os-release")
+        && !os_release.contains("Fedora Linux 43") {
+        println!("Warning: This tool is designed for Fedora Linux 43.");
+        println!("If you are on Fedora 42 or older, please use the original script:");
+        println!("https://github.com/jaydio/zenbook-duo-linux-fedora");
+
+        print!("Do you want to continue anyway? [y/N] ");
+        io::stdout().flush().unwrap();
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer).unwrap();
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            std::process::exit(1);
         }
     }
 
@@ -205,15 +284,23 @@ fn check_requirements() -> &'static str {
         return "GNOME";
     } else if desktop.contains("KDE") {
         return "KDE";
+    } else if let Some(wlroots) = WLROOTS_DESKTOPS.iter().find(|d| desktop.contains(*d)) {
+        return wlroots;
+    } else if env::var("XDG_SESSION_TYPE").is_ok_and(|t| t.eq_ignore_ascii_case("x11"))
+        && Path::new("/usr/bin/xrandr").exists()
+    {
+        return "X11";
     }
 
     // Fallbacks
-    if Path::new("/usr/bin/gdctl").exists() {
-        return "GNOME";
+    if Path::new("/usr/bin/gnome-shell").exists() {
+        "GNOME"
     } else if Path::new("/usr/bin/kscreen-doctor").exists() {
-        return "KDE";
+        "KDE"
+    } else if Path::new("/usr/bin/wlr-randr").exists() {
+        "WLROOTS"
     } else {
-        println!("Error: This tool relies on GNOME (gdctl) or KDE Plasma (kscreen-doctor).");
+        println!("Error: This tool relies on GNOME (Mutter), KDE Plasma (KScreen), a wlroots compositor (wlr-randr) or an Xorg session (xrandr).");
         println!("It seems you are running an unsupported environment.");
         std::process::exit(1);
     }
@@ -0,0 +1,586 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use log::{error, warn};
+use crate::config::{is_valid_scale, ExternalConfig, ExternalPolicy, LayoutConfig};
+use crate::drm::Panels;
+
+/// Names of the layouts that are always available.
+pub const BUILTIN_LAYOUTS: &[&str] = &["below", "side-by-side", "mirror", "top-only", "bottom-only"];
+
+/// Where a panel goes relative to the other panel.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Position {
+    /// Anchored at the origin of the layout.
+    #[default]
+    Origin,
+    Below,
+    Above,
+    LeftOf,
+    RightOf,
+    /// Showing the same content as the other panel.
+    Mirror,
+}
+
+/// Rotation of a panel, counter-clockwise.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rotation {
+    #[default]
+    Normal,
+    /// 90°
+    Left,
+    /// 180°
+    Inverted,
+    /// 270°
+    Right,
+}
+
+impl Rotation {
+    /// Whether width and height trade places under this rotation.
+    pub fn swaps_axes(self) -> bool {
+        matches!(self, Rotation::Left | Rotation::Right)
+    }
+
+    fn quarter_turns(self) -> u8 {
+        match self {
+            Rotation::Normal => 0,
+            Rotation::Left => 1,
+            Rotation::Inverted => 2,
+            Rotation::Right => 3,
+        }
+    }
+
+    fn from_quarter_turns(turns: u8) -> Rotation {
+        match turns % 4 {
+            0 => Rotation::Normal,
+            1 => Rotation::Left,
+            2 => Rotation::Inverted,
+            _ => Rotation::Right,
+        }
+    }
+
+    /// This rotation followed by `other`.
+    pub fn then(self, other: Rotation) -> Rotation {
+        Rotation::from_quarter_turns(self.quarter_turns() + other.quarter_turns())
+    }
+
+    /// The rotation that takes `from` to `self`.
+    pub fn relative_to(self, from: Rotation) -> Rotation {
+        Rotation::from_quarter_turns(self.quarter_turns() + 4 - from.quarter_turns())
+    }
+}
+
+impl Position {
+    /// Where the panel ends up once the content is turned a quarter counter-clockwise, i.e. the
+    /// device a quarter clockwise: what was below is now to the left.
+    fn turned_left(self) -> Position {
+        match self {
+            Position::Below => Position::LeftOf,
+            Position::LeftOf => Position::Above,
+            Position::Above => Position::RightOf,
+            Position::RightOf => Position::Below,
+            other => other,
+        }
+    }
+
+    fn rotated(self, rotation: Rotation) -> Position {
+        (0..rotation.quarter_turns()).fold(self, |position, _| position.turned_left())
+    }
+}
+
+/// How one panel is configured in a layout definition.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct PanelLayout {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub position: Position,
+    #[serde(default)]
+    pub rotation: Rotation,
+    /// Overrides the global `scale` for this panel.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<f64>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl PanelLayout {
+    const fn on(position: Position, rotation: Rotation) -> Self {
+        PanelLayout { enabled: true, position, rotation, scale: None }
+    }
+
+    const fn off() -> Self {
+        PanelLayout { enabled: false, position: Position::Origin, rotation: Rotation::Normal, scale: None }
+    }
+}
+
+/// A layout as written in the config: one entry per panel, positioned relative to each other.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct LayoutDefinition {
+    pub top: PanelLayout,
+    pub bottom: PanelLayout,
+}
+
+impl LayoutDefinition {
+    /// The layout for a device held in `orientation`, turning both panels and their placement as
+    /// one unit.
+    ///
+    /// The rotation of the first enabled panel is taken as the orientation the layout was written
+    /// for, so `side-by-side` stays as it is when the device is held that way and becomes `below`
+    /// when it is held upright.
+    pub fn oriented(&self, orientation: Rotation) -> LayoutDefinition {
+        let designed_for = if self.top.enabled { self.top.rotation } else { self.bottom.rotation };
+        let delta = orientation.relative_to(designed_for);
+        let turn = |panel: &PanelLayout| PanelLayout {
+            position: panel.position.rotated(delta),
+            rotation: panel.rotation.then(delta),
+            ..panel.clone()
+        };
+        LayoutDefinition { top: turn(&self.top), bottom: turn(&self.bottom) }
+    }
+}
+
+/// The built-in layouts, see [`BUILTIN_LAYOUTS`].
+pub fn builtin(name: &str) -> Option<LayoutDefinition> {
+    use Position::*;
+    use Rotation::*;
+
+    let (top, bottom) = match name {
+        "below" => (PanelLayout::on(Origin, Normal), PanelLayout::on(Below, Normal)),
+        // Book mode: the Duo turned a quarter counter-clockwise, so the top panel is on the left.
+        "side-by-side" => (PanelLayout::on(Origin, Right), PanelLayout::on(RightOf, Right)),
+        "mirror" => (PanelLayout::on(Origin, Normal), PanelLayout::on(Mirror, Normal)),
+        "top-only" => (PanelLayout::on(Origin, Normal), PanelLayout::off()),
+        "bottom-only" => (PanelLayout::off(), PanelLayout::on(Origin, Normal)),
+        _ => return None,
+    };
+    Some(LayoutDefinition { top, bottom })
+}
+
+/// The definition of the layout called `name`: a custom layout from `config`, else a built-in one.
+pub fn definition(name: &str, config: &LayoutConfig) -> Option<LayoutDefinition> {
+    config.custom.get(name).cloned().or_else(|| builtin(name))
+}
+
+/// One output of a resolved [`Layout`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputLayout {
+    pub connector: String,
+    pub enabled: bool,
+    pub position: Position,
+    /// The output `position` is relative to; `None` for outputs at the origin.
+    pub relative_to: Option<String>,
+    pub rotation: Rotation,
+    pub scale: f64,
+    pub primary: bool,
+}
+
+/// A named layout resolved to concrete connectors, ready for a `DisplayManager`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub name: String,
+    pub outputs: Vec<OutputLayout>,
+    /// External outputs the display manager has to leave as they are.
+    pub keep: Vec<String>,
+}
+
+impl Layout {
+    /// Resolve the layout called `name` (custom layouts from `config` first, then built-ins)
+    /// for the given panels, using the `(top, bottom)` `scales` where the definition does not set one.
+    #[cfg(test)]
+    pub fn resolve(name: &str, config: &LayoutConfig, panels: &Panels, scales: (f64, f64)) -> Option<Layout> {
+        Some(Layout::from_definition(name, &definition(name, config)?, panels, scales))
+    }
+
+    pub fn from_definition(name: &str, definition: &LayoutDefinition, panels: &Panels, scales: (f64, f64)) -> Layout {
+        let output = |connector: &str, panel: &PanelLayout, other: &str, other_panel: &PanelLayout, scale: f64| {
+            let relative = panel.position != Position::Origin && other_panel.enabled;
+            OutputLayout {
+                connector: connector.to_string(),
+                enabled: panel.enabled,
+                position: if relative { panel.position } else { Position::Origin },
+                relative_to: relative.then(|| other.to_string()),
+                rotation: panel.rotation,
+                scale: panel.scale
+                    .filter(|s| {
+                        let valid = is_valid_scale(*s);
+                        if !valid {
+                            error!("Ignoring invalid scale {} for {} in layout '{}'", s, connector, name);
+                        }
+                        valid
+                    })
+                    .unwrap_or(scale),
+                primary: false,
+            }
+        };
+
+        let mut outputs = vec![
+            output(&panels.top, &definition.top, &panels.bottom, &definition.bottom, scales.0),
+            output(&panels.bottom, &definition.bottom, &panels.top, &definition.top, scales.1),
+        ];
+        // The top panel is primary unless it is off.
+        if let Some(primary) = outputs.iter_mut().find(|o| o.enabled) {
+            primary.primary = true;
+        }
+
+        Layout { name: name.to_string(), outputs, keep: Vec::new() }
+    }
+
+    #[cfg(test)]
+    pub fn output(&self, connector: &str) -> Option<&OutputLayout> {
+        self.outputs.iter().find(|o| o.connector == connector)
+    }
+
+    pub fn enabled_outputs(&self) -> impl Iterator<Item = &OutputLayout> {
+        self.outputs.iter().filter(|o| o.enabled)
+    }
+
+    /// A copy of the layout that treats the `externals` connectors as `config` says.
+    ///
+    /// With the `relative` policy the first external output is placed next to the primary panel and
+    /// the others in a row to its right. With `internal-off` the panels are switched off and the
+    /// external outputs are lined up from the origin, the first one primary.
+    pub fn with_externals(&self, externals: &[String], config: &ExternalConfig) -> Layout {
+        let mut layout = self.clone();
+        if externals.is_empty() {
+            return layout;
+        }
+
+        let scale = config.scale
+            .filter(|s| {
+                let valid = is_valid_scale(*s);
+                if !valid {
+                    error!("Ignoring invalid scale {} for external monitors", s);
+                }
+                valid
+            })
+            .unwrap_or(1.0);
+        let mut reference = match config.policy {
+            ExternalPolicy::Keep => {
+                layout.keep = externals.to_vec();
+                return layout;
+            }
+            ExternalPolicy::Relative => layout.outputs.iter().find(|o| o.primary).map(|o| o.connector.clone()),
+            ExternalPolicy::InternalOff => {
+                for output in &mut layout.outputs {
+                    *output = OutputLayout {
+                        enabled: false,
+                        position: Position::Origin,
+                        relative_to: None,
+                        primary: false,
+                        ..output.clone()
+                    };
+                }
+                None
+            }
+        };
+
+        for (i, connector) in externals.iter().enumerate() {
+            let position = match &reference {
+                None => Position::Origin,
+                Some(_) if i == 0 => config.position,
+                Some(_) => Position::RightOf,
+            };
+            let primary = !layout.outputs.iter().any(|o| o.primary);
+            layout.outputs.push(OutputLayout {
+                connector: connector.clone(),
+                enabled: true,
+                position,
+                relative_to: reference.clone(),
+                rotation: Rotation::Normal,
+                scale,
+                primary,
+            });
+            reference = Some(connector.clone());
+        }
+        layout
+    }
+
+    /// A copy of the layout with every scale passed through `snap`, for compositors that only
+    /// support some scales.
+    pub fn with_scales<F: Fn(f64) -> f64>(&self, snap: F) -> Layout {
+        let mut layout = self.clone();
+        for output in &mut layout.outputs {
+            output.scale = snap(output.scale);
+        }
+        layout
+    }
+}
+
+/// Round `scale` to the 1/120 steps of `wp_fractional_scale_v1`, which is what Wayland compositors
+/// end up using, so that the positions we compute match the compositor's logical sizes.
+pub fn fractional_scale(scale: f64) -> f64 {
+    let snapped = (scale * 120.0).round() / 120.0;
+    if (snapped - scale).abs() > 1e-6 {
+        warn!("Scale {} is not a multiple of 1/120, using {} instead", scale, snapped);
+    }
+    snapped
+}
+
+/// Compute the top-left corner of every enabled output.
+///
+/// `size_of` returns the size an output occupies in the backend's layout coordinates (i.e. after
+/// rotation and, for logical layouts, scaling). Positions are shifted so the layout starts at 0,0.
+pub fn place<F>(layout: &Layout, size_of: F) -> Result<HashMap<String, (i32, i32)>, String>
+where
+    F: Fn(&OutputLayout) -> Option<(i32, i32)>,
+{
+    let mut sizes = HashMap::new();
+    for output in layout.enabled_outputs() {
+        let size = size_of(output).ok_or_else(|| format!("unknown size for {}", output.connector))?;
+        sizes.insert(output.connector.clone(), size);
+    }
+
+    let mut positions: HashMap<String, (i32, i32)> = HashMap::new();
+    // Anchors first, then everything placed relative to an already placed output.
+    for output in layout.enabled_outputs().filter(|o| o.relative_to.is_none()) {
+        positions.insert(output.connector.clone(), (0, 0));
+    }
+    for _ in 0..layout.outputs.len() {
+        for output in layout.enabled_outputs() {
+            if positions.contains_key(&output.connector) {
+                continue;
+            }
+            let Some(reference) = output.relative_to.as_ref() else { continue };
+            let (Some(&(rx, ry)), Some(&(rw, rh))) = (positions.get(reference), sizes.get(reference)) else {
+                continue;
+            };
+            let (w, h) = sizes[&output.connector];
+            let position = match output.position {
+                Position::Origin | Position::Mirror => (rx, ry),
+                Position::Below => (rx, ry + rh),
+                Position::Above => (rx, ry - h),
+                Position::LeftOf => (rx - w, ry),
+                Position::RightOf => (rx + rw, ry),
+            };
+            positions.insert(output.connector.clone(), position);
+        }
+    }
+
+    if let Some(unplaced) = layout.enabled_outputs().find(|o| !positions.contains_key(&o.connector)) {
+        return Err(format!("cannot place {} relative to {:?}", unplaced.connector, unplaced.relative_to));
+    }
+
+    let min_x = positions.values().map(|p| p.0).min().unwrap_or(0);
+    let min_y = positions.values().map(|p| p.1).min().unwrap_or(0);
+    for position in positions.values_mut() {
+        position.0 -= min_x;
+        position.1 -= min_y;
+    }
+    Ok(positions)
+}
+
+/// Where the placed layout has to start so that the outputs in `layout.keep` can stay where they
+/// are: the current position of its anchor panel, as `current` reports it for outputs that are on.
+/// `(0, 0)` while none of the kept outputs is on, or the anchor is off.
+pub fn keep_offset(layout: &Layout, current: impl Fn(&str) -> Option<(i32, i32)>) -> (i32, i32) {
+    if !layout.keep.iter().any(|connector| current(connector).is_some()) {
+        return (0, 0);
+    }
+    layout.enabled_outputs()
+        .find(|o| o.relative_to.is_none())
+        .and_then(|anchor| current(&anchor.connector))
+        .unwrap_or_default()
+}
+
+/// Move every position placed by [`place`] by `offset`, see [`keep_offset`].
+pub fn shift(positions: &mut HashMap<String, (i32, i32)>, (dx, dy): (i32, i32)) {
+    for position in positions.values_mut() {
+        position.0 += dx;
+        position.1 += dy;
+    }
+}
+
+/// Size of a `width`×`height` mode in logical layout coordinates.
+pub fn logical_size(width: i32, height: i32, rotation: Rotation, scale: f64) -> Option<(i32, i32)> {
+    if scale <= 0.0 {
+        return None;
+    }
+    let (w, h) = if rotation.swaps_axes() { (height, width) } else { (width, height) };
+    Some(((w as f64 / scale).round() as i32, (h as f64 / scale).round() as i32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn panels() -> Panels {
+        Panels::default()
+    }
+
+    #[test]
+    fn all_builtins_resolve() {
+        for name in BUILTIN_LAYOUTS {
+            let layout = Layout::resolve(name, &LayoutConfig::default(), &panels(), (1.5, 1.5)).expect(name);
+            assert_eq!(layout.outputs.iter().filter(|o| o.primary).count(), 1, "{}", name);
+        }
+        assert!(Layout::resolve("nope", &LayoutConfig::default(), &panels(), (1.5, 1.5)).is_none());
+    }
+
+    #[test]
+    fn bottom_only_makes_bottom_primary_at_origin() {
+        let layout = Layout::resolve("bottom-only", &LayoutConfig::default(), &panels(), (1.0, 1.0)).unwrap();
+        let bottom = layout.output("eDP-2").unwrap();
+        assert!(bottom.primary);
+        assert_eq!(bottom.relative_to, None);
+        assert!(!layout.output("eDP-1").unwrap().enabled);
+    }
+
+    #[test]
+    fn custom_layouts_win_and_override_scale() {
+        let mut config = LayoutConfig::default();
+        config.custom.insert("below".to_string(), LayoutDefinition {
+            top: PanelLayout { scale: Some(1.25), ..PanelLayout::on(Position::Origin, Rotation::Normal) },
+            bottom: PanelLayout::on(Position::Above, Rotation::Inverted),
+        });
+
+        let layout = Layout::resolve("below", &config, &panels(), (1.5, 1.5)).unwrap();
+        assert_eq!(layout.output("eDP-1").unwrap().scale, 1.25);
+        let bottom = layout.output("eDP-2").unwrap();
+        assert_eq!(bottom.position, Position::Above);
+        assert_eq!(bottom.rotation, Rotation::Inverted);
+        assert_eq!(bottom.scale, 1.5);
+    }
+
+    #[test]
+    fn places_below_and_side_by_side() {
+        let size = |o: &OutputLayout| logical_size(2880, 1800, o.rotation, o.scale);
+
+        let below = Layout::resolve("below", &LayoutConfig::default(), &panels(), (1.5, 1.5)).unwrap();
+        let positions = place(&below, size).unwrap();
+        assert_eq!(positions["eDP-1"], (0, 0));
+        assert_eq!(positions["eDP-2"], (0, 1200));
+
+        let book = Layout::resolve("side-by-side", &LayoutConfig::default(), &panels(), (1.5, 1.5)).unwrap();
+        let positions = place(&book, size).unwrap();
+        assert_eq!(positions["eDP-1"], (0, 0));
+        assert_eq!(positions["eDP-2"], (1200, 0));
+    }
+
+    #[test]
+    fn normalizes_negative_positions() {
+        let mut config = LayoutConfig::default();
+        config.custom.insert("above".to_string(), LayoutDefinition {
+            top: PanelLayout::on(Position::Origin, Rotation::Normal),
+            bottom: PanelLayout::on(Position::Above, Rotation::Normal),
+        });
+        let layout = Layout::resolve("above", &config, &panels(), (2.0, 2.0)).unwrap();
+        let positions = place(&layout, |o| logical_size(2880, 1800, o.rotation, o.scale)).unwrap();
+        assert_eq!(positions["eDP-2"], (0, 0));
+        assert_eq!(positions["eDP-1"], (0, 900));
+    }
+
+    #[test]
+    fn per_panel_scales_and_invalid_overrides() {
+        let mut config = LayoutConfig::default();
+        config.custom.insert("broken".to_string(), LayoutDefinition {
+            top: PanelLayout { scale: Some(0.0), ..PanelLayout::on(Position::Origin, Rotation::Normal) },
+            bottom: PanelLayout::on(Position::Below, Rotation::Normal),
+        });
+        let layout = Layout::resolve("broken", &config, &panels(), (1.5, 1.25)).unwrap();
+        assert_eq!(layout.outputs[0].scale, 1.5);
+        assert_eq!(layout.outputs[1].scale, 1.25);
+
+        let positions = place(&layout, |o| logical_size(2880, 1800, o.rotation, o.scale)).unwrap();
+        assert_eq!(positions["eDP-2"], (0, 1200));
+    }
+
+    #[test]
+    fn fractional_scale_snaps_to_120ths() {
+        assert_eq!(fractional_scale(1.5), 1.5);
+        assert_eq!(fractional_scale(1.25), 1.25);
+        assert_eq!(fractional_scale(1.333), 160.0 / 120.0);
+        let layout = Layout::resolve("below", &LayoutConfig::default(), &panels(), (1.333, 1.25)).unwrap();
+        assert_eq!(layout.with_scales(fractional_scale).outputs[0].scale, 160.0 / 120.0);
+    }
+
+    #[test]
+    fn orientation_turns_layout_as_one_unit() {
+        let below = builtin("below").unwrap();
+        assert_eq!(below.oriented(Rotation::Normal), below);
+
+        let upside_down = below.oriented(Rotation::Inverted);
+        assert_eq!(upside_down.top.rotation, Rotation::Inverted);
+        assert_eq!(upside_down.bottom.rotation, Rotation::Inverted);
+        assert_eq!(upside_down.bottom.position, Position::Above);
+
+        // Left edge up: the top panel ends up on the right.
+        let left_up = below.oriented(Rotation::Left);
+        assert_eq!(left_up.bottom.rotation, Rotation::Left);
+        assert_eq!(left_up.bottom.position, Position::LeftOf);
+
+        assert_eq!(below.oriented(Rotation::Right), builtin("side-by-side").unwrap());
+        assert_eq!(builtin("side-by-side").unwrap().oriented(Rotation::Normal), below);
+
+        let mirror = builtin("mirror").unwrap().oriented(Rotation::Left);
+        assert_eq!(mirror.bottom.position, Position::Mirror);
+    }
+
+    #[test]
+    fn oriented_layouts_place_consistently() {
+        let size = |o: &OutputLayout| logical_size(2880, 1800, o.rotation, o.scale);
+        let left_up = builtin("below").unwrap().oriented(Rotation::Left);
+        let layout = Layout::from_definition("below", &left_up, &panels(), (1.5, 1.5));
+        let positions = place(&layout, size).unwrap();
+        assert_eq!(positions["eDP-2"], (0, 0));
+        assert_eq!(positions["eDP-1"], (1200, 0));
+        assert!(layout.outputs.iter().all(|o| o.rotation == Rotation::Left));
+        assert_eq!(size(&layout.outputs[0]), Some((1200, 1920)));
+    }
+
+    #[test]
+    fn place_reports_missing_sizes() {
+        let layout = Layout::resolve("below", &LayoutConfig::default(), &panels(), (1.5, 1.5)).unwrap();
+        assert!(place(&layout, |_| None).is_err());
+    }
+
+    fn externals() -> Vec<String> {
+        vec!["DP-1".to_string(), "HDMI-A-1".to_string()]
+    }
+
+    #[test]
+    fn keep_policy_only_records_externals() {
+        let layout = Layout::resolve("top-only", &LayoutConfig::default(), &panels(), (1.5, 1.5)).unwrap();
+        let kept = layout.with_externals(&externals(), &ExternalConfig::default());
+        assert_eq!(kept.outputs, layout.outputs);
+        assert_eq!(kept.keep, externals());
+        assert_eq!(layout.with_externals(&[], &ExternalConfig::default()), layout);
+    }
+
+    #[test]
+    fn relative_policy_places_externals_next_to_primary_panel() {
+        let config = ExternalConfig { policy: ExternalPolicy::Relative, scale: Some(1.25), ..ExternalConfig::default() };
+        let layout = Layout::resolve("bottom-only", &LayoutConfig::default(), &panels(), (1.5, 1.5))
+            .unwrap()
+            .with_externals(&externals(), &config);
+
+        let first = layout.output("DP-1").unwrap();
+        assert_eq!((first.position, first.relative_to.as_deref(), first.scale, first.primary), (Position::Above, Some("eDP-2"), 1.25, false));
+        let second = layout.output("HDMI-A-1").unwrap();
+        assert_eq!((second.position, second.relative_to.as_deref()), (Position::RightOf, Some("DP-1")));
+
+        let size = |o: &OutputLayout| if o.connector.starts_with("eDP") { Some((1920, 1200)) } else { Some((2560, 1440)) };
+        let positions = place(&layout, size).unwrap();
+        assert_eq!(positions["DP-1"], (0, 0));
+        assert_eq!(positions["HDMI-A-1"], (2560, 0));
+        assert_eq!(positions["eDP-2"], (0, 1440));
+    }
+
+    #[test]
+    fn internal_off_policy_switches_panels_off() {
+        let config = ExternalConfig { policy: ExternalPolicy::InternalOff, ..ExternalConfig::default() };
+        let layout = Layout::resolve("below", &LayoutConfig::default(), &panels(), (1.5, 1.5))
+            .unwrap()
+            .with_externals(&externals(), &config);
+
+        let enabled: Vec<&str> = layout.enabled_outputs().map(|o| o.connector.as_str()).collect();
+        assert_eq!(enabled, vec!["DP-1", "HDMI-A-1"]);
+        let first = layout.output("DP-1").unwrap();
+        assert_eq!((first.position, first.relative_to.as_deref(), first.scale, first.primary), (Position::Origin, None, 1.0, true));
+        assert_eq!(layout.output("HDMI-A-1").unwrap().relative_to.as_deref(), Some("DP-1"));
+        assert!(layout.output("eDP-2").unwrap().relative_to.is_none());
+    }
+}
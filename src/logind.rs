@@ -0,0 +1,191 @@
+use futures::StreamExt;
+use log::{debug, error, info, warn};
+use tokio::sync::watch;
+use zbus::zvariant::OwnedObjectPath;
+use zbus::{proxy, Connection};
+
+#[proxy(
+    interface = "org.freedesktop.login1.Seat",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1/seat/seat0"
+)]
+trait Seat {
+    #[zbus(property)]
+    fn active_session(&self) -> zbus::Result<(String, OwnedObjectPath)>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.login1.Session",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1/session/auto"
+)]
+pub trait Session {
+    fn set_brightness(&self, subsystem: &str, name: &str, brightness: u32) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn user(&self) -> zbus::Result<(u32, OwnedObjectPath)>;
+
+    #[zbus(property, name = "Type")]
+    fn session_type(&self) -> zbus::Result<String>;
+}
+
+/// The user of the session at `path` if it is a graphical one; `/` stands for no session.
+async fn graphical_user(connection: &Connection, path: OwnedObjectPath) -> zbus::Result<Option<u32>> {
+    if path.as_str() == "/" {
+        return Ok(None);
+    }
+    let session = SessionProxy::builder(connection).path(path)?.build().await?;
+    let session_type = session.session_type().await?;
+    if session_type != "wayland" && session_type != "x11" {
+        debug!("Active session is a {} session", session_type);
+        return Ok(None);
+    }
+    Ok(Some(session.user().await?.0))
+}
+
+/// Publish the user of seat0's active graphical session on `users` until logind goes away,
+/// following switches between sessions. A text console or the greeter counts as no user.
+pub async fn watch_active_user(connection: &Connection, users: &watch::Sender<Option<u32>>) -> zbus::Result<()> {
+    let seat = SeatProxy::new(connection).await?;
+    let mut changes = seat.receive_active_session_changed().await;
+
+    // Without logind the first read fails; later failures only lose track of one switch.
+    let mut active = Ok(seat.active_session().await?);
+    loop {
+        let user = match active {
+            Ok((_, path)) => graphical_user(connection, path).await.unwrap_or_else(|e| {
+                warn!("Failed to look up the active session: {}", e);
+                None
+            }),
+            Err(e) => {
+                warn!("Failed to read the active session: {}", e);
+                None
+            }
+        };
+        if users.send_if_modified(|active| std::mem::replace(active, user) != user) {
+            match user {
+                Some(uid) => info!("Active graphical session belongs to user {}", uid),
+                None => info!("No graphical session is active"),
+            }
+        }
+
+        let Some(change) = changes.next().await else { return Ok(()) };
+        active = change.get().await;
+    }
+}
+
+/// Follow the active graphical session through logind on the system bus, see [`watch_active_user`].
+///
+/// Does nothing but log if logind is not available. `users` is kept open either way, so its
+/// receivers keep waiting for a session rather than seeing it closed.
+pub async fn monitor_active_user(users: watch::Sender<Option<u32>>) {
+    match Connection::system().await {
+        Ok(connection) => {
+            if let Err(e) = watch_active_user(&connection, &users).await {
+                error!("Not following sessions: {}", e);
+            }
+        }
+        Err(e) => error!("Failed to connect to the system bus, not following sessions: {}", e),
+    }
+    std::future::pending::<()>().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use zbus::object_server::SignalEmitter;
+    use crate::test_bus::test_bus_or_skip;
+
+    struct FakeSeat {
+        active: (String, OwnedObjectPath),
+    }
+
+    #[zbus::interface(name = "org.freedesktop.login1.Seat")]
+    impl FakeSeat {
+        #[zbus(property)]
+        fn active_session(&self) -> (String, OwnedObjectPath) {
+            self.active.clone()
+        }
+
+        /// Stand-in for a session switch, which logind does not offer as a seat method.
+        async fn switch(&mut self, id: String, path: OwnedObjectPath, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) {
+            self.active = (id, path);
+            let _ = self.active_session_changed(&emitter).await;
+        }
+    }
+
+    struct FakeSession {
+        uid: u32,
+        session_type: &'static str,
+    }
+
+    #[zbus::interface(name = "org.freedesktop.login1.Session")]
+    impl FakeSession {
+        #[zbus(property)]
+        fn user(&self) -> (u32, OwnedObjectPath) {
+            (self.uid, OwnedObjectPath::try_from(format!("/org/freedesktop/login1/user/_{}", self.uid)).unwrap())
+        }
+
+        #[zbus(property, name = "Type")]
+        fn session_type(&self) -> String {
+            self.session_type.to_string()
+        }
+    }
+
+    fn path(path: &str) -> OwnedObjectPath {
+        OwnedObjectPath::try_from(path).unwrap()
+    }
+
+    async fn next(users: &mut watch::Receiver<Option<u32>>) -> Option<u32> {
+        tokio::time::timeout(Duration::from_secs(5), users.changed()).await.expect("timeout").expect("closed");
+        *users.borrow_and_update()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn follows_session_switches() {
+        let bus = test_bus_or_skip!();
+        let seat = FakeSeat { active: ("2".to_string(), path("/org/freedesktop/login1/session/_32")) };
+        let _service = zbus::blocking::connection::Builder::address(bus.address()).unwrap()
+            .name("org.freedesktop.login1").unwrap()
+            .serve_at("/org/freedesktop/login1/seat/seat0", seat).unwrap()
+            .serve_at("/org/freedesktop/login1/session/_32", FakeSession { uid: 1000, session_type: "wayland" }).unwrap()
+            .serve_at("/org/freedesktop/login1/session/_33", FakeSession { uid: 1001, session_type: "x11" }).unwrap()
+            .serve_at("/org/freedesktop/login1/session/_34", FakeSession { uid: 1000, session_type: "tty" }).unwrap()
+            .build()
+            .unwrap();
+
+        let (sender, mut users) = watch::channel(None);
+        let connection: Connection = bus.connect().into();
+        let watcher = tokio::spawn(async move { watch_active_user(&connection, &sender).await });
+        assert_eq!(next(&mut users).await, Some(1000));
+
+        let client = bus.connect();
+        let switch = |id: &str, to: &str| {
+            client.call_method(
+                Some("org.freedesktop.login1"),
+                "/org/freedesktop/login1/seat/seat0",
+                Some("org.freedesktop.login1.Seat"),
+                "Switch",
+                &(id, path(to)),
+            ).expect("switch session");
+        };
+        switch("3", "/org/freedesktop/login1/session/_33");
+        assert_eq!(next(&mut users).await, Some(1001));
+        switch("4", "/org/freedesktop/login1/session/_34");
+        assert_eq!(next(&mut users).await, None);
+        switch("", "/");
+        switch("2", "/org/freedesktop/login1/session/_32");
+        assert_eq!(next(&mut users).await, Some(1000));
+
+        watcher.abort();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn missing_logind_is_an_error() {
+        let bus = test_bus_or_skip!();
+        let (sender, _users) = watch::channel(None);
+        let connection: Connection = bus.connect().into();
+        assert!(watch_active_user(&connection, &sender).await.is_err());
+    }
+}
@@ -1,14 +1,27 @@
+mod agent;
+mod bluez;
+mod brightness;
+mod cli;
 mod config;
+mod control;
+mod daemon;
+mod dbus_service;
+mod drm;
 mod install;
+mod layout;
+mod logind;
 mod monitor_handling;
+mod orientation;
+#[cfg(test)]
+mod test_bus;
+mod touch;
 mod udev_utils;
 mod usb;
 
-use crate::config::load_config;
+use crate::config::{load_config, load_system_config};
 use std::env;
-use futures::{StreamExt, stream::FuturesUnordered};
 use tokio::task::LocalSet;
-use log::{info, error, LevelFilter};
+use log::LevelFilter;
 
 #[tokio::main]
 async fn main() {
@@ -24,7 +37,29 @@ async fn main() {
         return;
     }
 
-    let mut config = load_config();
+    if args.len() > 1 && args[1] == "--status" {
+        cli::status();
+        return;
+    }
+
+    if args.len() > 1 && args[1] == "--apply-layout" {
+        cli::apply_layout(&args).await;
+        return;
+    }
+
+    if args.len() > 1 && args[1] == "--reload" {
+        cli::reload();
+        return;
+    }
+
+    if args.len() > 1 && args[1] == "--watch" {
+        cli::watch();
+        return;
+    }
+
+    // The system daemon has no user to take the config from.
+    let daemon = args.iter().any(|arg| arg == "--daemon");
+    let mut config = if daemon { load_system_config() } else { load_config() };
     
     // Check for verbose flag in args
     if args.iter().any(|arg| arg == "--verbose" || arg == "-v") {
@@ -42,36 +77,12 @@ async fn main() {
         .filter_level(log_level)
         .init();
 
-    let (current_state, _keyboard_devpath) = usb::check_initial_state(&config);
-    if current_state == Some(usb::DeviceState::Added) {
-        if let Err(e) = usb::backlight::set_backlight_level(config.brightness as u8, &config) {
-            error!("Failed to set initial backlight level: {:?}. (Are you running as root or have udev rules set up?)", e);
-        }
-    } else {
-        info!("Keyboard not detected at startup, skipping initial backlight setup.");
-    }
-
     // LocalSet allows us to spawn !Send futures (like the udev monitor) on the current thread
     let local = LocalSet::new();
 
-    local
-        .run_until(async move {
-            let watchers = FuturesUnordered::new();
-
-            // 1. USB Connection Watcher (Display handling)
-            watchers.push(tokio::task::spawn_local(async move {
-                usb::monitor_usb_events().await;
-            }));
-
-            // 2. Keyboard Key Watcher (Backlight F4 handling)
-            let config_keys = config.clone();
-            watchers.push(tokio::task::spawn_local(async move {
-                usb::monitor_special_keys(config_keys).await;
-            }));
-
-            info!("Monitoring started (USB events & Special keys)...");
-
-            watchers.for_each(|_| async {}).await;
-        })
-        .await;
+    if daemon {
+        local.run_until(daemon::run(config)).await;
+    } else {
+        local.run_until(agent::run(config)).await;
+    }
 }
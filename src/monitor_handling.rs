@@ -1,146 +1,181 @@
-use std::thread;
+mod kscreen;
+mod mutter;
+mod snapshot;
+mod wlroots;
+mod x11;
+
+use std::fmt;
+use std::path::Path;
 use std::process::{Command, Stdio};
+use std::sync::Arc;
 use std::time::Duration;
-use crate::config::Config;
-use crate::usb::backlight::set_backlight_level;
+use lazy_static::lazy_static;
+use tokio::sync::watch;
+use crate::brightness;
+use crate::config::{BacklightOff, Config};
+use crate::drm;
+use crate::layout::{self, Layout, Rotation, BUILTIN_LAYOUTS};
+use crate::touch::{self, TouchMapping};
 use crate::usb::DeviceState;
-use log::{info, debug, error};
-
-trait DisplayManager {
-    fn set_single_monitor(&self, scale: &str);
-    fn set_dual_monitor(&self, scale: &str);
-}
-
-struct GnomeManager;
-
-impl DisplayManager for GnomeManager {
-    /// Configure eDP-1 as the primary monitor using the provided scale.
-    ///
-    /// This method applies the given scale to the eDP-1 display and attempts to set it as the primary monitor.
-    /// Failures to apply the configuration are logged.
-    ///
-    /// # Parameters
-    ///
-    /// - `scale`: The scale factor to apply to eDP-1 (for example, `"1.00"` or `"1.25"`).
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// // Assuming `mgr` implements this method (e.g., GnomeManager or KdeManager).
-    /// // let mgr = GnomeManager::new();
-    /// // mgr.set_single_monitor("1.00");
-    /// ```
-    fn set_single_monitor(&self, scale: &str) {
-        let base_args = vec![
-            "set", "--logical-monitor", "--primary", "--scale", scale, "--monitor", "eDP-1"
-        ];
-        debug!("Executing 'gdctl {}'", base_args.join(" "));
-        
-        match Command::new("gdctl")
-            .args(&base_args)
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .status()
-        {
-            Ok(status) if !status.success() => {
-                error!("gdctl exited with status: {}", status);
-            }
-            Err(e) => {
-                error!("Failed to execute gdctl: {}", e);
-            }
-            _ => {}
+use log::{debug, info, error, warn};
+use self::kscreen::KScreenManager;
+use self::mutter::MutterManager;
+use self::snapshot::DisplaySnapshot;
+use self::wlroots::WlrootsManager;
+use self::x11::X11Manager;
+
+pub(crate) use self::wlroots::WLROOTS_DESKTOPS;
+
+lazy_static! {
+    /// The layout last applied by this process; `None` before the first one and after the user's
+    /// own arrangement was restored.
+    static ref CURRENT_LAYOUT: watch::Sender<Option<String>> = watch::Sender::new(None);
+}
+
+/// The layout last applied, following every change, see `CURRENT_LAYOUT`.
+pub fn current_layout() -> watch::Receiver<Option<String>> {
+    CURRENT_LAYOUT.subscribe()
+}
+
+/// Why a display layout could not be applied.
+#[derive(Debug)]
+pub enum DisplayError {
+    /// A helper program could not be started or exited unsuccessfully.
+    Command(String),
+    /// Talking to the compositor over D-Bus failed.
+    DBus(zbus::Error),
+    /// The compositor does not know the connector we tried to configure.
+    ConnectorNotFound(String),
+    /// The compositor reports no mode we could use for the connector.
+    NoUsableMode(String),
+    /// The configured scale is not a number.
+    InvalidScale(String),
+    /// The compositor did not end up with the layout we asked for.
+    Rejected(String),
+}
+
+impl fmt::Display for DisplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisplayError::Command(msg) => write!(f, "{}", msg),
+            DisplayError::DBus(e) => write!(f, "D-Bus error: {}", e),
+            DisplayError::ConnectorNotFound(c) => write!(f, "connector {} not found", c),
+            DisplayError::NoUsableMode(c) => write!(f, "no usable mode for connector {}", c),
+            DisplayError::InvalidScale(s) => write!(f, "invalid scale '{}'", s),
+            DisplayError::Rejected(msg) => write!(f, "layout was not applied: {}", msg),
         }
     }
+}
 
-    /// Configure a dual-monitor layout by applying `scale` to both displays and placing `eDP-2` below `eDP-1`.
-    ///
-    /// This will invoke the `gdctl` utility to make `eDP-1` the primary monitor and position `eDP-2` beneath it, using the provided scale value for both monitors. Errors encountered while launching `gdctl` are logged.
-    ///
-    /// # Examples
-    ///
-    /// ```no_run
-    /// let manager = GnomeManager;
-    /// manager.set_dual_monitor("1.25");
-    /// ```
-    fn set_dual_monitor(&self, scale: &str) {
-        let dual_args = vec![
-            "set", "--logical-monitor", "--primary", "--scale", scale, "--monitor", "eDP-1",
-            "--logical-monitor", "--scale", scale, "--monitor", "eDP-2", "--below", "eDP-1"
-        ];
-        debug!("Executing 'gdctl {}'", dual_args.join(" "));
-        
-        if let Err(e) = Command::new("gdctl")
-            .args(&dual_args)
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .status() 
-        {
-            error!("Failed to execute gdctl: {}", e);
-        }
+impl From<zbus::Error> for DisplayError {
+    fn from(e: zbus::Error) -> Self {
+        DisplayError::DBus(e)
+    }
+}
+
+impl From<zbus::fdo::Error> for DisplayError {
+    fn from(e: zbus::fdo::Error) -> Self {
+        DisplayError::DBus(e.into())
     }
 }
 
-struct KdeManager;
-
-impl DisplayManager for KdeManager {
-    /// Configure KDE to use a single-monitor layout by scaling `eDP-1` and disabling `eDP-2`.
-    ///
-    /// The function invokes `kscreen-doctor` with arguments to set the scale for `eDP-1` and
-    /// disable `eDP-2`. Errors from launching the command are logged but not returned.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// // Create a KdeManager and set a scale of 1.25 for the internal display.
-    /// let mgr = KdeManager { /* fields if any */ };
-    /// mgr.set_single_monitor("1.25");
-    /// ```
-    fn set_single_monitor(&self, scale: &str) {
-        let args = vec![
-            format!("output.eDP-1.scale.{}", scale),
-            "output.eDP-2.disable".to_string(),
-        ];
-        debug!("Executing 'kscreen-doctor {}'", args.join(" "));
-        
-        if let Err(e) = Command::new("kscreen-doctor")
-            .args(&args)
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .status() 
-        {
-            error!("Failed to execute kscreen-doctor: {}", e);
+/// A compositor's display configuration. Its calls block, see [`blocking`].
+pub(crate) trait DisplayManager: Send + Sync {
+    /// Enable, position, rotate and scale the outputs as described by `layout`, leaving the outputs
+    /// in `layout.keep` as they are.
+    fn apply_layout(&self, layout: &Layout) -> Result<(), DisplayError>;
+
+    /// Read the outputs back and check that every output of `layout` is on or off as it should be.
+    fn verify_layout(&self, layout: &Layout) -> Result<(), DisplayError> {
+        let snapshot = self.snapshot()?;
+        for o in &layout.outputs {
+            let state = snapshot.output(&o.connector)
+                .ok_or_else(|| DisplayError::ConnectorNotFound(o.connector.clone()))?;
+            check_enabled(&o.connector, o.enabled, state.enabled)?;
         }
+        Ok(())
+    }
+
+    /// Map every digitizer in `mappings` to its panel's output.
+    fn map_touchscreens(&self, mappings: &[TouchMapping]) -> Result<(), DisplayError>;
+
+    /// Capture the configuration of every connected output.
+    fn snapshot(&self) -> Result<DisplaySnapshot, DisplayError>;
+
+    /// Bring the outputs back to a configuration captured by `snapshot`.
+    fn restore(&self, snapshot: &DisplaySnapshot) -> Result<(), DisplayError>;
+}
+
+/// Fail with `DisplayError::Rejected` unless `connector` ended up `enabled` as `expected`.
+fn check_enabled(connector: &str, expected: bool, enabled: bool) -> Result<(), DisplayError> {
+    if enabled == expected {
+        Ok(())
+    } else {
+        Err(DisplayError::Rejected(format!(
+            "{} is {} after applying the layout",
+            connector,
+            if expected { "still disabled" } else { "still enabled" }
+        )))
     }
+}
 
-    /// Configure KDE to use a dual-monitor layout and apply the given scale to both displays.
-    ///
-    /// The `scale` string is passed directly to `kscreen-doctor` (for example `"1.0"` or `"1.25"`).
-    ///
-    /// # Examples
-    ///
-    /// ```no_run
-    /// let mgr = KdeManager {};
-    /// mgr.set_dual_monitor("1.0");
-    /// ```
-    fn set_dual_monitor(&self, scale: &str) {
-        let args = vec![
-            format!("output.eDP-1.scale.{}", scale),
-            "output.eDP-2.enable".to_string(),
-            format!("output.eDP-2.scale.{}", scale),
-        ];
-        debug!("Executing 'kscreen-doctor {}'", args.join(" "));
-        
-        if let Err(e) = Command::new("kscreen-doctor")
-            .args(&args)
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .status() 
-        {
-            error!("Failed to execute kscreen-doctor: {}", e);
+/// How often a display configuration is tried before giving up.
+const APPLY_ATTEMPTS: u32 = 5;
+/// Wait before the first retry; doubled for every further one.
+const APPLY_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Run `attempt` until it succeeds, retrying with exponential backoff starting at `backoff`.
+///
+/// Compositors reject configurations for a while right after login or resume, and connectors can
+/// be missing until they have been probed again, so every error but an invalid scale is retried.
+async fn with_retries<F, Fut>(what: &str, backoff: Duration, attempt: F) -> Result<(), DisplayError>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<(), DisplayError>>,
+{
+    let mut delay = backoff;
+    let mut tries = 1;
+    loop {
+        match attempt().await {
+            Ok(()) => return Ok(()),
+            Err(e) if tries < APPLY_ATTEMPTS && !matches!(e, DisplayError::InvalidScale(_)) => {
+                warn!("Failed to {} (attempt {}/{}): {}, retrying in {:?}", what, tries, APPLY_ATTEMPTS, e, delay);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                tries += 1;
+            }
+            Err(e) => return Err(e),
         }
     }
 }
 
+/// Run `call` on `manager` on the blocking thread pool, as the compositors are talked to through
+/// blocking D-Bus proxies and helper programs that would otherwise hold up every other task.
+async fn blocking<T, F>(manager: &Arc<dyn DisplayManager>, call: F) -> Result<T, DisplayError>
+where
+    T: Send + 'static,
+    F: FnOnce(&dyn DisplayManager) -> Result<T, DisplayError> + Send + 'static,
+{
+    let manager = Arc::clone(manager);
+    tokio::task::spawn_blocking(move || call(manager.as_ref()))
+        .await
+        .unwrap_or_else(|e| Err(DisplayError::Command(format!("Display configuration task failed: {}", e))))
+}
+
+/// Run `program` with `args`, turning spawn failures and unsuccessful exits into a `DisplayError`.
+fn run_command<S: AsRef<std::ffi::OsStr>>(program: &str, args: &[S]) -> Result<(), DisplayError> {
+    match Command::new(program)
+        .args(args)
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status()
+    {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(DisplayError::Command(format!("{} exited with status: {}", program, status))),
+        Err(e) => Err(DisplayError::Command(format!("Failed to execute {}: {}", program, e))),
+    }
+}
+
 /// Selects a display manager implementation appropriate for the current desktop environment.
 ///
 /// # Examples
@@ -151,66 +186,337 @@ impl DisplayManager for KdeManager {
 ///
 /// # Returns
 ///
-/// A `Box<dyn DisplayManager>` containing a `KdeManager` if the `XDG_CURRENT_DESKTOP` environment
-/// variable contains "KDE" (case-insensitive); otherwise a `GnomeManager`.
-fn get_display_manager() -> Box<dyn DisplayManager> {
-    if std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default().to_uppercase().contains("KDE") {
-        Box::new(KdeManager)
+/// An `Arc<dyn DisplayManager>` containing:
+/// - an `X11Manager` if `XDG_SESSION_TYPE` is "x11", whatever the desktop,
+/// - a `KScreenManager` if the `XDG_CURRENT_DESKTOP` environment variable contains "KDE"
+///   (case-insensitive),
+/// - a `MutterManager` if it contains "GNOME",
+/// - a `WlrootsManager` if it names a wlroots-based compositor (Sway, Hyprland, river, niri, ...)
+///   or, failing that, if `WAYLAND_DISPLAY` is set,
+/// - otherwise a `MutterManager`.
+pub(crate) fn get_display_manager() -> Arc<dyn DisplayManager> {
+    let desktop = std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default().to_uppercase();
+
+    if std::env::var("XDG_SESSION_TYPE").is_ok_and(|t| t.eq_ignore_ascii_case("x11")) {
+        Arc::new(X11Manager)
+    } else if desktop.contains("KDE") {
+        Arc::new(KScreenManager::new())
+    } else if desktop.contains("GNOME") {
+        Arc::new(MutterManager::new())
+    } else if WLROOTS_DESKTOPS.iter().any(|d| desktop.contains(d))
+        || std::env::var_os("WAYLAND_DISPLAY").is_some()
+    {
+        Arc::new(WlrootsManager)
     } else {
-        Box::new(GnomeManager)
+        Arc::new(MutterManager::new())
     }
 }
 
-/// Adjusts monitor layout and backlight when the Zenbook Duo keyboard state changes.
+/// Resolve the layout called `name`, falling back to the built-in `fallback` if it is unknown.
+///
+/// With an `orientation`, the layout is turned to match how the device is held. Connected external
+/// monitors are handled according to the `[external]` policy.
+fn resolve_layout(
+    name: &str,
+    fallback: &str,
+    attached: bool,
+    orientation: Option<Rotation>,
+    config: &Config,
+    panels: &drm::Panels,
+) -> Layout {
+    let (name, definition) = match layout::definition(name, &config.layouts) {
+        Some(definition) => (name, definition),
+        None => {
+            let mut known: Vec<&str> = config.layouts.custom.keys().map(String::as_str).collect();
+            known.extend(BUILTIN_LAYOUTS);
+            error!("Unknown layout '{}' (known: {}), using '{}' instead", name, known.join(", "), fallback);
+            (fallback, layout::builtin(fallback).expect("fallback layouts are built in"))
+        }
+    };
+    let definition = match orientation {
+        Some(orientation) => definition.oriented(orientation),
+        None => definition,
+    };
+    let externals = drm::discover_externals(Path::new(drm::DRM_SYSFS), panels);
+    Layout::from_definition(name, &definition, panels, config.scale.for_state(attached))
+        .with_externals(&externals, &config.external)
+}
+
+/// Apply the detached layout, turned to `orientation` if that is known.
+async fn apply_detached(orientation: Option<Rotation>, config: &Config, manager: &Arc<dyn DisplayManager>) {
+    let panels = drm::discover_panels(Path::new(drm::DRM_SYSFS), &config.panels);
+    let layout = resolve_layout(&config.layouts.detached, "below", false, orientation, config, &panels);
+    apply(&layout, &panels, config, manager).await;
+}
+
+/// Apply `layout`, then map the touch screens and pens of the enabled panels to them, as
+/// compositors tend to lose that mapping when outputs are switched or rotated.
+///
+/// The layout is read back after applying it and retried with backoff until it sticks. Returns
+/// whether it did.
+async fn apply(layout: &Layout, panels: &drm::Panels, config: &Config, manager: &Arc<dyn DisplayManager>) -> bool {
+    let result = with_retries(&format!("apply layout '{}'", layout.name), APPLY_BACKOFF, || {
+        let layout = layout.clone();
+        blocking(manager, move |manager| {
+            manager.apply_layout(&layout)?;
+            manager.verify_layout(&layout)
+        })
+    }).await;
+    if let Err(e) = result {
+        error!("Failed to apply layout '{}': {}", layout.name, e);
+        return false;
+    }
+    map_touch(panels, config, manager, |connector| layout.enabled_outputs().any(|o| o.connector == connector)).await;
+    CURRENT_LAYOUT.send_replace(Some(layout.name.clone()));
+    true
+}
+
+/// Map the touch screens and pens of the panels for which `enabled` holds.
+async fn map_touch(panels: &drm::Panels, config: &Config, manager: &Arc<dyn DisplayManager>, enabled: impl Fn(&str) -> bool) {
+    let mappings: Vec<TouchMapping> = touch::assign(&touch::discover_digitizers(), panels, &config.touch)
+        .into_iter()
+        .filter(|m| enabled(&m.connector))
+        .collect();
+    if mappings.is_empty() {
+        debug!("No digitizers to map");
+        return;
+    }
+    if let Err(e) = blocking(manager, move |manager| manager.map_touchscreens(&mappings)).await {
+        error!("Failed to map touch screens: {}", e);
+    }
+}
+
+/// Wait until both panels report `connected`, which takes a while after resume, for at most
+/// `output_timeout_ms`.
+async fn wait_for_panels(panels: &drm::Panels, config: &Config) {
+    let timeout = Duration::from_millis(config.output_timeout_ms);
+    let names = [panels.top.as_str(), panels.bottom.as_str()];
+    if !drm::wait_for_connectors(Path::new(drm::DRM_SYSFS), &names, timeout).await {
+        warn!("{} and {} not connected after {:?}, applying the layout anyway", panels.top, panels.bottom, timeout);
+    }
+}
+
+/// Save the current display configuration, to be restored when the keyboard is removed.
+async fn save_snapshot(manager: &Arc<dyn DisplayManager>) {
+    let Some(path) = snapshot::snapshot_path() else { return };
+    let result = blocking(manager, |manager| manager.snapshot()).await
+        .and_then(|s| snapshot::save(&s, &path).map_err(|e| DisplayError::Command(e.to_string())));
+    match result {
+        Ok(()) => info!("Saved display configuration to {:?}", path),
+        Err(e) => error!("Failed to save display configuration: {}", e),
+    }
+}
+
+/// Restore the display configuration saved by `save_snapshot` and forget it.
+///
+/// Returns `false` if there was nothing to restore or it no longer fits, e.g. because a monitor was
+/// plugged in or out since.
+async fn restore_snapshot(panels: &drm::Panels, config: &Config, manager: &Arc<dyn DisplayManager>) -> bool {
+    let Some(path) = snapshot::snapshot_path() else { return false };
+    let Some(saved) = snapshot::load(&path) else { return false };
+    snapshot::remove(&path);
+
+    let result = match blocking(manager, |manager| manager.snapshot()).await {
+        Ok(current) if !saved.same_outputs(&current) => {
+            Err(DisplayError::Rejected("the connected outputs changed since it was saved".to_string()))
+        }
+        Ok(_) => with_retries("restore the display configuration", APPLY_BACKOFF, || {
+            let saved = saved.clone();
+            blocking(manager, move |manager| manager.restore(&saved))
+        }).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        warn!("Not restoring the saved display configuration: {}", e);
+        return false;
+    }
+
+    info!("Restored display configuration from {:?}", path);
+    CURRENT_LAYOUT.send_replace(None);
+    // Digitizers are only left unmapped if the snapshot knows their panel is off.
+    map_touch(panels, config, manager, |connector| saved.output(connector).is_none_or(|o| o.enabled)).await;
+    true
+}
+
+/// Adjusts the monitor layout when the Zenbook Duo keyboard state changes.
 ///
 /// This inspects the transition from `before` to `current` and:
 /// - If the keyboard was added (current = `Some(Added)` and previous is `None` or `Some(Removed)`),
-///   waits 500ms, switches to the single‑monitor layout using `config.scale`, and attempts to set
-///   the backlight to `config.brightness` (errors are logged).
+///   saves the current display configuration (only when coming from `Some(Removed)`, so a snapshot
+///   left from before a restart is kept), waits for the panels, applies the `layouts.attached` layout
+///   (`top-only` by default) and switches the bottom panel's backlight off as `bottom_backlight_off`
+///   asks for.
 /// - If the keyboard was removed (current = `Some(Removed)` and previous is `None` or `Some(Added)`),
-///   waits 500ms and switches to the dual‑monitor layout using `config.scale`.
+///   switches the bottom panel's backlight back on, waits for the panels and restores the saved display
+///   configuration. Without one, if it no longer fits the connected outputs, or while the device is
+///   held rotated, the `layouts.detached` layout (`below` by default) is applied instead.
 /// - Otherwise does nothing.
 ///
+/// Waiting for the panels means waiting for DRM hotplug events until both panel connectors report
+/// `connected`, for at most `output_timeout_ms`; usually they already are and nothing is waited for.
+///
+/// The keyboard backlight and the bottom panel's touch are handled separately, see
+/// `usb::follow_devices`.
+///
+/// The top and bottom panel connectors are discovered from DRM sysfs on every change (see
+/// `drm::discover_panels`), honouring the `[panels]` overrides in `config`. Layout names are looked
+/// up in `[layouts.custom]` first, then among the built-in layouts; an unknown name is logged and
+/// replaced by the default for that keyboard state. The detached layout is turned to match
+/// `orientation` when it is known (see `handle_orientation_change`). After a layout is applied, the
+/// touch screens and pens are mapped to their panels again.
+///
 /// Parameters:
 /// - `current`: the new device state to evaluate.
 /// - `before`: the previous device state to compare against.
-/// - `config`: runtime configuration containing `scale` (used for monitor layout) and `brightness`
-///   (used when the keyboard is added).
+/// - `orientation`: the last rotation reported by the accelerometer, if it is followed.
+/// - `config`: runtime configuration containing `layouts` and `scale` (the per-panel and per-state
+///   scales, used where a layout sets no scale of its own).
+/// - `manager`: the display manager for the running desktop, see `get_display_manager`.
+///
+/// The compositor is talked to on the blocking thread pool and retries wait asynchronously, so other
+/// tasks keep running meanwhile. Failures to apply a layout are logged.
 ///
 /// # Examples
 ///
 /// ```
 /// // Example usage (types and constructors depend on the surrounding crate):
 /// // let cfg = Config { scale: "1.0".into(), brightness: 120, ... };
-/// // handle_if_changed(&Some(DeviceState::Added), &None, &cfg);
+/// // let manager = get_display_manager();
+/// // handle_if_changed(&Some(DeviceState::Added), &None, None, &cfg, &manager).await;
 /// ```
-pub fn handle_if_changed(current: &Option<DeviceState>, before: &Option<DeviceState>, config: &Config) {
-    let scale = config.scale.to_string();
-    let manager = get_display_manager();
-
+pub async fn handle_if_changed(
+    current: &Option<DeviceState>,
+    before: &Option<DeviceState>,
+    orientation: Option<Rotation>,
+    config: &Config,
+    manager: &Arc<dyn DisplayManager>,
+) {
     match (current, before) {
-        // ── Keyboard added → single‑monitor layout ─────────────────────
+        // ── Keyboard added → attached layout ───────────────────────────
         (Some(DeviceState::Added), None) |
         (Some(DeviceState::Added), Some(DeviceState::Removed)) => {
             info!("Zenbook Duo Keyboard detected!");
-            thread::sleep(Duration::from_millis(500));
-
-            manager.set_single_monitor(&scale);
+            if before.is_some() {
+                save_snapshot(manager).await;
+            }
 
-            info!("Setting backlight level to {}", config.brightness);
-            if let Err(e) = set_backlight_level(config.brightness as u8, config) {
-                error!("Failed to set backlight level: {}", e);
+            let panels = drm::discover_panels(Path::new(drm::DRM_SYSFS), &config.panels);
+            wait_for_panels(&panels, config).await;
+            let layout = resolve_layout(&config.layouts.attached, "top-only", true, None, config, &panels);
+            let applied = apply(&layout, &panels, config, manager).await;
+            let bottom_off = !layout.enabled_outputs().any(|o| o.connector == panels.bottom);
+            let power_off = match config.bottom_backlight_off {
+                BacklightOff::Never => false,
+                BacklightOff::Fallback => !applied && bottom_off,
+                BacklightOff::Always => bottom_off,
+            };
+            if power_off {
+                brightness::power_off_bottom(&panels, &config.brightness_sync);
             }
         }
 
-        // ── Keyboard removed → dual‑monitor layout ───────────────────────
+        // ── Keyboard removed → detached layout ─────────────────────────
         (Some(DeviceState::Removed), None) |
         (Some(DeviceState::Removed), Some(DeviceState::Added)) => {
             info!("Zenbook Duo Keyboard removed!");
-            thread::sleep(Duration::from_millis(500));
+            brightness::power_on_bottom();
 
-            manager.set_dual_monitor(&scale);
+            let panels = drm::discover_panels(Path::new(drm::DRM_SYSFS), &config.panels);
+            wait_for_panels(&panels, config).await;
+            let rotated = orientation.is_some_and(|o| o != Rotation::Normal);
+            if rotated {
+                if let Some(path) = snapshot::snapshot_path() {
+                    snapshot::remove(&path);
+                }
+            } else if restore_snapshot(&panels, config, manager).await {
+                return;
+            }
+            apply_detached(orientation, config, manager).await;
         }
         _ => {}
     }
 }
+
+/// Re-apply the detached layout, turned as one unit, when the device is rotated.
+///
+/// Only acts while the keyboard is detached; with the keyboard on the bottom panel the Duo is used
+/// as a laptop and the attached layout stays as it is.
+pub async fn handle_orientation_change(
+    state: &Option<DeviceState>,
+    orientation: Rotation,
+    config: &Config,
+    manager: &Arc<dyn DisplayManager>,
+) {
+    if *state != Some(DeviceState::Removed) {
+        return;
+    }
+    info!("Device rotated ({:?})", orientation);
+    apply_detached(Some(orientation), config, manager).await;
+}
+
+/// Apply the layout called `name` on request, turned to `orientation` while the keyboard is detached.
+///
+/// Returns `false` if there is no such layout or it could not be applied.
+pub async fn apply_layout_named(
+    name: &str,
+    state: &Option<DeviceState>,
+    orientation: Option<Rotation>,
+    config: &Config,
+    manager: &Arc<dyn DisplayManager>,
+) -> bool {
+    if layout::definition(name, &config.layouts).is_none() {
+        error!("Unknown layout '{}'", name);
+        return false;
+    }
+    info!("Applying layout '{}' on request", name);
+    let attached = *state == Some(DeviceState::Added);
+    let orientation = if attached { None } else { orientation };
+    let panels = drm::discover_panels(Path::new(drm::DRM_SYSFS), &config.panels);
+    let layout = resolve_layout(name, name, attached, orientation, config, &panels);
+    apply(&layout, &panels, config, manager).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[tokio::test]
+    async fn retries_until_success() {
+        let calls = Cell::new(0);
+        let result = with_retries("test", Duration::from_millis(1), || {
+            calls.set(calls.get() + 1);
+            std::future::ready(if calls.get() < 3 { Err(DisplayError::Rejected("busy".to_string())) } else { Ok(()) })
+        }).await;
+        assert!(result.is_ok());
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_last_attempt() {
+        let calls = Cell::new(0);
+        let result = with_retries("test", Duration::from_millis(1), || {
+            calls.set(calls.get() + 1);
+            std::future::ready(Err(DisplayError::ConnectorNotFound("eDP-2".to_string())))
+        }).await;
+        assert!(matches!(result, Err(DisplayError::ConnectorNotFound(_))));
+        assert_eq!(calls.get(), APPLY_ATTEMPTS);
+    }
+
+    #[tokio::test]
+    async fn invalid_scale_is_not_retried() {
+        let calls = Cell::new(0);
+        let result = with_retries("test", Duration::from_millis(1), || {
+            calls.set(calls.get() + 1);
+            std::future::ready(Err(DisplayError::InvalidScale("0".to_string())))
+        }).await;
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn enabled_state_is_checked() {
+        assert!(check_enabled("eDP-2", true, true).is_ok());
+        assert!(matches!(check_enabled("eDP-2", false, true), Err(DisplayError::Rejected(_))));
+    }
+}
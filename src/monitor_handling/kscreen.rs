@@ -0,0 +1,669 @@
+use std::collections::HashMap;
+use zbus::blocking::Connection;
+use zbus::proxy;
+use zbus::zvariant::{OwnedValue, Value};
+use log::debug;
+use crate::layout::{self, Layout, Position, Rotation};
+use crate::touch::TouchMapping;
+use super::snapshot::{DisplaySnapshot, OutputState};
+use super::{check_enabled, DisplayError, DisplayManager};
+
+const BACKEND: &str = "kscreen";
+
+/// A KScreen config, output or mode as serialized by `KScreen::ConfigSerializer`.
+type KMap = HashMap<String, OwnedValue>;
+
+#[proxy(
+    interface = "org.kde.kscreen.Backend",
+    default_service = "org.kde.KScreen",
+    default_path = "/backend"
+)]
+trait Backend {
+    #[zbus(name = "getConfig")]
+    fn get_config(&self) -> zbus::Result<KMap>;
+
+    #[zbus(name = "setConfig")]
+    fn set_config(&self, config: KMap) -> zbus::Result<KMap>;
+}
+
+#[proxy(interface = "org.kde.KWin.InputDevice", default_service = "org.kde.KWin")]
+trait InputDevice {
+    #[zbus(property, name = "outputName")]
+    fn output_name(&self) -> zbus::Result<String>;
+
+    #[zbus(property, name = "outputName")]
+    fn set_output_name(&self, name: &str) -> zbus::Result<()>;
+}
+
+/// Display manager for KDE Plasma, talking to the KScreen backend over D-Bus.
+///
+/// The backend forwards the config to KWin's output management protocol and answers with the
+/// configuration that was actually applied, which is checked before reporting success.
+pub(super) struct KScreenManager {
+    connection: Option<Connection>,
+}
+
+impl KScreenManager {
+    pub(super) fn new() -> Self {
+        KScreenManager { connection: None }
+    }
+
+    #[cfg(test)]
+    fn with_connection(connection: Connection) -> Self {
+        KScreenManager { connection: Some(connection) }
+    }
+
+    fn connection(&self) -> Result<Connection, DisplayError> {
+        match &self.connection {
+            Some(connection) => Ok(connection.clone()),
+            None => Ok(Connection::session()?),
+        }
+    }
+
+    fn proxy(&self) -> Result<BackendProxyBlocking<'static>, DisplayError> {
+        Ok(BackendProxyBlocking::new(&self.connection()?)?)
+    }
+
+    fn apply(&self, layout: &Layout) -> Result<(), DisplayError> {
+        let proxy = self.proxy()?;
+        let mut config = proxy.get_config()?;
+        let mut outputs = parse_outputs(&config)?;
+
+        let mut indices = HashMap::new();
+        for output in &layout.outputs {
+            indices.insert(output.connector.as_str(), find_output(&outputs, &output.connector)?);
+        }
+
+        let mut positions = layout::place(layout, |o| {
+            let output = &outputs[indices[o.connector.as_str()]];
+            let (width, height) = mode_size(output, &active_mode_id(output)?)?;
+            layout::logical_size(width as i32, height as i32, o.rotation, o.scale)
+        }).map_err(DisplayError::Rejected)?;
+        // Kept external outputs are passed on unchanged, so the panels are moved to keep the anchor
+        // panel at its current position next to them.
+        layout::shift(&mut positions, layout::keep_offset(layout, |connector| {
+            let output = &outputs[find_output(&outputs, connector).ok()?];
+            get_bool(output, "enabled").unwrap_or(false).then(|| position(output)).flatten()
+        }));
+
+        // The primary output gets priority 1, the others follow in layout order.
+        let mut priority = 2;
+        for o in &layout.outputs {
+            let index = indices[o.connector.as_str()];
+            if !o.enabled {
+                outputs[index].insert("enabled".to_string(), owned(Value::from(false))?);
+                continue;
+            }
+            let replication_source = match (o.position, &o.relative_to) {
+                (Position::Mirror, Some(reference)) => get_number(&outputs[indices[reference.as_str()]], "id")
+                    .ok_or_else(|| DisplayError::ConnectorNotFound(reference.clone()))? as i32,
+                _ => 0,
+            };
+            let output_priority = if o.primary { 1 } else { priority };
+            if !o.primary {
+                priority += 1;
+            }
+            ensure_mode(&mut outputs[index])?;
+            let pos = positions[o.connector.as_str()];
+            configure_output(&mut outputs[index], o.scale, o.rotation, pos, output_priority, replication_source)?;
+        }
+        set_outputs(&mut config, outputs)?;
+
+        debug!("Applying KScreen config for layout '{}' ({:?})", layout.name, positions);
+        let applied = proxy.set_config(config)?;
+
+        let applied_outputs = parse_outputs(&applied)?;
+        for o in &layout.outputs {
+            let output = &applied_outputs[find_output(&applied_outputs, &o.connector)?];
+            check_enabled(&o.connector, o.enabled, get_bool(output, "enabled").unwrap_or(false))?;
+            let expected = positions.get(&o.connector).copied();
+            if o.enabled && position(output) != expected {
+                return Err(DisplayError::Rejected(format!(
+                    "{} ended up at {:?} instead of {:?}", o.connector, position(output), expected
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn restore_snapshot(&self, snapshot: &DisplaySnapshot) -> Result<(), DisplayError> {
+        snapshot.check_backend(BACKEND)?;
+        let proxy = self.proxy()?;
+        let mut config = proxy.get_config()?;
+        let mut outputs = parse_outputs(&config)?;
+
+        let mut priority = 2;
+        for state in &snapshot.outputs {
+            let index = find_output(&outputs, &state.connector)?;
+            if !state.enabled {
+                outputs[index].insert("enabled".to_string(), owned(Value::from(false))?);
+                continue;
+            }
+            let replication_source = match &state.mirror_of {
+                Some(reference) => get_number(&outputs[find_output(&outputs, reference)?], "id")
+                    .ok_or_else(|| DisplayError::ConnectorNotFound(reference.clone()))? as i32,
+                None => 0,
+            };
+            let output_priority = if state.primary { 1 } else { priority };
+            if !state.primary {
+                priority += 1;
+            }
+            let output = &mut outputs[index];
+            let mode = closest_mode_id(output, state)
+                .ok_or_else(|| DisplayError::NoUsableMode(state.connector.clone()))?;
+            output.insert("currentModeId".to_string(), owned(Value::from(mode))?);
+            configure_output(output, state.scale, state.rotation, (state.x, state.y), output_priority, replication_source)?;
+        }
+        set_outputs(&mut config, outputs)?;
+
+        debug!("Restoring KScreen config from snapshot");
+        let applied_outputs = parse_outputs(&proxy.set_config(config)?)?;
+        for state in &snapshot.outputs {
+            let output = &applied_outputs[find_output(&applied_outputs, &state.connector)?];
+            if get_bool(output, "enabled") != Some(state.enabled) {
+                return Err(DisplayError::Rejected(format!("{} was not restored", state.connector)));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DisplayManager for KScreenManager {
+    /// Enable, position, rotate and scale the panels as described by `layout` and disable the
+    /// panels it leaves off.
+    ///
+    /// Panels keep their previous mode if KScreen still knows it, otherwise they get their preferred
+    /// one. Mirrored panels are set up through KScreen's replication source. KWin rounds scales to
+    /// multiples of 1/120, so that is done up front to keep the computed positions in line.
+    fn apply_layout(&self, layout: &Layout) -> Result<(), DisplayError> {
+        self.apply(&layout.with_scales(layout::fractional_scale))
+    }
+
+    /// The config returned by `setConfig` was already checked in `apply_layout`.
+    fn verify_layout(&self, _layout: &Layout) -> Result<(), DisplayError> {
+        Ok(())
+    }
+
+    /// Set the `outputName` of each digitizer's KWin input device, which KWin exports per evdev node.
+    fn map_touchscreens(&self, mappings: &[TouchMapping]) -> Result<(), DisplayError> {
+        let connection = self.connection()?;
+        for mapping in mappings {
+            let path = format!("/org/kde/KWin/InputDevice/{}", mapping.digitizer.sys_name);
+            debug!("Mapping {} to {}", path, mapping.connector);
+            let device = InputDeviceProxyBlocking::builder(&connection).path(path)?.build()?;
+            device.set_output_name(&mapping.connector)?;
+        }
+        Ok(())
+    }
+
+    /// Read every connected output from the KScreen config. Mirrors are taken from the replication
+    /// source, the primary output from the priority (or the `primary` flag before Plasma 5.27).
+    fn snapshot(&self) -> Result<DisplaySnapshot, DisplayError> {
+        let outputs = parse_outputs(&self.proxy()?.get_config()?)?;
+        let states = outputs.iter()
+            .filter(|o| get_bool(o, "connected") != Some(false))
+            .map(|o| output_state(o, &outputs))
+            .collect();
+        Ok(DisplaySnapshot { backend: BACKEND.to_string(), outputs: states })
+    }
+
+    /// Configure every output as recorded, using the mode with the recorded size and the closest
+    /// refresh rate.
+    fn restore(&self, snapshot: &DisplaySnapshot) -> Result<(), DisplayError> {
+        self.restore_snapshot(snapshot)
+    }
+}
+
+fn owned(value: Value<'_>) -> Result<OwnedValue, DisplayError> {
+    Ok(value.try_to_owned().map_err(zbus::Error::from)?)
+}
+
+/// Strip the variant wrapping of `av` entries.
+fn unwrap_variant<'a>(value: &'a Value<'a>) -> &'a Value<'a> {
+    match value {
+        Value::Value(inner) => unwrap_variant(inner),
+        other => other,
+    }
+}
+
+fn get_map(value: &Value<'_>) -> Option<KMap> {
+    unwrap_variant(value).try_clone().ok()?.try_into().ok()
+}
+
+fn get_list(map: &KMap, key: &str) -> Vec<OwnedValue> {
+    map.get(key)
+        .and_then(|v| unwrap_variant(v).try_clone().ok())
+        .and_then(|v| Vec::<OwnedValue>::try_from(v).ok())
+        .unwrap_or_default()
+}
+
+fn get_bool(map: &KMap, key: &str) -> Option<bool> {
+    bool::try_from(unwrap_variant(map.get(key)?)).ok()
+}
+
+fn get_str(map: &KMap, key: &str) -> Option<String> {
+    String::try_from(unwrap_variant(map.get(key)?).try_clone().ok()?).ok()
+}
+
+/// Read a number regardless of the integer/float type Qt chose to marshal it as.
+fn get_number(map: &KMap, key: &str) -> Option<f64> {
+    match unwrap_variant(map.get(key)?) {
+        Value::I32(n) => Some(*n as f64),
+        Value::U32(n) => Some(*n as f64),
+        Value::I64(n) => Some(*n as f64),
+        Value::U64(n) => Some(*n as f64),
+        Value::I16(n) => Some(*n as f64),
+        Value::U16(n) => Some(*n as f64),
+        Value::U8(n) => Some(*n as f64),
+        Value::F64(n) => Some(*n),
+        _ => None,
+    }
+}
+
+fn parse_outputs(config: &KMap) -> Result<Vec<KMap>, DisplayError> {
+    get_list(config, "outputs")
+        .iter()
+        .map(|o| get_map(o).ok_or_else(|| DisplayError::Rejected("malformed output in KScreen config".to_string())))
+        .collect()
+}
+
+fn find_output(outputs: &[KMap], name: &str) -> Result<usize, DisplayError> {
+    outputs.iter()
+        .position(|o| get_str(o, "name").as_deref() == Some(name))
+        .ok_or_else(|| DisplayError::ConnectorNotFound(name.to_string()))
+}
+
+fn position(output: &KMap) -> Option<(i32, i32)> {
+    let pos = get_map(output.get("pos")?)?;
+    Some((get_number(&pos, "x")? as i32, get_number(&pos, "y")? as i32))
+}
+
+fn modes(output: &KMap) -> Vec<KMap> {
+    get_list(output, "modes").iter().filter_map(|m| get_map(m)).collect()
+}
+
+fn size_of(mode: &KMap) -> Option<(f64, f64)> {
+    let size = get_map(mode.get("size")?)?;
+    Some((get_number(&size, "width")?, get_number(&size, "height")?))
+}
+
+fn mode_size(output: &KMap, mode_id: &str) -> Option<(f64, f64)> {
+    modes(output).iter()
+        .find(|m| get_str(m, "id").as_deref() == Some(mode_id))
+        .and_then(size_of)
+}
+
+/// The id of the mode of `output` with the size of `state` and the closest refresh rate.
+fn closest_mode_id(output: &KMap, state: &OutputState) -> Option<String> {
+    let refresh_offset = |m: &KMap| (get_number(m, "refreshRate").unwrap_or_default() - state.refresh).abs();
+    modes(output).into_iter()
+        .filter(|m| size_of(m) == Some((state.width as f64, state.height as f64)))
+        .min_by(|a, b| refresh_offset(a).total_cmp(&refresh_offset(b)))
+        .and_then(|m| get_str(&m, "id"))
+}
+
+/// Describe `output` for a snapshot; `outputs` is needed to name its replication source.
+fn output_state(output: &KMap, outputs: &[KMap]) -> OutputState {
+    let name = get_str(output, "name").unwrap_or_default();
+    let mode = get_str(output, "currentModeId")
+        .and_then(|id| modes(output).into_iter().find(|m| get_str(m, "id") == Some(id.clone())));
+    let (Some(true), Some(mode)) = (get_bool(output, "enabled"), mode) else {
+        return OutputState::disabled(&name);
+    };
+    let (width, height) = size_of(&mode).unwrap_or_default();
+    let (x, y) = position(output).unwrap_or_default();
+    let primary = match get_number(output, "priority") {
+        Some(priority) => priority == 1.0,
+        None => get_bool(output, "primary").unwrap_or(false),
+    };
+    let mirror_of = get_number(output, "replicationSource")
+        .filter(|id| *id != 0.0)
+        .and_then(|id| outputs.iter().find(|o| get_number(o, "id") == Some(id)))
+        .and_then(|o| get_str(o, "name"));
+    OutputState {
+        connector: name,
+        enabled: true,
+        x,
+        y,
+        width: width as i32,
+        height: height as i32,
+        refresh: get_number(&mode, "refreshRate").unwrap_or_default(),
+        scale: get_number(output, "scale").unwrap_or(1.0),
+        rotation: rotation(get_number(output, "rotation").unwrap_or(1.0) as u32),
+        primary,
+        mirror_of,
+    }
+}
+
+/// The mode KScreen will use for `output`: its current one, else the first preferred one.
+fn active_mode_id(output: &KMap) -> Option<String> {
+    get_str(output, "currentModeId")
+        .filter(|id| !id.is_empty())
+        .or_else(|| {
+            output.get("preferredModes")
+                .and_then(|v| Vec::<String>::try_from(unwrap_variant(v).try_clone().ok()?).ok())
+                .and_then(|modes| modes.into_iter().next())
+        })
+}
+
+/// KScreen rotations are bit flags: 1 none, 2 left, 4 inverted, 8 right.
+fn kscreen_rotation(rotation: Rotation) -> u32 {
+    match rotation {
+        Rotation::Normal => 1,
+        Rotation::Left => 2,
+        Rotation::Inverted => 4,
+        Rotation::Right => 8,
+    }
+}
+
+/// The inverse of `kscreen_rotation`.
+fn rotation(flags: u32) -> Rotation {
+    match flags {
+        2 => Rotation::Left,
+        4 => Rotation::Inverted,
+        8 => Rotation::Right,
+        _ => Rotation::Normal,
+    }
+}
+
+/// Make sure a disabled output has a mode to come back with.
+fn ensure_mode(output: &mut KMap) -> Result<(), DisplayError> {
+    let mode = active_mode_id(output).ok_or_else(|| {
+        DisplayError::NoUsableMode(get_str(output, "name").unwrap_or_default())
+    })?;
+    output.insert("currentModeId".to_string(), owned(Value::from(mode))?);
+    Ok(())
+}
+
+/// Enable `output` and set its scale, rotation, position, priority and replication source.
+fn configure_output(
+    output: &mut KMap,
+    scale: f64,
+    rotation: Rotation,
+    pos: (i32, i32),
+    priority: u32,
+    replication_source: i32,
+) -> Result<(), DisplayError> {
+    let mut position: HashMap<String, Value> = HashMap::new();
+    position.insert("x".to_string(), Value::from(pos.0));
+    position.insert("y".to_string(), Value::from(pos.1));
+
+    output.insert("enabled".to_string(), owned(Value::from(true))?);
+    output.insert("scale".to_string(), owned(Value::from(scale))?);
+    output.insert("rotation".to_string(), owned(Value::from(kscreen_rotation(rotation)))?);
+    output.insert("pos".to_string(), owned(Value::from(position))?);
+
+    // Only newer backends support mirroring a single output onto another one.
+    if output.contains_key("replicationSource") {
+        output.insert("replicationSource".to_string(), owned(Value::from(replication_source))?);
+    }
+
+    // Plasma 5.27 replaced the `primary` flag with an output priority.
+    if output.contains_key("priority") {
+        output.insert("priority".to_string(), owned(Value::from(priority))?);
+    }
+    if output.contains_key("primary") {
+        output.insert("primary".to_string(), owned(Value::from(priority == 1))?);
+    }
+    Ok(())
+}
+
+/// Put `outputs` back into `config`, wrapped in variants like KScreen sends them.
+fn set_outputs(config: &mut KMap, outputs: Vec<KMap>) -> Result<(), DisplayError> {
+    let list: Vec<Value> = outputs.into_iter()
+        .map(|output| {
+            let output: HashMap<String, Value> = output.into_iter().map(|(k, v)| (k, Value::from(v))).collect();
+            Value::Value(Box::new(Value::from(output)))
+        })
+        .collect();
+    config.insert("outputs".to_string(), owned(Value::from(list))?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use crate::config::LayoutConfig;
+    use crate::drm::Panels;
+    use crate::test_bus::{test_bus_or_skip, TestBus};
+
+    fn layout(name: &str, scale: f64) -> Layout {
+        Layout::resolve(name, &LayoutConfig::default(), &Panels::default(), (scale, scale)).unwrap()
+    }
+
+    fn size(width: i32, height: i32) -> Value<'static> {
+        let mut size: HashMap<String, Value> = HashMap::new();
+        size.insert("width".to_string(), Value::from(width));
+        size.insert("height".to_string(), Value::from(height));
+        Value::from(size)
+    }
+
+    fn output(id: i32, name: &str, enabled: bool, current_mode: &str) -> Value<'static> {
+        output_at(id, name, enabled, current_mode, (0, 0))
+    }
+
+    fn output_at(id: i32, name: &str, enabled: bool, current_mode: &str, (x, y): (i32, i32)) -> Value<'static> {
+        let mut mode: HashMap<String, Value> = HashMap::new();
+        mode.insert("id".to_string(), Value::from("0"));
+        mode.insert("size".to_string(), size(2880, 1800));
+        mode.insert("refreshRate".to_string(), Value::from(120.0));
+
+        let mut pos: HashMap<String, Value> = HashMap::new();
+        pos.insert("x".to_string(), Value::from(x));
+        pos.insert("y".to_string(), Value::from(y));
+
+        let mut output: HashMap<String, Value> = HashMap::new();
+        output.insert("id".to_string(), Value::from(id));
+        output.insert("name".to_string(), Value::from(name.to_string()));
+        output.insert("enabled".to_string(), Value::from(enabled));
+        output.insert("scale".to_string(), Value::from(1.0));
+        output.insert("rotation".to_string(), Value::from(1));
+        output.insert("priority".to_string(), Value::from(0u32));
+        output.insert("replicationSource".to_string(), Value::from(0));
+        output.insert("pos".to_string(), Value::from(pos));
+        output.insert("currentModeId".to_string(), Value::from(current_mode.to_string()));
+        output.insert("preferredModes".to_string(), Value::from(vec!["0".to_string()]));
+        output.insert("modes".to_string(), Value::from(vec![Value::Value(Box::new(Value::from(mode)))]));
+        Value::Value(Box::new(Value::from(output)))
+    }
+
+    struct FakeKScreen {
+        /// Whether to ignore requests to change eDP-2, like a compositor refusing the layout.
+        stubborn: bool,
+        /// Whether an external monitor is on above the top panel.
+        external: bool,
+        applied: Arc<Mutex<Vec<KMap>>>,
+    }
+
+    #[zbus::interface(name = "org.kde.kscreen.Backend")]
+    impl FakeKScreen {
+        #[zbus(name = "getConfig")]
+        fn get_config(&self) -> KMap {
+            let mut config = KMap::new();
+            let outputs = if self.external {
+                vec![output_at(3, "DP-1", true, "0", (0, 0)), output_at(1, "eDP-1", true, "0", (0, 1800)), output(2, "eDP-2", false, "")]
+            } else {
+                vec![output(1, "eDP-1", true, "0"), output(2, "eDP-2", false, "")]
+            };
+            config.insert("outputs".to_string(), Value::from(outputs).try_to_owned().unwrap());
+            config.insert("features".to_string(), Value::from(0).try_to_owned().unwrap());
+            config
+        }
+
+        #[zbus(name = "setConfig")]
+        fn set_config(&self, config: KMap) -> KMap {
+            let stored = config.iter().map(|(k, v)| (k.clone(), v.try_clone().unwrap())).collect();
+            self.applied.lock().unwrap().push(stored);
+            if self.stubborn { self.get_config() } else { config }
+        }
+    }
+
+    fn serve(bus: &TestBus, stubborn: bool) -> (Connection, Arc<Mutex<Vec<KMap>>>) {
+        serve_with(bus, stubborn, false)
+    }
+
+    fn serve_with(bus: &TestBus, stubborn: bool, external: bool) -> (Connection, Arc<Mutex<Vec<KMap>>>) {
+        let applied = Arc::new(Mutex::new(Vec::new()));
+        let service = zbus::blocking::connection::Builder::address(bus.address()).unwrap()
+            .name("org.kde.KScreen").unwrap()
+            .serve_at("/backend", FakeKScreen { stubborn, external, applied: applied.clone() }).unwrap()
+            .build()
+            .unwrap();
+        (service, applied)
+    }
+
+    #[test]
+    fn dual_enables_and_positions_bottom_panel() {
+        let bus = test_bus_or_skip!();
+        let (_service, applied) = serve(&bus, false);
+
+        KScreenManager::with_connection(bus.connect()).apply_layout(&layout("below", 1.5)).expect("apply");
+
+        let applied = applied.lock().unwrap();
+        let outputs = parse_outputs(&applied[0]).unwrap();
+        let top = &outputs[find_output(&outputs, "eDP-1").unwrap()];
+        let bottom = &outputs[find_output(&outputs, "eDP-2").unwrap()];
+        assert_eq!(get_number(top, "scale"), Some(1.5));
+        assert_eq!(get_number(top, "priority"), Some(1.0));
+        assert_eq!(get_bool(bottom, "enabled"), Some(true));
+        assert_eq!(get_str(bottom, "currentModeId").as_deref(), Some("0"));
+        assert_eq!(position(bottom), Some((0, 1200)));
+    }
+
+    #[test]
+    fn single_disables_bottom_panel() {
+        let bus = test_bus_or_skip!();
+        let (_service, applied) = serve(&bus, false);
+
+        KScreenManager::with_connection(bus.connect()).apply_layout(&layout("top-only", 1.25)).expect("apply");
+
+        let applied = applied.lock().unwrap();
+        let outputs = parse_outputs(&applied[0]).unwrap();
+        let bottom = &outputs[find_output(&outputs, "eDP-2").unwrap()];
+        assert_eq!(get_bool(bottom, "enabled"), Some(false));
+    }
+
+    #[test]
+    fn side_by_side_rotates_and_mirror_replicates() {
+        let bus = test_bus_or_skip!();
+        let (_service, applied) = serve(&bus, false);
+        let manager = KScreenManager::with_connection(bus.connect());
+
+        manager.apply_layout(&layout("side-by-side", 1.5)).expect("apply");
+        manager.apply_layout(&layout("mirror", 1.5)).expect("apply");
+
+        let applied = applied.lock().unwrap();
+        let outputs = parse_outputs(&applied[0]).unwrap();
+        let bottom = &outputs[find_output(&outputs, "eDP-2").unwrap()];
+        assert_eq!(get_number(bottom, "rotation"), Some(8.0));
+        assert_eq!(position(bottom), Some((1200, 0)));
+
+        let outputs = parse_outputs(&applied[1]).unwrap();
+        let bottom = &outputs[find_output(&outputs, "eDP-2").unwrap()];
+        assert_eq!(get_number(bottom, "replicationSource"), Some(1.0));
+        assert_eq!(position(bottom), Some((0, 0)));
+    }
+
+    #[test]
+    fn kept_external_output_stays_in_place() {
+        let bus = test_bus_or_skip!();
+        let (_service, applied) = serve_with(&bus, false, true);
+
+        let layout = layout("below", 1.5).with_externals(&["DP-1".to_string()], &Default::default());
+        KScreenManager::with_connection(bus.connect()).apply_layout(&layout).expect("apply");
+
+        let applied = applied.lock().unwrap();
+        let outputs = parse_outputs(&applied[0]).unwrap();
+        let position_of = |name| position(&outputs[find_output(&outputs, name).unwrap()]);
+        assert_eq!(position_of("DP-1"), Some((0, 0)));
+        assert_eq!(position_of("eDP-1"), Some((0, 1800)));
+        assert_eq!(position_of("eDP-2"), Some((0, 3000)));
+    }
+
+    #[test]
+    fn refused_layout_is_reported() {
+        let bus = test_bus_or_skip!();
+        let (_service, _) = serve(&bus, true);
+
+        let result = KScreenManager::with_connection(bus.connect()).apply_layout(&layout("below", 1.5));
+        assert!(matches!(result, Err(DisplayError::Rejected(_))));
+    }
+
+    #[test]
+    fn snapshot_round_trips() {
+        let bus = test_bus_or_skip!();
+        let (_service, applied) = serve(&bus, false);
+        let manager = KScreenManager::with_connection(bus.connect());
+
+        let mut snapshot = manager.snapshot().expect("snapshot");
+        assert_eq!(snapshot.backend, "kscreen");
+        let top = snapshot.output("eDP-1").unwrap().clone();
+        assert!(top.enabled);
+        assert_eq!((top.width, top.height, top.refresh), (2880, 1800, 120.0));
+        assert!(!snapshot.output("eDP-2").unwrap().enabled);
+
+        snapshot.outputs[1] = OutputState { connector: "eDP-2".to_string(), x: 1920, rotation: Rotation::Left, ..top };
+        snapshot.outputs[0].primary = true;
+        manager.restore(&snapshot).expect("restore");
+
+        let applied = applied.lock().unwrap();
+        let outputs = parse_outputs(&applied[0]).unwrap();
+        let top = &outputs[find_output(&outputs, "eDP-1").unwrap()];
+        let bottom = &outputs[find_output(&outputs, "eDP-2").unwrap()];
+        assert_eq!(get_number(top, "priority"), Some(1.0));
+        assert_eq!(get_bool(bottom, "enabled"), Some(true));
+        assert_eq!(get_str(bottom, "currentModeId").as_deref(), Some("0"));
+        assert_eq!(get_number(bottom, "rotation"), Some(2.0));
+        assert_eq!(position(bottom), Some((1920, 0)));
+    }
+
+    struct FakeInputDevice {
+        output_name: String,
+    }
+
+    #[zbus::interface(name = "org.kde.KWin.InputDevice")]
+    impl FakeInputDevice {
+        #[zbus(property, name = "outputName")]
+        fn output_name(&self) -> String {
+            self.output_name.clone()
+        }
+
+        #[zbus(property, name = "outputName")]
+        fn set_output_name(&mut self, name: String) {
+            self.output_name = name;
+        }
+    }
+
+    #[test]
+    fn maps_touchscreens_through_kwin() {
+        let bus = test_bus_or_skip!();
+        let _service = zbus::blocking::connection::Builder::address(bus.address()).unwrap()
+            .name("org.kde.KWin").unwrap()
+            .serve_at("/org/kde/KWin/InputDevice/event9", FakeInputDevice { output_name: "eDP-1".to_string() }).unwrap()
+            .build()
+            .unwrap();
+
+        let mapping = TouchMapping {
+            digitizer: crate::touch::Digitizer {
+                name: "ELAN9009:00 04F3:425A".to_string(),
+                sys_name: "event9".to_string(),
+                vendor_id: 0x04f3,
+                product_id: 0x425a,
+                pen: false,
+                physical: "i2c-ELAN9009:00".to_string(),
+            },
+            connector: "eDP-2".to_string(),
+        };
+        let connection = bus.connect();
+        KScreenManager::with_connection(connection.clone()).map_touchscreens(&[mapping]).expect("map");
+
+        let device = InputDeviceProxyBlocking::builder(&connection)
+            .path("/org/kde/KWin/InputDevice/event9").unwrap()
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build()
+            .unwrap();
+        assert_eq!(device.output_name().unwrap(), "eDP-2");
+    }
+}
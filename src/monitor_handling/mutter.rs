@@ -0,0 +1,599 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use zbus::blocking::Connection;
+use zbus::proxy;
+use zbus::zvariant::OwnedValue;
+use log::{debug, warn};
+use crate::layout::{self, Layout, Position, Rotation};
+use crate::touch::TouchMapping;
+use super::snapshot::{DisplaySnapshot, OutputState};
+use super::{run_command, DisplayError, DisplayManager};
+
+/// `(connector, vendor, product, serial)`
+type MonitorSpec = (String, String, String, String);
+/// `(id, width, height, refresh_rate, preferred_scale, supported_scales, properties)`
+type ModeInfo = (String, i32, i32, f64, f64, Vec<f64>, HashMap<String, OwnedValue>);
+/// `(spec, modes, properties)`
+type MonitorInfo = (MonitorSpec, Vec<ModeInfo>, HashMap<String, OwnedValue>);
+/// `(x, y, scale, transform, primary, monitors, properties)`
+type LogicalMonitorInfo = (i32, i32, f64, u32, bool, Vec<MonitorSpec>, HashMap<String, OwnedValue>);
+/// `(serial, monitors, logical_monitors, properties)`
+type CurrentState = (u32, Vec<MonitorInfo>, Vec<LogicalMonitorInfo>, HashMap<String, OwnedValue>);
+/// `(connector, mode_id, properties)`
+type MonitorConfig = (String, String, HashMap<String, OwnedValue>);
+/// `(x, y, scale, transform, primary, monitors)`
+type LogicalMonitorConfig = (i32, i32, f64, u32, bool, Vec<MonitorConfig>);
+
+const LAYOUT_MODE_PHYSICAL: u32 = 2;
+const METHOD_TEMPORARY: u32 = 1;
+const BACKEND: &str = "mutter";
+
+#[proxy(
+    interface = "org.gnome.Mutter.DisplayConfig",
+    default_service = "org.gnome.Mutter.DisplayConfig",
+    default_path = "/org/gnome/Mutter/DisplayConfig"
+)]
+trait DisplayConfig {
+    fn get_current_state(&self) -> zbus::Result<CurrentState>;
+
+    fn apply_monitors_config(
+        &self,
+        serial: u32,
+        method: u32,
+        logical_monitors: Vec<LogicalMonitorConfig>,
+        properties: HashMap<String, OwnedValue>,
+    ) -> zbus::Result<()>;
+}
+
+/// Display manager for GNOME, talking to Mutter's `org.gnome.Mutter.DisplayConfig` directly.
+///
+/// The mode of every connector is remembered while it is active, so a panel that is switched off
+/// and on again comes back with the same resolution and refresh rate instead of the preferred one.
+pub(super) struct MutterManager {
+    connection: Option<Connection>,
+    last_modes: Mutex<HashMap<String, String>>,
+}
+
+impl MutterManager {
+    pub(super) fn new() -> Self {
+        MutterManager { connection: None, last_modes: Mutex::new(HashMap::new()) }
+    }
+
+    #[cfg(test)]
+    fn with_connection(connection: Connection) -> Self {
+        MutterManager { connection: Some(connection), last_modes: Mutex::new(HashMap::new()) }
+    }
+
+    fn proxy(&self) -> Result<DisplayConfigProxyBlocking<'static>, DisplayError> {
+        let connection = match &self.connection {
+            Some(connection) => connection.clone(),
+            None => Connection::session()?,
+        };
+        Ok(DisplayConfigProxyBlocking::new(&connection)?)
+    }
+
+    fn current_state(&self, proxy: &DisplayConfigProxyBlocking) -> Result<CurrentState, DisplayError> {
+        let state = proxy.get_current_state()?;
+
+        let mut last_modes = self.last_modes.lock().unwrap();
+        for (spec, modes, _) in &state.1 {
+            if let Some(mode) = modes.iter().find(|m| mode_flag(m, "is-current")) {
+                last_modes.insert(spec.0.clone(), mode.0.clone());
+            }
+        }
+
+        Ok(state)
+    }
+
+    /// Pick the mode to use for `connector`: the active one, else the last one we saw active,
+    /// else the preferred one.
+    fn mode_for<'a>(&self, state: &'a CurrentState, connector: &str) -> Result<&'a ModeInfo, DisplayError> {
+        let (_, modes, _) = state.1.iter()
+            .find(|(spec, _, _)| spec.0 == connector)
+            .ok_or_else(|| DisplayError::ConnectorNotFound(connector.to_string()))?;
+
+        let remembered = self.last_modes.lock().unwrap().get(connector).cloned();
+
+        modes.iter().find(|m| mode_flag(m, "is-current"))
+            .or_else(|| remembered.and_then(|id| modes.iter().find(|m| m.0 == id)))
+            .or_else(|| modes.iter().find(|m| mode_flag(m, "is-preferred")))
+            .or_else(|| modes.first())
+            .ok_or_else(|| DisplayError::NoUsableMode(connector.to_string()))
+    }
+
+    fn apply(&self, layout: &Layout) -> Result<(), DisplayError> {
+        let proxy = self.proxy()?;
+        let state = self.current_state(&proxy)?;
+        let physical = state.3.get("layout-mode")
+            .and_then(|v| u32::try_from(v).ok())
+            .is_some_and(|mode| mode == LAYOUT_MODE_PHYSICAL);
+
+        let mut modes = HashMap::new();
+        let mut scales = HashMap::new();
+        for output in layout.enabled_outputs() {
+            let mode = self.mode_for(&state, &output.connector)?;
+            scales.insert(output.connector.as_str(), closest_scale(mode, output.scale));
+            modes.insert(output.connector.as_str(), mode);
+        }
+
+        // In physical layout mode positions are in device pixels, otherwise in logical pixels.
+        let positions = layout::place(layout, |o| {
+            let mode = modes.get(o.connector.as_str())?;
+            let scale = if physical { 1.0 } else { scales[o.connector.as_str()] };
+            layout::logical_size(mode.1, mode.2, o.rotation, scale)
+        }).map_err(DisplayError::Rejected)?;
+
+        let mut logical_monitors: Vec<LogicalMonitorConfig> = Vec::new();
+        for output in layout.enabled_outputs().filter(|o| o.position != Position::Mirror) {
+            let connector = output.connector.as_str();
+            let (x, y) = positions[connector];
+            logical_monitors.push((
+                x, y, scales[connector], transform(output.rotation), output.primary,
+                vec![(output.connector.clone(), modes[connector].0.clone(), HashMap::new())],
+            ));
+        }
+        // A mirrored panel joins the logical monitor of the panel it mirrors, sharing its scale and
+        // transform.
+        for output in layout.enabled_outputs().filter(|o| o.position == Position::Mirror) {
+            let reference = output.relative_to.as_deref().unwrap_or_default();
+            let logical_monitor = logical_monitors.iter_mut()
+                .find(|lm| lm.5.iter().any(|m| m.0 == reference))
+                .ok_or_else(|| DisplayError::ConnectorNotFound(reference.to_string()))?;
+            logical_monitor.5.push((output.connector.clone(), modes[output.connector.as_str()].0.clone(), HashMap::new()));
+        }
+
+        // Kept external monitors stay where they are, so the panels are moved to keep the anchor
+        // panel at its current position next to them.
+        let kept: Vec<&LogicalMonitorInfo> = state.2.iter()
+            .filter(|lm| lm.5.iter().all(|m| layout.keep.contains(&m.0)))
+            .collect();
+        let (dx, dy) = layout::keep_offset(layout, |connector| {
+            state.2.iter().find(|lm| lm.5.iter().any(|m| m.0 == connector)).map(|lm| (lm.0, lm.1))
+        });
+        for logical_monitor in &mut logical_monitors {
+            logical_monitor.0 += dx;
+            logical_monitor.1 += dy;
+        }
+        for lm in kept {
+            let mut monitors = Vec::new();
+            for spec in &lm.5 {
+                monitors.push((spec.0.clone(), self.mode_for(&state, &spec.0)?.0.clone(), HashMap::new()));
+            }
+            logical_monitors.push((lm.0, lm.1, lm.2, lm.3, false, monitors));
+        }
+
+        debug!("Applying Mutter monitors config {:?}", logical_monitors);
+        proxy.apply_monitors_config(state.0, METHOD_TEMPORARY, logical_monitors, HashMap::new())?;
+        Ok(())
+    }
+
+    fn restore_snapshot(&self, snapshot: &DisplaySnapshot) -> Result<(), DisplayError> {
+        snapshot.check_backend(BACKEND)?;
+        let proxy = self.proxy()?;
+        let state = self.current_state(&proxy)?;
+
+        let mut logical_monitors: Vec<LogicalMonitorConfig> = Vec::new();
+        for output in snapshot.enabled_outputs().filter(|o| o.mirror_of.is_none()) {
+            let mode = snapshot_mode(&state, output)?;
+            logical_monitors.push((
+                output.x, output.y, output.scale, transform(output.rotation), output.primary,
+                vec![(output.connector.clone(), mode.0.clone(), HashMap::new())],
+            ));
+        }
+        for output in snapshot.enabled_outputs() {
+            let Some(reference) = &output.mirror_of else { continue };
+            let mode = snapshot_mode(&state, output)?;
+            let logical_monitor = logical_monitors.iter_mut()
+                .find(|lm| lm.5.iter().any(|m| m.0 == *reference))
+                .ok_or_else(|| DisplayError::ConnectorNotFound(reference.clone()))?;
+            logical_monitor.5.push((output.connector.clone(), mode.0.clone(), HashMap::new()));
+        }
+
+        debug!("Restoring Mutter monitors config {:?}", logical_monitors);
+        proxy.apply_monitors_config(state.0, METHOD_TEMPORARY, logical_monitors, HashMap::new())?;
+        Ok(())
+    }
+}
+
+impl DisplayManager for MutterManager {
+    /// Apply `layout` as a temporary monitors config, snapping every scale to the closest value
+    /// supported by the panel's mode.
+    ///
+    /// Panels keep their current mode, so a custom resolution or refresh rate survives the switch.
+    /// A panel that is switched back on gets the mode it had the last time it was active, falling
+    /// back to its preferred mode. Panels left out of the config are turned off by Mutter, so the
+    /// logical monitors of the kept external monitors are passed on unchanged.
+    fn apply_layout(&self, layout: &Layout) -> Result<(), DisplayError> {
+        self.apply(layout)
+    }
+
+    /// Point the per-device `output` setting of gnome-settings-daemon at the panel's monitor.
+    ///
+    /// GNOME identifies monitors by their EDID vendor, product and serial, which are looked up in
+    /// the current state.
+    fn map_touchscreens(&self, mappings: &[TouchMapping]) -> Result<(), DisplayError> {
+        let state = self.current_state(&self.proxy()?)?;
+        for mapping in mappings {
+            let (spec, _, _) = state.1.iter()
+                .find(|(spec, _, _)| spec.0 == mapping.connector)
+                .ok_or_else(|| DisplayError::ConnectorNotFound(mapping.connector.clone()))?;
+            let args = gsettings_args(mapping, spec);
+            debug!("Executing 'gsettings {}'", args.join(" "));
+            run_command("gsettings", &args)?;
+        }
+        Ok(())
+    }
+
+    /// Read every connected monitor from the current state, together with the logical monitor it
+    /// belongs to. The first monitor of a logical monitor is the one the others mirror.
+    fn snapshot(&self) -> Result<DisplaySnapshot, DisplayError> {
+        let state = self.current_state(&self.proxy()?)?;
+        let outputs = state.1.iter()
+            .map(|(spec, modes, _)| {
+                let logical_monitor = state.2.iter().find(|lm| lm.5.iter().any(|m| m.0 == spec.0));
+                let mode = modes.iter().find(|m| mode_flag(m, "is-current"));
+                let (Some(lm), Some(mode)) = (logical_monitor, mode) else {
+                    return OutputState::disabled(&spec.0);
+                };
+                OutputState {
+                    connector: spec.0.clone(),
+                    enabled: true,
+                    x: lm.0,
+                    y: lm.1,
+                    width: mode.1,
+                    height: mode.2,
+                    refresh: mode.3,
+                    scale: lm.2,
+                    rotation: rotation(lm.3),
+                    primary: lm.4,
+                    mirror_of: lm.5.first().filter(|m| m.0 != spec.0).map(|m| m.0.clone()),
+                }
+            })
+            .collect();
+        Ok(DisplaySnapshot { backend: BACKEND.to_string(), outputs })
+    }
+
+    /// Apply the snapshot as a temporary monitors config, picking the mode with the recorded size
+    /// and the closest refresh rate. Monitors the snapshot has no logical monitor for are turned off.
+    fn restore(&self, snapshot: &DisplaySnapshot) -> Result<(), DisplayError> {
+        self.restore_snapshot(snapshot)
+    }
+}
+
+/// The mode of `output`'s monitor that matches the snapshot best.
+fn snapshot_mode<'a>(state: &'a CurrentState, output: &OutputState) -> Result<&'a ModeInfo, DisplayError> {
+    let (_, modes, _) = state.1.iter()
+        .find(|(spec, _, _)| spec.0 == output.connector)
+        .ok_or_else(|| DisplayError::ConnectorNotFound(output.connector.clone()))?;
+    modes.iter()
+        .filter(|m| m.1 == output.width && m.2 == output.height)
+        .min_by(|a, b| (a.3 - output.refresh).abs().total_cmp(&(b.3 - output.refresh).abs()))
+        .ok_or_else(|| DisplayError::NoUsableMode(output.connector.clone()))
+}
+
+/// `gsettings set` arguments mapping a touch screen or pen to the monitor described by `spec`.
+fn gsettings_args(mapping: &TouchMapping, spec: &MonitorSpec) -> Vec<String> {
+    let digitizer = &mapping.digitizer;
+    let (schema, dir) = if digitizer.pen {
+        ("org.gnome.desktop.peripherals.tablet", "tablets")
+    } else {
+        ("org.gnome.desktop.peripherals.touchscreen", "touchscreens")
+    };
+    let quote = |s: &str| format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"));
+    vec![
+        "set".to_string(),
+        format!("{}:/org/gnome/desktop/peripherals/{}/{:04x}:{:04x}/", schema, dir, digitizer.vendor_id, digitizer.product_id),
+        "output".to_string(),
+        format!("[{}, {}, {}]", quote(&spec.1), quote(&spec.2), quote(&spec.3)),
+    ]
+}
+
+/// Mutter transforms count 90° steps counter-clockwise, like `Rotation`.
+fn transform(rotation: Rotation) -> u32 {
+    match rotation {
+        Rotation::Normal => 0,
+        Rotation::Left => 1,
+        Rotation::Inverted => 2,
+        Rotation::Right => 3,
+    }
+}
+
+/// The inverse of `transform`. Flipped transforms (4 to 7) lose their flip.
+fn rotation(transform: u32) -> Rotation {
+    match transform % 4 {
+        1 => Rotation::Left,
+        2 => Rotation::Inverted,
+        3 => Rotation::Right,
+        _ => Rotation::Normal,
+    }
+}
+
+fn mode_flag(mode: &ModeInfo, name: &str) -> bool {
+    mode.6.get(name).and_then(|v| bool::try_from(v).ok()).unwrap_or(false)
+}
+
+/// Mutter only accepts scales from the mode's `supported_scales`, so snap to the nearest one.
+fn closest_scale(mode: &ModeInfo, requested: f64) -> f64 {
+    let closest = mode.5.iter()
+        .copied()
+        .min_by(|a, b| (a - requested).abs().total_cmp(&(b - requested).abs()))
+        .unwrap_or(requested);
+
+    if (closest - requested).abs() > 0.01 {
+        warn!("Scale {} is not supported by mode {}, using {} instead", requested, mode.0, closest);
+    }
+    closest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use zbus::zvariant::Value;
+    use crate::config::LayoutConfig;
+    use crate::drm::Panels;
+    use crate::test_bus::test_bus_or_skip;
+
+    fn flags(current: bool, preferred: bool) -> HashMap<String, OwnedValue> {
+        let mut props = HashMap::new();
+        if current {
+            props.insert("is-current".to_string(), Value::from(true).try_into().unwrap());
+        }
+        if preferred {
+            props.insert("is-preferred".to_string(), Value::from(true).try_into().unwrap());
+        }
+        props
+    }
+
+    fn mode(id: &str, refresh: f64, current: bool, preferred: bool) -> ModeInfo {
+        (id.to_string(), 2880, 1800, refresh, 1.5, vec![1.0, 1.25, 1.5, 2.0], flags(current, preferred))
+    }
+
+    fn spec(connector: &str) -> MonitorSpec {
+        (connector.to_string(), "SDC".to_string(), "0x419f".to_string(), "0x00000000".to_string())
+    }
+
+    fn layout(name: &str, scale: f64) -> Layout {
+        Layout::resolve(name, &LayoutConfig::default(), &Panels::default(), (scale, scale)).unwrap()
+    }
+
+    type Applied = Arc<Mutex<Vec<(u32, u32, Vec<LogicalMonitorConfig>)>>>;
+
+    struct FakeMutter {
+        bottom_enabled: bool,
+        /// Whether an external monitor sits above the top panel.
+        docked: bool,
+        applied: Applied,
+    }
+
+    #[zbus::interface(name = "org.gnome.Mutter.DisplayConfig")]
+    impl FakeMutter {
+        #[zbus(out_args("serial", "monitors", "logical_monitors", "properties"))]
+        fn get_current_state(&self) -> CurrentState {
+            let monitors = vec![
+                (spec("eDP-1"), vec![mode("2880x1800@60", 60.0, false, true), mode("2880x1800@120", 120.0, true, false)], HashMap::new()),
+                (spec("eDP-2"), vec![mode("2880x1800@60", 60.0, false, true), mode("2880x1800@120", 120.0, self.bottom_enabled, false)], HashMap::new()),
+            ];
+            let mut monitors = monitors;
+            let mut logical_monitors = vec![(0, 0, 1.5, 0, true, vec![spec("eDP-1")], HashMap::new())];
+            if self.docked {
+                monitors.push((spec("DP-1"), vec![mode("2560x1440@60", 60.0, true, true)], HashMap::new()));
+                logical_monitors[0].1 = 1440;
+                logical_monitors.push((0, 0, 1.0, 0, false, vec![spec("DP-1")], HashMap::new()));
+            }
+            let mut properties = HashMap::new();
+            properties.insert("layout-mode".to_string(), Value::from(1u32).try_into().unwrap());
+            (42, monitors, logical_monitors, properties)
+        }
+
+        fn apply_monitors_config(
+            &self,
+            serial: u32,
+            method: u32,
+            logical_monitors: Vec<LogicalMonitorConfig>,
+            _properties: HashMap<String, OwnedValue>,
+        ) {
+            self.applied.lock().unwrap().push((serial, method, logical_monitors));
+        }
+    }
+
+    fn serve(bus: &crate::test_bus::TestBus, bottom_enabled: bool) -> (Connection, Applied) {
+        serve_fake(bus, bottom_enabled, false)
+    }
+
+    fn serve_fake(bus: &crate::test_bus::TestBus, bottom_enabled: bool, docked: bool) -> (Connection, Applied) {
+        let applied = Arc::new(Mutex::new(Vec::new()));
+        let fake = FakeMutter { bottom_enabled, docked, applied: applied.clone() };
+        let service = zbus::blocking::connection::Builder::address(bus.address()).unwrap()
+            .name("org.gnome.Mutter.DisplayConfig").unwrap()
+            .serve_at("/org/gnome/Mutter/DisplayConfig", fake).unwrap()
+            .build()
+            .unwrap();
+        (service, applied)
+    }
+
+    #[test]
+    fn dual_places_bottom_panel_below_and_keeps_modes() {
+        let bus = test_bus_or_skip!();
+        let (_service, applied) = serve(&bus, false);
+
+        MutterManager::with_connection(bus.connect()).apply_layout(&layout("below", 1.5)).expect("apply");
+
+        let applied = applied.lock().unwrap();
+        let (serial, method, logical_monitors) = &applied[0];
+        assert_eq!(*serial, 42);
+        assert_eq!(*method, METHOD_TEMPORARY);
+        assert_eq!(logical_monitors.len(), 2);
+        let (x, y, scale, _, primary, monitors) = &logical_monitors[0];
+        assert_eq!((*x, *y, *scale, *primary), (0, 0, 1.5, true));
+        assert_eq!(monitors[0].1, "2880x1800@120");
+        let (x, y, _, _, primary, monitors) = &logical_monitors[1];
+        assert_eq!((*x, *y, *primary), (0, 1200, false));
+        assert_eq!(monitors[0].0, "eDP-2");
+        assert_eq!(monitors[0].1, "2880x1800@60");
+    }
+
+    #[test]
+    fn remembers_mode_of_disabled_panel() {
+        let bus = test_bus_or_skip!();
+        let manager = MutterManager::with_connection(bus.connect());
+
+        {
+            let (_service, _) = serve(&bus, true);
+            manager.apply_layout(&layout("top-only", 1.5)).expect("apply");
+        }
+
+        let (_service, applied) = serve(&bus, false);
+        manager.apply_layout(&layout("below", 1.5)).expect("apply");
+        let applied = applied.lock().unwrap();
+        assert_eq!(applied[0].2[1].5[0].1, "2880x1800@120");
+    }
+
+    #[test]
+    fn single_only_configures_top_panel() {
+        let bus = test_bus_or_skip!();
+        let (_service, applied) = serve(&bus, true);
+
+        MutterManager::with_connection(bus.connect()).apply_layout(&layout("top-only", 1.25)).expect("apply");
+
+        let applied = applied.lock().unwrap();
+        let logical_monitors = &applied[0].2;
+        assert_eq!(logical_monitors.len(), 1);
+        assert_eq!(logical_monitors[0].2, 1.25);
+        assert_eq!(logical_monitors[0].5[0].0, "eDP-1");
+    }
+
+    #[test]
+    fn side_by_side_rotates_both_panels() {
+        let bus = test_bus_or_skip!();
+        let (_service, applied) = serve(&bus, true);
+
+        MutterManager::with_connection(bus.connect()).apply_layout(&layout("side-by-side", 1.5)).expect("apply");
+
+        let applied = applied.lock().unwrap();
+        let logical_monitors = &applied[0].2;
+        assert_eq!(logical_monitors.len(), 2);
+        assert_eq!((logical_monitors[0].0, logical_monitors[0].1, logical_monitors[0].3), (0, 0, 3));
+        assert_eq!((logical_monitors[1].0, logical_monitors[1].1, logical_monitors[1].3), (1200, 0, 3));
+    }
+
+    #[test]
+    fn panels_get_their_own_scale() {
+        let bus = test_bus_or_skip!();
+        let (_service, applied) = serve(&bus, true);
+
+        let layout = Layout::resolve("below", &LayoutConfig::default(), &Panels::default(), (1.5, 1.3)).unwrap();
+        MutterManager::with_connection(bus.connect()).apply_layout(&layout).expect("apply");
+
+        let applied = applied.lock().unwrap();
+        let logical_monitors = &applied[0].2;
+        assert_eq!(logical_monitors[0].2, 1.5);
+        // 1.3 is not supported by the mode and snaps to 1.25.
+        assert_eq!((logical_monitors[1].1, logical_monitors[1].2), (1200, 1.25));
+    }
+
+    #[test]
+    fn kept_external_monitor_stays_in_place() {
+        let bus = test_bus_or_skip!();
+        let (_service, applied) = serve_fake(&bus, false, true);
+
+        let layout = layout("top-only", 1.5).with_externals(&["DP-1".to_string()], &Default::default());
+        MutterManager::with_connection(bus.connect()).apply_layout(&layout).expect("apply");
+
+        let applied = applied.lock().unwrap();
+        let logical_monitors = &applied[0].2;
+        assert_eq!(logical_monitors.len(), 2);
+        assert_eq!((logical_monitors[0].0, logical_monitors[0].1, logical_monitors[0].4), (0, 1440, true));
+        let external = &logical_monitors[1];
+        assert_eq!((external.0, external.1, external.2, external.4), (0, 0, 1.0, false));
+        assert_eq!(external.5[0].1, "2560x1440@60");
+    }
+
+    #[test]
+    fn mirror_shares_one_logical_monitor() {
+        let bus = test_bus_or_skip!();
+        let (_service, applied) = serve(&bus, true);
+
+        MutterManager::with_connection(bus.connect()).apply_layout(&layout("mirror", 1.5)).expect("apply");
+
+        let applied = applied.lock().unwrap();
+        let logical_monitors = &applied[0].2;
+        assert_eq!(logical_monitors.len(), 1);
+        let connectors: Vec<&str> = logical_monitors[0].5.iter().map(|m| m.0.as_str()).collect();
+        assert_eq!(connectors, vec!["eDP-1", "eDP-2"]);
+    }
+
+    #[test]
+    fn snapshot_records_current_modes_and_restores_them() {
+        let bus = test_bus_or_skip!();
+        let (_service, applied) = serve(&bus, false);
+        let manager = MutterManager::with_connection(bus.connect());
+
+        let mut snapshot = manager.snapshot().expect("snapshot");
+        assert_eq!(snapshot.backend, "mutter");
+        let top = snapshot.output("eDP-1").unwrap();
+        assert!(top.enabled && top.primary);
+        assert_eq!((top.width, top.height, top.refresh, top.scale), (2880, 1800, 120.0, 1.5));
+        assert!(!snapshot.output("eDP-2").unwrap().enabled);
+
+        snapshot.outputs[1] = OutputState { connector: "eDP-2".to_string(), y: 1200, refresh: 59.9, primary: false, ..top.clone() };
+        manager.restore(&snapshot).expect("restore");
+
+        let applied = applied.lock().unwrap();
+        let logical_monitors = &applied[0].2;
+        assert_eq!(logical_monitors.len(), 2);
+        assert_eq!((logical_monitors[0].1, logical_monitors[0].4), (0, true));
+        assert_eq!(logical_monitors[0].5[0].1, "2880x1800@120");
+        assert_eq!((logical_monitors[1].1, logical_monitors[1].4), (1200, false));
+        assert_eq!(logical_monitors[1].5[0].1, "2880x1800@60");
+    }
+
+    #[test]
+    fn snapshot_of_other_backend_is_refused() {
+        let bus = test_bus_or_skip!();
+        let (_service, applied) = serve(&bus, true);
+        let manager = MutterManager::with_connection(bus.connect());
+
+        let mut snapshot = manager.snapshot().expect("snapshot");
+        snapshot.backend = "kscreen".to_string();
+        assert!(matches!(manager.restore(&snapshot), Err(DisplayError::Rejected(_))));
+        assert!(applied.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn missing_service_is_reported() {
+        let bus = test_bus_or_skip!();
+
+        let result = MutterManager::with_connection(bus.connect()).apply_layout(&layout("top-only", 1.5));
+        assert!(matches!(result, Err(DisplayError::DBus(_))));
+    }
+
+    #[test]
+    fn gsettings_args_target_device_schema() {
+        let mapping = TouchMapping {
+            digitizer: crate::touch::Digitizer {
+                name: "ELAN9009:00 04F3:425A Stylus".to_string(),
+                sys_name: "event10".to_string(),
+                vendor_id: 0x04f3,
+                product_id: 0x425a,
+                pen: true,
+                physical: "i2c-ELAN9009:00".to_string(),
+            },
+            connector: "eDP-2".to_string(),
+        };
+        assert_eq!(gsettings_args(&mapping, &spec("eDP-2")), vec![
+            "set",
+            "org.gnome.desktop.peripherals.tablet:/org/gnome/desktop/peripherals/tablets/04f3:425a/",
+            "output",
+            "['SDC', '0x419f', '0x00000000']",
+        ]);
+    }
+
+    #[test]
+    fn closest_scale_snaps_to_supported_value() {
+        let m = mode("m", 60.0, true, true);
+        assert_eq!(closest_scale(&m, 1.5), 1.5);
+        assert_eq!(closest_scale(&m, 1.3), 1.25);
+        assert_eq!(closest_scale(&m, 3.0), 2.0);
+    }
+}
@@ -0,0 +1,184 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use log::warn;
+use crate::config::get_config_path;
+use crate::layout::Rotation;
+use super::DisplayError;
+
+/// How one output was configured when a snapshot was taken.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputState {
+    /// The output's name as the display manager knows it.
+    pub connector: String,
+    pub enabled: bool,
+    /// Position in the compositor's layout space.
+    pub x: i32,
+    pub y: i32,
+    /// Unrotated size of the mode in device pixels.
+    pub width: i32,
+    pub height: i32,
+    /// Refresh rate of the mode in Hz.
+    pub refresh: f64,
+    pub scale: f64,
+    pub rotation: Rotation,
+    pub primary: bool,
+    /// The output this one mirrors.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror_of: Option<String>,
+}
+
+impl OutputState {
+    pub fn disabled(connector: &str) -> Self {
+        OutputState {
+            connector: connector.to_string(),
+            enabled: false,
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+            refresh: 0.0,
+            scale: 1.0,
+            rotation: Rotation::Normal,
+            primary: false,
+            mirror_of: None,
+        }
+    }
+}
+
+/// The configuration of every connected output, taken before the keyboard layout replaces it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DisplaySnapshot {
+    /// The display manager that took the snapshot; output names and positions only make sense to it.
+    pub backend: String,
+    pub outputs: Vec<OutputState>,
+}
+
+impl DisplaySnapshot {
+    /// Fail unless this snapshot was taken by the display manager called `backend`.
+    pub fn check_backend(&self, backend: &str) -> Result<(), DisplayError> {
+        if self.backend == backend {
+            Ok(())
+        } else {
+            Err(DisplayError::Rejected(format!("snapshot was taken by {}, not {}", self.backend, backend)))
+        }
+    }
+
+    pub fn enabled_outputs(&self) -> impl Iterator<Item = &OutputState> {
+        self.outputs.iter().filter(|o| o.enabled)
+    }
+
+    pub fn output(&self, connector: &str) -> Option<&OutputState> {
+        self.outputs.iter().find(|o| o.connector == connector)
+    }
+
+    /// Whether both snapshots were taken by the same display manager with the same outputs connected.
+    pub fn same_outputs(&self, other: &DisplaySnapshot) -> bool {
+        let mut ours: Vec<&str> = self.outputs.iter().map(|o| o.connector.as_str()).collect();
+        let mut theirs: Vec<&str> = other.outputs.iter().map(|o| o.connector.as_str()).collect();
+        ours.sort();
+        theirs.sort();
+        self.backend == other.backend && ours == theirs
+    }
+}
+
+/// `display-snapshot.json` next to the config file.
+pub fn snapshot_path() -> Option<PathBuf> {
+    get_config_path().map(|path| path.with_file_name("display-snapshot.json"))
+}
+
+pub fn save(snapshot: &DisplaySnapshot, path: &Path) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let json = serde_json::to_string_pretty(snapshot).map_err(io::Error::other)?;
+    fs::write(path, json)
+}
+
+/// Read the snapshot at `path`; a missing file is no snapshot, an unreadable one is logged.
+pub fn load(path: &Path) -> Option<DisplaySnapshot> {
+    let json = fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&json) {
+        Ok(snapshot) => Some(snapshot),
+        Err(e) => {
+            warn!("Ignoring unreadable display snapshot {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+pub fn remove(path: &Path) {
+    if let Err(e) = fs::remove_file(path)
+        && e.kind() != io::ErrorKind::NotFound {
+        warn!("Failed to remove display snapshot {:?}: {}", path, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn sample() -> DisplaySnapshot {
+        DisplaySnapshot {
+            backend: "test".to_string(),
+            outputs: vec![
+                OutputState {
+                    connector: "eDP-1".to_string(),
+                    enabled: true,
+                    x: 0,
+                    y: 0,
+                    width: 2880,
+                    height: 1800,
+                    refresh: 120.0,
+                    scale: 1.5,
+                    rotation: Rotation::Normal,
+                    primary: true,
+                    mirror_of: None,
+                },
+                OutputState::disabled("eDP-2"),
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trips_through_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("zenbook-duo").join("display-snapshot.json");
+        assert_eq!(load(&path), None);
+
+        save(&sample(), &path).expect("save");
+        assert_eq!(load(&path), Some(sample()));
+
+        remove(&path);
+        assert_eq!(load(&path), None);
+        remove(&path);
+    }
+
+    #[test]
+    fn unreadable_snapshot_is_ignored() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("display-snapshot.json");
+        fs::write(&path, "{").unwrap();
+        assert_eq!(load(&path), None);
+    }
+
+    #[test]
+    fn compares_connected_outputs() {
+        let snapshot = sample();
+        let mut reordered = sample();
+        reordered.outputs.reverse();
+        assert!(snapshot.same_outputs(&reordered));
+
+        let mut unplugged = sample();
+        unplugged.outputs.push(OutputState::disabled("DP-1"));
+        assert!(!snapshot.same_outputs(&unplugged));
+
+        let mut other = sample();
+        other.backend = "other".to_string();
+        assert!(!snapshot.same_outputs(&other));
+        assert!(other.check_backend("test").is_err());
+        assert!(snapshot.check_backend("test").is_ok());
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+use serde::Deserialize;
+use log::{debug, warn};
+use crate::layout::{self, Layout, Rotation};
+use crate::touch::{Digitizer, TouchMapping};
+use super::snapshot::{DisplaySnapshot, OutputState};
+use super::{run_command, DisplayError, DisplayManager};
+
+const BACKEND: &str = "wlroots";
+
+/// `XDG_CURRENT_DESKTOP` tokens of compositors implementing `zwlr_output_manager_v1`.
+pub(crate) const WLROOTS_DESKTOPS: &[&str] = &[
+    "SWAY", "HYPRLAND", "RIVER", "NIRI", "WAYFIRE", "LABWC", "WLROOTS",
+];
+
+#[derive(Debug, Deserialize)]
+struct WlrOutput {
+    name: String,
+    #[serde(default)]
+    enabled: bool,
+    #[serde(default)]
+    modes: Vec<WlrMode>,
+    #[serde(default)]
+    position: Option<WlrPosition>,
+    #[serde(default)]
+    transform: Option<String>,
+    #[serde(default)]
+    scale: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WlrPosition {
+    x: i32,
+    y: i32,
+}
+
+#[derive(Debug, Deserialize)]
+struct WlrMode {
+    width: i32,
+    height: i32,
+    #[serde(default)]
+    refresh: f64,
+    #[serde(default)]
+    preferred: bool,
+    #[serde(default)]
+    current: bool,
+}
+
+/// Display manager for wlroots-based compositors (Sway, Hyprland, river, niri, ...).
+///
+/// Drives `wlr-randr`, which speaks the `zwlr_output_manager_v1` protocol.
+pub(super) struct WlrootsManager;
+
+impl WlrootsManager {
+    fn query_outputs(&self) -> Result<Vec<WlrOutput>, DisplayError> {
+        debug!("Executing 'wlr-randr --json'");
+        let output = Command::new("wlr-randr")
+            .arg("--json")
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| DisplayError::Command(format!("Failed to execute wlr-randr: {}", e)))?;
+
+        if !output.status.success() {
+            return Err(DisplayError::Command(format!("wlr-randr exited with status: {}", output.status)));
+        }
+
+        parse_outputs(&String::from_utf8_lossy(&output.stdout))
+            .map_err(|e| DisplayError::Command(format!("Failed to parse wlr-randr output: {}", e)))
+    }
+
+    fn run(&self, args: &[String]) -> Result<(), DisplayError> {
+        debug!("Executing 'wlr-randr {}'", args.join(" "));
+        run_command("wlr-randr", args)
+    }
+}
+
+impl DisplayManager for WlrootsManager {
+    /// Enable, position, rotate and scale the panels as described by `layout` and turn the others off.
+    ///
+    /// Positions are derived from each panel's current mode (its preferred mode if it is off, which
+    /// is also the mode it gets when turned on), rotation and scale. wlr-randr cannot mirror outputs,
+    /// so a mirrored panel is placed on top of the panel it mirrors. If the current outputs can't be
+    /// queried, relative panels are still enabled but left wherever the compositor puts them.
+    ///
+    /// Kept external outputs are left out of the call, so they stay where they are, and the panels
+    /// are moved to keep the anchor panel at its current position next to them.
+    ///
+    /// Scales are rounded to the 1/120 steps fractional scaling works in.
+    fn apply_layout(&self, layout: &Layout) -> Result<(), DisplayError> {
+        let layout = &layout.with_scales(layout::fractional_scale);
+        let outputs = match self.query_outputs() {
+            Ok(outputs) => outputs,
+            Err(e) => {
+                warn!("Could not query outputs: {}", e);
+                Vec::new()
+            }
+        };
+
+        self.run(&layout_args(layout, &outputs, &positions(layout, &outputs)))
+    }
+
+    /// Map each digitizer with Sway's `map_to_output`; other wlroots compositors have no common
+    /// command for it, so they are skipped.
+    fn map_touchscreens(&self, mappings: &[TouchMapping]) -> Result<(), DisplayError> {
+        if std::env::var_os("SWAYSOCK").is_none() {
+            warn!("Mapping touch screens is only supported on Sway, skipping");
+            return Ok(());
+        }
+        for mapping in mappings {
+            let identifier = sway_identifier(&mapping.digitizer);
+            debug!("Executing 'swaymsg input {} map_to_output {}'", identifier, mapping.connector);
+            run_command("swaymsg", &["input", &identifier, "map_to_output", &mapping.connector])?;
+        }
+        Ok(())
+    }
+
+    /// Read the current mode, position, transform and scale of every output from `wlr-randr --json`.
+    fn snapshot(&self) -> Result<DisplaySnapshot, DisplayError> {
+        let outputs = self.query_outputs()?.iter().map(output_state).collect();
+        Ok(DisplaySnapshot { backend: BACKEND.to_string(), outputs })
+    }
+
+    /// Set every output back to its recorded mode, position, transform and scale in one call.
+    fn restore(&self, snapshot: &DisplaySnapshot) -> Result<(), DisplayError> {
+        snapshot.check_backend(BACKEND)?;
+        self.run(&restore_args(snapshot))
+    }
+}
+
+fn output_state(output: &WlrOutput) -> OutputState {
+    let Some(mode) = output.modes.iter().find(|m| m.current).filter(|_| output.enabled) else {
+        return OutputState::disabled(&output.name);
+    };
+    let (x, y) = output.position.as_ref().map(|p| (p.x, p.y)).unwrap_or_default();
+    OutputState {
+        connector: output.name.clone(),
+        enabled: true,
+        x,
+        y,
+        width: mode.width,
+        height: mode.height,
+        refresh: mode.refresh,
+        scale: output.scale.unwrap_or(1.0),
+        rotation: output.transform.as_deref().map(rotation).unwrap_or_default(),
+        primary: false,
+        mirror_of: None,
+    }
+}
+
+/// `wlr-randr` arguments bringing back the outputs of `snapshot`.
+fn restore_args(snapshot: &DisplaySnapshot) -> Vec<String> {
+    let mut args = Vec::new();
+    for o in &snapshot.outputs {
+        args.extend(["--output".to_string(), o.connector.clone()]);
+        if !o.enabled {
+            args.push("--off".to_string());
+            continue;
+        }
+        args.extend([
+            "--on".to_string(),
+            "--mode".to_string(),
+            format!("{}x{}@{:.3}Hz", o.width, o.height, o.refresh),
+            "--transform".to_string(),
+            transform(o.rotation).to_string(),
+            "--scale".to_string(),
+            o.scale.to_string(),
+            "--pos".to_string(),
+            format!("{},{}", o.x, o.y),
+        ]);
+    }
+    args
+}
+
+/// Sway's input identifier: decimal vendor and product ids and the name with spaces replaced.
+fn sway_identifier(digitizer: &Digitizer) -> String {
+    format!("{}:{}:{}", digitizer.vendor_id, digitizer.product_id, digitizer.name.replace(' ', "_"))
+}
+
+fn parse_outputs(json: &str) -> Result<Vec<WlrOutput>, serde_json::Error> {
+    serde_json::from_str(json)
+}
+
+/// Unrotated size of the mode `output` uses, or will use once enabled with `--preferred`.
+fn mode_size(output: &WlrOutput) -> Option<(i32, i32)> {
+    let mode = if output.enabled {
+        output.modes.iter().find(|m| m.current)
+    } else {
+        None
+    };
+    let mode = mode
+        .or_else(|| output.modes.iter().find(|m| m.preferred))
+        .or_else(|| output.modes.first())?;
+    Some((mode.width, mode.height))
+}
+
+fn transform(rotation: Rotation) -> &'static str {
+    match rotation {
+        Rotation::Normal => "normal",
+        Rotation::Left => "90",
+        Rotation::Inverted => "180",
+        Rotation::Right => "270",
+    }
+}
+
+/// The inverse of `transform`. Flipped transforms lose their flip.
+fn rotation(transform: &str) -> Rotation {
+    match transform.trim_start_matches("flipped").trim_start_matches('-') {
+        "90" => Rotation::Left,
+        "180" => Rotation::Inverted,
+        "270" => Rotation::Right,
+        _ => Rotation::Normal,
+    }
+}
+
+/// Where the enabled outputs of `layout` go, from the modes and positions of the current `outputs`;
+/// empty if that cannot be worked out.
+fn positions(layout: &Layout, outputs: &[WlrOutput]) -> HashMap<String, (i32, i32)> {
+    let mut positions = layout::place(layout, |o| {
+        let (width, height) = mode_size(outputs.iter().find(|w| w.name == o.connector)?)?;
+        layout::logical_size(width, height, o.rotation, o.scale)
+    }).unwrap_or_else(|e| {
+        warn!("Could not position the panels: {}", e);
+        HashMap::new()
+    });
+    layout::shift(&mut positions, layout::keep_offset(layout, |connector| {
+        let output = outputs.iter().find(|w| w.name == connector && w.enabled)?;
+        output.position.as_ref().map(|p| (p.x, p.y))
+    }));
+    positions
+}
+
+/// Build the `wlr-randr` arguments for `layout`, skipping `--pos` for outputs without a position.
+fn layout_args(layout: &Layout, outputs: &[WlrOutput], positions: &HashMap<String, (i32, i32)>) -> Vec<String> {
+    let mut args = Vec::new();
+    for o in &layout.outputs {
+        args.extend(["--output".to_string(), o.connector.clone()]);
+        if !o.enabled {
+            args.push("--off".to_string());
+            continue;
+        }
+
+        args.push("--on".to_string());
+        // Keep the current mode of outputs that are already on.
+        if !outputs.iter().any(|w| w.name == o.connector && w.enabled) {
+            args.push("--preferred".to_string());
+        }
+        args.extend(["--transform", transform(o.rotation), "--scale"].map(String::from));
+        args.push(o.scale.to_string());
+        match positions.get(&o.connector) {
+            Some((x, y)) => {
+                args.push("--pos".to_string());
+                args.push(format!("{},{}", x, y));
+            }
+            None if o.relative_to.is_none() => args.extend(["--pos", "0,0"].map(String::from)),
+            None => warn!("Could not determine the position of {}, not positioning it", o.connector),
+        }
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LayoutConfig;
+    use crate::drm::Panels;
+
+    const SAMPLE: &str = r#"[
+        {
+            "name": "eDP-1",
+            "enabled": true,
+            "modes": [
+                {"width": 1920, "height": 1200, "refresh": 60.0, "preferred": false, "current": false},
+                {"width": 2880, "height": 1800, "refresh": 120.0, "preferred": true, "current": true}
+            ],
+            "position": {"x": 0, "y": 0},
+            "transform": "normal",
+            "scale": 1.5
+        },
+        {
+            "name": "eDP-2",
+            "enabled": false,
+            "modes": [
+                {"width": 2880, "height": 1800, "refresh": 120.0, "preferred": true, "current": false}
+            ],
+            "position": {"x": 0, "y": 0},
+            "transform": "90",
+            "scale": 1.0
+        }
+    ]"#;
+
+    #[test]
+    fn parses_wlr_randr_json() {
+        let outputs = parse_outputs(SAMPLE).expect("parse");
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs[0].name, "eDP-1");
+        assert_eq!(outputs[1].modes.len(), 1);
+    }
+
+    #[test]
+    fn mode_size_uses_current_or_preferred_mode() {
+        let outputs = parse_outputs(SAMPLE).unwrap();
+        assert_eq!(mode_size(&outputs[0]), Some((2880, 1800)));
+        assert_eq!(mode_size(&outputs[1]), Some((2880, 1800)));
+    }
+
+    fn layout(name: &str) -> Layout {
+        Layout::resolve(name, &LayoutConfig::default(), &Panels::default(), (1.5, 1.5)).unwrap()
+    }
+
+    fn args_for(name: &str) -> String {
+        let outputs = parse_outputs(SAMPLE).unwrap();
+        let layout = layout(name);
+        layout_args(&layout, &outputs, &positions(&layout, &outputs)).join(" ")
+    }
+
+    #[test]
+    fn builds_args_for_layouts() {
+        assert_eq!(
+            args_for("below"),
+            "--output eDP-1 --on --transform normal --scale 1.5 --pos 0,0 \
+             --output eDP-2 --on --preferred --transform normal --scale 1.5 --pos 0,1200"
+        );
+        assert_eq!(
+            args_for("side-by-side"),
+            "--output eDP-1 --on --transform 270 --scale 1.5 --pos 0,0 \
+             --output eDP-2 --on --preferred --transform 270 --scale 1.5 --pos 1200,0"
+        );
+        assert_eq!(
+            args_for("top-only"),
+            "--output eDP-1 --on --transform normal --scale 1.5 --pos 0,0 --output eDP-2 --off"
+        );
+    }
+
+    #[test]
+    fn kept_external_output_stays_in_place() {
+        let outputs = parse_outputs(r#"[
+            {
+                "name": "DP-1",
+                "enabled": true,
+                "modes": [{"width": 2560, "height": 1440, "refresh": 60.0, "preferred": true, "current": true}],
+                "position": {"x": 0, "y": 0},
+                "scale": 1.0
+            },
+            {
+                "name": "eDP-1",
+                "enabled": true,
+                "modes": [{"width": 2880, "height": 1800, "refresh": 120.0, "preferred": true, "current": true}],
+                "position": {"x": 0, "y": 1440},
+                "scale": 1.5
+            },
+            {
+                "name": "eDP-2",
+                "enabled": false,
+                "modes": [{"width": 2880, "height": 1800, "refresh": 120.0, "preferred": true, "current": false}]
+            }
+        ]"#).unwrap();
+        let layout = layout("below").with_externals(&["DP-1".to_string()], &Default::default());
+        assert_eq!(
+            layout_args(&layout, &outputs, &positions(&layout, &outputs)).join(" "),
+            "--output eDP-1 --on --transform normal --scale 1.5 --pos 0,1440 \
+             --output eDP-2 --on --preferred --transform normal --scale 1.5 --pos 0,2640"
+        );
+    }
+
+    #[test]
+    fn snapshot_restores_modes_and_positions() {
+        let outputs = parse_outputs(SAMPLE).unwrap();
+        let snapshot = DisplaySnapshot {
+            backend: BACKEND.to_string(),
+            outputs: outputs.iter().map(output_state).collect(),
+        };
+        assert_eq!(snapshot.outputs[0].refresh, 120.0);
+        assert_eq!(snapshot.outputs[0].scale, 1.5);
+        assert!(!snapshot.outputs[1].enabled);
+        assert_eq!(
+            restore_args(&snapshot).join(" "),
+            "--output eDP-1 --on --mode 2880x1800@120.000Hz --transform normal --scale 1.5 --pos 0,0 \
+             --output eDP-2 --off"
+        );
+        assert_eq!(rotation("flipped-90"), Rotation::Left);
+    }
+
+    #[test]
+    fn sway_identifier_uses_decimal_ids() {
+        let digitizer = Digitizer {
+            name: "ELAN9008:00 04F3:425B".to_string(),
+            sys_name: "event7".to_string(),
+            vendor_id: 0x04f3,
+            product_id: 0x425b,
+            pen: false,
+            physical: "i2c-ELAN9008:00".to_string(),
+        };
+        assert_eq!(sway_identifier(&digitizer), "1267:16987:ELAN9008:00_04F3:425B");
+    }
+
+    #[test]
+    fn anchors_are_positioned_without_query() {
+        let args = layout_args(&layout("below"), &[], &HashMap::new()).join(" ");
+        assert_eq!(
+            args,
+            "--output eDP-1 --on --preferred --transform normal --scale 1.5 --pos 0,0 \
+             --output eDP-2 --on --preferred --transform normal --scale 1.5"
+        );
+    }
+}
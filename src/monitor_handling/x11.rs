@@ -0,0 +1,478 @@
+use std::process::{Command, Stdio};
+use log::debug;
+use crate::layout::{self, Layout, OutputLayout, Position, Rotation};
+use crate::touch::TouchMapping;
+use super::snapshot::{DisplaySnapshot, OutputState};
+use super::{check_enabled, run_command, DisplayError, DisplayManager};
+
+const BACKEND: &str = "x11";
+
+#[derive(Debug, PartialEq)]
+struct XrandrOutput {
+    name: String,
+    connected: bool,
+    /// Whether the output currently scans out a mode (i.e. has a `WxH+X+Y` geometry).
+    active: bool,
+}
+
+/// Display manager for Xorg sessions, driving `xrandr`.
+///
+/// Xorg names the panels after its video driver (`eDP-1` with modesetting, `eDP1` with the Intel
+/// driver, `eDP-1-1` behind PRIME), so the kernel connector names are mapped first. X11 has no
+/// per-output fractional scaling, so the scale is applied as an xrandr framebuffer transform.
+pub(super) struct X11Manager;
+
+impl X11Manager {
+    fn query(&self) -> Result<String, DisplayError> {
+        debug!("Executing 'xrandr --query'");
+        let output = Command::new("xrandr")
+            .arg("--query")
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| DisplayError::Command(format!("Failed to execute xrandr: {}", e)))?;
+
+        if !output.status.success() {
+            return Err(DisplayError::Command(format!("xrandr exited with status: {}", output.status)));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn query_outputs(&self) -> Result<Vec<XrandrOutput>, DisplayError> {
+        Ok(parse_outputs(&self.query()?))
+    }
+
+    fn run(&self, args: &[String]) -> Result<(), DisplayError> {
+        debug!("Executing 'xrandr {}'", args.join(" "));
+        run_command("xrandr", args)
+    }
+
+    /// Arguments configuring `output` as described by `layout`, with `reference` being the Xorg
+    /// name of the output it is positioned relative to. Outputs without one are put at `origin`.
+    fn output_args(
+        &self,
+        output: &XrandrOutput,
+        layout: &OutputLayout,
+        reference: Option<&str>,
+        origin: (i32, i32),
+    ) -> Result<Vec<String>, DisplayError> {
+        let mut args = vec!["--output".to_string(), output.name.clone()];
+        if !layout.enabled {
+            args.push("--off".to_string());
+            return Ok(args);
+        }
+
+        // Keep the current mode if the panel is already on.
+        if !output.active {
+            args.push("--auto".to_string());
+        }
+        if layout.primary {
+            args.push("--primary".to_string());
+        }
+        args.extend(["--rotate", rotation(layout.rotation), "--scale"].map(String::from));
+        args.push(xrandr_scale(layout.scale)?);
+
+        let relation = match layout.position {
+            Position::Origin => None,
+            Position::Below => Some("--below"),
+            Position::Above => Some("--above"),
+            Position::LeftOf => Some("--left-of"),
+            Position::RightOf => Some("--right-of"),
+            Position::Mirror => Some("--same-as"),
+        };
+        match (relation, reference) {
+            (Some(relation), Some(reference)) => args.extend([relation.to_string(), reference.to_string()]),
+            _ => args.extend(["--pos".to_string(), format!("{}x{}", origin.0, origin.1)]),
+        }
+        Ok(args)
+    }
+}
+
+impl DisplayManager for X11Manager {
+    /// Enable, rotate, scale and position the panels as described by `layout` in a single xrandr
+    /// call and turn the others off.
+    ///
+    /// Placement uses xrandr's relative options (`--below`, `--right-of`, `--same-as`, ...), which
+    /// take the rotation and scale of both outputs into account. Kept external outputs are left out
+    /// of the call, so they stay where they are, and the anchor panel stays at its current position
+    /// next to them.
+    fn apply_layout(&self, layout: &Layout) -> Result<(), DisplayError> {
+        self.run(&layout_args(layout, &self.query()?)?)
+    }
+
+    /// Check the outputs in a fresh `xrandr --query`, looking them up by their Xorg names.
+    fn verify_layout(&self, layout: &Layout) -> Result<(), DisplayError> {
+        let outputs = self.query_outputs()?;
+        for o in &layout.outputs {
+            let output = map_connector(&outputs, &o.connector)?;
+            check_enabled(&o.connector, o.enabled, output.active)?;
+        }
+        Ok(())
+    }
+
+    /// Map every X input device created for a digitizer (the pen gets several) to the panel's output.
+    fn map_touchscreens(&self, mappings: &[TouchMapping]) -> Result<(), DisplayError> {
+        let outputs = self.query_outputs()?;
+        debug!("Executing 'xinput list --name-only'");
+        let list = Command::new("xinput")
+            .args(["list", "--name-only"])
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| DisplayError::Command(format!("Failed to execute xinput: {}", e)))?;
+        let list = String::from_utf8_lossy(&list.stdout);
+
+        for mapping in mappings {
+            let output = map_connector(&outputs, &mapping.connector)?;
+            for device in xinput_devices(&list, &mapping.digitizer.name) {
+                debug!("Executing 'xinput map-to-output {} {}'", device, output.name);
+                run_command("xinput", &["map-to-output", device, &output.name])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read every connected output from `xrandr --query`, under its Xorg name.
+    fn snapshot(&self) -> Result<DisplaySnapshot, DisplayError> {
+        let outputs = parse_states(&self.query()?);
+        Ok(DisplaySnapshot { backend: BACKEND.to_string(), outputs })
+    }
+
+    /// Set every output back to its recorded mode, rate, position, rotation and scale in one call.
+    fn restore(&self, snapshot: &DisplaySnapshot) -> Result<(), DisplayError> {
+        snapshot.check_backend(BACKEND)?;
+        self.run(&restore_args(snapshot)?)
+    }
+}
+
+/// `xrandr` arguments for `layout`, given the outputs in `query`.
+fn layout_args(layout: &Layout, query: &str) -> Result<Vec<String>, DisplayError> {
+    let outputs = parse_outputs(query);
+    let states = parse_states(query);
+    let origin = layout::keep_offset(layout, |connector| {
+        let name = &map_connector(&outputs, connector).ok()?.name;
+        states.iter().find(|s| s.connector == *name && s.enabled).map(|s| (s.x, s.y))
+    });
+
+    let mut args = Vec::new();
+    for o in &layout.outputs {
+        let output = map_connector(&outputs, &o.connector)?;
+        let reference = match &o.relative_to {
+            Some(reference) => Some(map_connector(&outputs, reference)?.name.as_str()),
+            None => None,
+        };
+        args.extend(X11Manager.output_args(output, o, reference, origin)?);
+    }
+    Ok(args)
+}
+
+/// `xrandr` arguments bringing back the outputs of `snapshot`.
+fn restore_args(snapshot: &DisplaySnapshot) -> Result<Vec<String>, DisplayError> {
+    let mut args = Vec::new();
+    for o in &snapshot.outputs {
+        args.extend(["--output".to_string(), o.connector.clone()]);
+        if !o.enabled {
+            args.push("--off".to_string());
+            continue;
+        }
+        args.extend([
+            "--mode".to_string(),
+            format!("{}x{}", o.width, o.height),
+            "--rate".to_string(),
+            format!("{:.2}", o.refresh),
+            "--pos".to_string(),
+            format!("{}x{}", o.x, o.y),
+            "--rotate".to_string(),
+            rotation(o.rotation).to_string(),
+            "--scale".to_string(),
+            xrandr_scale(o.scale)?,
+        ]);
+        if o.primary {
+            args.push("--primary".to_string());
+        }
+    }
+    Ok(args)
+}
+
+/// X input devices created for the kernel device `name`, e.g. `<name> Stylus Pen (0)`.
+fn xinput_devices<'a>(list: &'a str, name: &str) -> Vec<&'a str> {
+    list.lines()
+        .map(str::trim)
+        .filter(|device| device.starts_with(name))
+        .collect()
+}
+
+fn rotation(rotation: Rotation) -> &'static str {
+    match rotation {
+        Rotation::Normal => "normal",
+        Rotation::Left => "left",
+        Rotation::Inverted => "inverted",
+        Rotation::Right => "right",
+    }
+}
+
+/// Parse the output lines of `xrandr --query`, skipping the screen and mode lines.
+fn parse_outputs(query: &str) -> Vec<XrandrOutput> {
+    query.lines()
+        .filter(|line| !line.starts_with(' ') && !line.starts_with('\t') && !line.starts_with("Screen "))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next()?.to_string();
+            let status = fields.next()?;
+            let connected = status == "connected";
+            let active = fields
+                .take_while(|f| !f.starts_with('('))
+                .any(|f| f.contains('x') && f.contains('+'));
+            Some(XrandrOutput { name, connected, active })
+        })
+        .collect()
+}
+
+/// Parse the configuration of every connected output from `xrandr --query`.
+///
+/// The scale is the mode's size divided by the size the output takes up on the screen, undoing
+/// `xrandr_scale`. xrandr mirrors by putting outputs on the same position, so no mirror source is
+/// recorded; restoring the positions mirrors them again.
+fn parse_states(query: &str) -> Vec<OutputState> {
+    let mut states: Vec<(OutputState, (i32, i32))> = Vec::new();
+    let mut in_connected = false;
+    for line in query.lines().filter(|line| !line.starts_with("Screen ")) {
+        if !line.starts_with(' ') && !line.starts_with('\t') {
+            let fields: Vec<&str> = line.split_whitespace().take_while(|f| !f.starts_with('(')).collect();
+            in_connected = fields.get(1) == Some(&"connected");
+            if !in_connected {
+                continue;
+            }
+            let mut state = OutputState::disabled(fields[0]);
+            state.primary = fields.contains(&"primary");
+            state.rotation = fields.iter()
+                .find_map(|f| match *f {
+                    "left" => Some(Rotation::Left),
+                    "inverted" => Some(Rotation::Inverted),
+                    "right" => Some(Rotation::Right),
+                    _ => None,
+                })
+                .unwrap_or_default();
+            let geometry = fields.iter().find_map(|f| parse_geometry(f));
+            if let Some((_, _, x, y)) = geometry {
+                state.enabled = true;
+                state.x = x;
+                state.y = y;
+            }
+            states.push((state, geometry.map(|(w, h, _, _)| (w, h)).unwrap_or_default()));
+        } else if in_connected
+            && let Some((state, _)) = states.last_mut() {
+            // `   2880x1800    120.00*+  60.00 +`, the active rate is marked with `*`.
+            let mut fields = line.split_whitespace();
+            let Some((width, height)) = fields.next().and_then(parse_size) else { continue };
+            if let Some(rate) = fields.find(|f| f.contains('*'))
+                && let Ok(refresh) = rate.trim_end_matches(['*', '+']).parse() {
+                state.width = width;
+                state.height = height;
+                state.refresh = refresh;
+            }
+        }
+    }
+
+    states.into_iter()
+        .map(|(mut state, (width, height))| {
+            let shown = if state.rotation.swaps_axes() { height } else { width };
+            if state.enabled && shown > 0 && state.width > 0 {
+                state.scale = (state.width as f64 / shown as f64 * 1000.0).round() / 1000.0;
+            }
+            state
+        })
+        .collect()
+}
+
+/// Parse a mode name like `2880x1800` (or `1920x1080i`, `2880x1800_60.00`).
+fn parse_size(mode: &str) -> Option<(i32, i32)> {
+    let (width, rest) = mode.split_once('x')?;
+    let height: String = rest.chars().take_while(char::is_ascii_digit).collect();
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+/// Parse an output geometry like `1920x1200+0+0`.
+fn parse_geometry(field: &str) -> Option<(i32, i32, i32, i32)> {
+    let (size, position) = field.split_once('+')?;
+    let (x, y) = position.split_once('+')?;
+    let (width, height) = size.split_once('x')?;
+    Some((width.parse().ok()?, height.parse().ok()?, x.parse().ok()?, y.parse().ok()?))
+}
+
+/// Find the Xorg output for a kernel connector name such as `eDP-2`.
+///
+/// Prefers an exact match after ignoring dashes (`eDP2`), then, for panels only, falls back to the
+/// n-th connected eDP output, which covers driver-specific names like `eDP-1-2`. External outputs
+/// have to match by name, as their numbers say nothing about their order.
+fn map_connector<'a>(outputs: &'a [XrandrOutput], connector: &str) -> Result<&'a XrandrOutput, DisplayError> {
+    let normalized = connector.replace('-', "");
+    if let Some(output) = outputs.iter().find(|o| o.name.replace('-', "") == normalized) {
+        return Ok(output);
+    }
+
+    let not_found = || DisplayError::ConnectorNotFound(connector.to_string());
+    if !connector.starts_with("eDP") {
+        return Err(not_found());
+    }
+    let index = connector.rsplit('-').next()
+        .and_then(|n| n.parse::<usize>().ok())
+        .and_then(|n| n.checked_sub(1));
+    index
+        .and_then(|i| outputs.iter().filter(|o| o.connected && o.name.starts_with("eDP")).nth(i))
+        .ok_or_else(not_found)
+}
+
+/// xrandr scales the framebuffer, so a UI scale of 1.5 means showing 1/1.5 of the mode.
+fn xrandr_scale(scale: f64) -> Result<String, DisplayError> {
+    if scale > 0.0 {
+        let inverse = 1.0 / scale;
+        Ok(format!("{:.4}x{:.4}", inverse, inverse))
+    } else {
+        Err(DisplayError::InvalidScale(scale.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LayoutConfig;
+    use crate::drm::Panels;
+
+    const QUERY: &str = "\
+Screen 0: minimum 320 x 200, current 2880 x 1800, maximum 16384 x 16384
+eDP-1-1 connected primary 2880x1800+0+0 (normal left inverted right x axis y axis) 310mm x 190mm
+   2880x1800    120.00*+  60.00 +
+   1920x1200     60.00
+eDP-1-2 connected (normal left inverted right x axis y axis)
+   2880x1800    120.00 +  60.00 +
+DP-1-1 disconnected (normal left inverted right x axis y axis)
+";
+
+    #[test]
+    fn parses_outputs_and_activity() {
+        let outputs = parse_outputs(QUERY);
+        assert_eq!(outputs, vec![
+            XrandrOutput { name: "eDP-1-1".into(), connected: true, active: true },
+            XrandrOutput { name: "eDP-1-2".into(), connected: true, active: false },
+            XrandrOutput { name: "DP-1-1".into(), connected: false, active: false },
+        ]);
+    }
+
+    #[test]
+    fn maps_connectors_by_name_or_position() {
+        let prime = parse_outputs(QUERY);
+        assert_eq!(map_connector(&prime, "eDP-1").unwrap().name, "eDP-1-1");
+        assert_eq!(map_connector(&prime, "eDP-2").unwrap().name, "eDP-1-2");
+
+        let intel = parse_outputs("eDP1 connected 2880x1800+0+0 (normal)\neDP2 connected (normal)\n");
+        assert_eq!(map_connector(&intel, "eDP-2").unwrap().name, "eDP2");
+
+        assert!(map_connector(&intel, "eDP-3").is_err());
+
+        // `DP-1-2` behind PRIME is not the second panel.
+        let external = parse_outputs(&format!("{}DP-1-2 connected 2560x1440+0+0 (normal)\n", QUERY));
+        assert!(matches!(map_connector(&external, "DP-2"), Err(DisplayError::ConnectorNotFound(_))));
+        assert_eq!(map_connector(&external, "eDP-2").unwrap().name, "eDP-1-2");
+    }
+
+    #[test]
+    fn snapshot_reads_and_restores_states() {
+        let query = "\
+Screen 0: minimum 320 x 200, current 3120 x 1920, maximum 16384 x 16384
+eDP-1 connected primary 1920x1200+0+0 (normal left inverted right x axis y axis) 310mm x 190mm
+   2880x1800    120.00*+  60.00 +
+eDP-2 connected 1200x1920+1920+0 left (normal left inverted right x axis y axis) 310mm x 190mm
+   2880x1800    120.00 +  60.00*+
+DP-1 connected (normal left inverted right x axis y axis)
+   3840x2160     60.00 +
+HDMI-1 disconnected (normal left inverted right x axis y axis)
+";
+        let snapshot = DisplaySnapshot { backend: BACKEND.to_string(), outputs: parse_states(query) };
+        assert_eq!(snapshot.outputs.len(), 3);
+        let top = &snapshot.outputs[0];
+        assert!(top.enabled && top.primary);
+        assert_eq!((top.width, top.height, top.refresh, top.scale), (2880, 1800, 120.0, 1.5));
+        let bottom = &snapshot.outputs[1];
+        assert_eq!((bottom.x, bottom.rotation, bottom.refresh, bottom.scale), (1920, Rotation::Left, 60.0, 1.5));
+        assert!(!snapshot.outputs[2].enabled);
+
+        assert_eq!(
+            restore_args(&snapshot).unwrap().join(" "),
+            "--output eDP-1 --mode 2880x1800 --rate 120.00 --pos 0x0 --rotate normal --scale 0.6667x0.6667 --primary \
+             --output eDP-2 --mode 2880x1800 --rate 60.00 --pos 1920x0 --rotate left --scale 0.6667x0.6667 \
+             --output DP-1 --off"
+        );
+    }
+
+    #[test]
+    fn xrandr_scale_is_inverse_of_ui_scale() {
+        assert_eq!(xrandr_scale(1.5).unwrap(), "0.6667x0.6667");
+        assert_eq!(xrandr_scale(1.0).unwrap(), "1.0000x1.0000");
+        assert!(xrandr_scale(0.0).is_err());
+    }
+
+    #[test]
+    fn finds_xinput_devices_of_digitizer() {
+        let list = "Virtual core pointer\nELAN9008:00 04F3:425B\nELAN9008:00 04F3:425B Stylus Pen (0)\n\
+                    ELAN9008:00 04F3:425B Stylus Eraser (0)\nELAN9009:00 04F3:425A\n";
+        assert_eq!(xinput_devices(list, "ELAN9008:00 04F3:425B"), vec![
+            "ELAN9008:00 04F3:425B", "ELAN9008:00 04F3:425B Stylus Pen (0)", "ELAN9008:00 04F3:425B Stylus Eraser (0)",
+        ]);
+        assert!(xinput_devices(list, "ELAN9010").is_empty());
+    }
+
+    fn args_for(name: &str) -> String {
+        let outputs = parse_outputs(QUERY);
+        let layout = Layout::resolve(name, &LayoutConfig::default(), &Panels::default(), (1.5, 1.5)).unwrap();
+        let top = &layout.outputs[0];
+        let bottom = &layout.outputs[1];
+        let reference = bottom.relative_to.as_ref().map(|_| "eDP-1-1");
+        let mut args = X11Manager.output_args(&outputs[0], top, None, (0, 0)).unwrap();
+        args.extend(X11Manager.output_args(&outputs[1], bottom, reference, (0, 0)).unwrap());
+        args.join(" ")
+    }
+
+    #[test]
+    fn builds_relative_placement_args() {
+        assert_eq!(
+            args_for("below"),
+            "--output eDP-1-1 --primary --rotate normal --scale 0.6667x0.6667 --pos 0x0 \
+             --output eDP-1-2 --auto --rotate normal --scale 0.6667x0.6667 --below eDP-1-1"
+        );
+        assert_eq!(
+            args_for("side-by-side"),
+            "--output eDP-1-1 --primary --rotate right --scale 0.6667x0.6667 --pos 0x0 \
+             --output eDP-1-2 --auto --rotate right --scale 0.6667x0.6667 --right-of eDP-1-1"
+        );
+        assert_eq!(
+            args_for("mirror"),
+            "--output eDP-1-1 --primary --rotate normal --scale 0.6667x0.6667 --pos 0x0 \
+             --output eDP-1-2 --auto --rotate normal --scale 0.6667x0.6667 --same-as eDP-1-1"
+        );
+        assert_eq!(
+            args_for("bottom-only"),
+            "--output eDP-1-1 --off \
+             --output eDP-1-2 --auto --primary --rotate normal --scale 0.6667x0.6667 --pos 0x0"
+        );
+    }
+
+    #[test]
+    fn kept_external_output_stays_in_place() {
+        let query = "\
+Screen 0: minimum 320 x 200, current 2560 x 2640, maximum 16384 x 16384
+DP-1 connected 2560x1440+0+0 (normal left inverted right x axis y axis) 600mm x 340mm
+   2560x1440     60.00*+
+eDP-1 connected primary 1920x1200+0+1440 (normal left inverted right x axis y axis) 310mm x 190mm
+   2880x1800    120.00*+  60.00 +
+eDP-2 connected (normal left inverted right x axis y axis)
+   2880x1800    120.00 +  60.00 +
+";
+        let layout = Layout::resolve("below", &LayoutConfig::default(), &Panels::default(), (1.5, 1.5)).unwrap()
+            .with_externals(&["DP-1".to_string()], &Default::default());
+        assert_eq!(
+            layout_args(&layout, query).unwrap().join(" "),
+            "--output eDP-1 --primary --rotate normal --scale 0.6667x0.6667 --pos 0x1440 \
+             --output eDP-2 --auto --rotate normal --scale 0.6667x0.6667 --below eDP-1"
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use futures::StreamExt;
+use log::{debug, error, info, warn};
+use tokio::sync::mpsc::UnboundedSender;
+use zbus::{proxy, Connection};
+use crate::layout::Rotation;
+
+#[proxy(
+    interface = "net.hadess.SensorProxy",
+    default_service = "net.hadess.SensorProxy",
+    default_path = "/net/hadess/SensorProxy"
+)]
+trait SensorProxy {
+    fn claim_accelerometer(&self) -> zbus::Result<()>;
+
+    fn release_accelerometer(&self) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn has_accelerometer(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn accelerometer_orientation(&self) -> zbus::Result<String>;
+}
+
+/// Map an iio-sensor-proxy orientation to the rotation that keeps the content upright.
+///
+/// `left-up` means the left edge of the panel points up, so the content has to turn a quarter
+/// counter-clockwise. `undefined` (e.g. lying flat) has no rotation.
+pub fn rotation_for(orientation: &str) -> Option<Rotation> {
+    match orientation {
+        "normal" => Some(Rotation::Normal),
+        "left-up" => Some(Rotation::Left),
+        "bottom-up" => Some(Rotation::Inverted),
+        "right-up" => Some(Rotation::Right),
+        _ => None,
+    }
+}
+
+/// Watch the accelerometer through iio-sensor-proxy on the system bus and send every new rotation.
+///
+/// Does nothing but log if iio-sensor-proxy or the accelerometer is missing.
+pub async fn monitor_orientation(sender: UnboundedSender<Rotation>) {
+    let connection = match Connection::system().await {
+        Ok(connection) => connection,
+        Err(e) => {
+            error!("Failed to connect to the system bus, not following orientation: {}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = watch_orientation(&connection, sender).await {
+        warn!("Not following orientation: {}", e);
+    }
+}
+
+/// Claim the accelerometer on `connection` and forward orientation changes until `sender` is closed
+/// or the sensor goes away.
+///
+/// The current orientation is sent first, so the receiver starts from a known state.
+pub async fn watch_orientation(connection: &Connection, sender: UnboundedSender<Rotation>) -> zbus::Result<()> {
+    let proxy = SensorProxyProxy::new(connection).await?;
+    if !proxy.has_accelerometer().await? {
+        return Err(zbus::Error::Failure("no accelerometer".to_string()));
+    }
+
+    let mut changes = proxy.receive_accelerometer_orientation_changed().await;
+    proxy.claim_accelerometer().await?;
+    info!("Following accelerometer orientation");
+
+    let mut last = None;
+    let mut orientation = proxy.accelerometer_orientation().await;
+    loop {
+        match orientation {
+            Ok(orientation) => {
+                debug!("Accelerometer orientation: {}", orientation);
+                if let Some(rotation) = rotation_for(&orientation)
+                    && last != Some(rotation) {
+                    last = Some(rotation);
+                    if sender.send(rotation).is_err() {
+                        break;
+                    }
+                }
+            }
+            Err(e) => warn!("Failed to read accelerometer orientation: {}", e),
+        }
+
+        let Some(change) = changes.next().await else { break };
+        orientation = change.get().await;
+    }
+
+    // The claim is dropped with the connection anyway, this just releases it early.
+    let _ = proxy.release_accelerometer().await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+    use zbus::zvariant::Value;
+    use crate::test_bus::test_bus_or_skip;
+
+    struct FakeSensorProxy {
+        orientation: String,
+        claims: Arc<Mutex<i32>>,
+    }
+
+    #[zbus::interface(name = "net.hadess.SensorProxy")]
+    impl FakeSensorProxy {
+        fn claim_accelerometer(&self) {
+            *self.claims.lock().unwrap() += 1;
+        }
+
+        fn release_accelerometer(&self) {
+            *self.claims.lock().unwrap() -= 1;
+        }
+
+        #[zbus(property)]
+        fn has_accelerometer(&self) -> bool {
+            true
+        }
+
+        #[zbus(property)]
+        fn accelerometer_orientation(&self) -> String {
+            self.orientation.clone()
+        }
+
+        #[zbus(property)]
+        fn set_accelerometer_orientation(&mut self, orientation: String) {
+            self.orientation = orientation;
+        }
+    }
+
+    async fn next(receiver: &mut mpsc::UnboundedReceiver<Rotation>) -> Option<Rotation> {
+        tokio::time::timeout(Duration::from_secs(5), receiver.recv()).await.expect("timeout")
+    }
+
+    #[test]
+    fn maps_orientations() {
+        assert_eq!(rotation_for("normal"), Some(Rotation::Normal));
+        assert_eq!(rotation_for("left-up"), Some(Rotation::Left));
+        assert_eq!(rotation_for("bottom-up"), Some(Rotation::Inverted));
+        assert_eq!(rotation_for("right-up"), Some(Rotation::Right));
+        assert_eq!(rotation_for("undefined"), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn forwards_orientation_changes() {
+        let bus = test_bus_or_skip!();
+        let claims = Arc::new(Mutex::new(0));
+        let fake = FakeSensorProxy { orientation: "normal".to_string(), claims: claims.clone() };
+        let _service = zbus::blocking::connection::Builder::address(bus.address()).unwrap()
+            .name("net.hadess.SensorProxy").unwrap()
+            .serve_at("/net/hadess/SensorProxy", fake).unwrap()
+            .build()
+            .unwrap();
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let connection: Connection = bus.connect().into();
+        let watcher = tokio::spawn(async move { watch_orientation(&connection, sender).await });
+
+        assert_eq!(next(&mut receiver).await, Some(Rotation::Normal));
+        assert_eq!(*claims.lock().unwrap(), 1);
+
+        let client = bus.connect();
+        let set = |orientation: &str| {
+            client.call_method(
+                Some("net.hadess.SensorProxy"),
+                "/net/hadess/SensorProxy",
+                Some("org.freedesktop.DBus.Properties"),
+                "Set",
+                &("net.hadess.SensorProxy", "AccelerometerOrientation", Value::from(orientation)),
+            ).expect("set orientation");
+        };
+        set("left-up");
+        assert_eq!(next(&mut receiver).await, Some(Rotation::Left));
+
+        // `undefined` and the repeated `left-up` are not forwarded.
+        for orientation in ["undefined", "left-up", "bottom-up"] {
+            set(orientation);
+        }
+        assert_eq!(next(&mut receiver).await, Some(Rotation::Inverted));
+
+        drop(receiver);
+        watcher.abort();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn missing_sensor_proxy_is_an_error() {
+        let bus = test_bus_or_skip!();
+        let (sender, _receiver) = mpsc::unbounded_channel();
+        let connection: Connection = bus.connect().into();
+        assert!(watch_orientation(&connection, sender).await.is_err());
+    }
+}
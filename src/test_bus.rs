@@ -0,0 +1,66 @@
+//! Private D-Bus daemon for tests that talk to stand-in services.
+
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use zbus::blocking::{connection, Connection};
+
+pub struct TestBus {
+    daemon: Child,
+    address: String,
+}
+
+impl TestBus {
+    /// Start a private session bus, or `None` if `dbus-daemon` is not available.
+    pub fn start() -> Option<Self> {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take()?).read_line(&mut address).ok()?;
+        let address = address.trim().to_string();
+        if address.is_empty() {
+            let _ = daemon.kill();
+            return None;
+        }
+
+        Some(TestBus { daemon, address })
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Open a new client connection to the private bus.
+    pub fn connect(&self) -> Connection {
+        connection::Builder::address(self.address())
+            .expect("Invalid bus address")
+            .build()
+            .expect("Failed to connect to test bus")
+    }
+}
+
+impl Drop for TestBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+/// Start a [`TestBus`] or return early from the calling test when `dbus-daemon` is missing.
+macro_rules! test_bus_or_skip {
+    () => {
+        match $crate::test_bus::TestBus::start() {
+            Some(bus) => bus,
+            None => {
+                eprintln!("dbus-daemon not available, skipping");
+                return;
+            }
+        }
+    };
+}
+
+pub(crate) use test_bus_or_skip;
@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use evdev::Device;
+use lazy_static::lazy_static;
+use log::{debug, error, info, warn};
+use crate::config::{Config, TouchConfig};
+use crate::drm::{self, Panels};
+
+lazy_static! {
+    /// Digitizer nodes we hold an exclusive grab on; dropping a device releases its grab.
+    static ref INHIBITED: Mutex<Vec<(String, Device)>> = Mutex::new(Vec::new());
+}
+
+/// `BUS_I2C` from `linux/input.h`; the panel digitizers sit on I2C, external touch screens on USB.
+const BUS_I2C: u16 = 0x18;
+
+/// A touch screen or pen input node of one of the panel digitizers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Digitizer {
+    /// Kernel device name, e.g. `ELAN9008:00 04F3:425B`.
+    pub name: String,
+    /// Name of the evdev node, e.g. `event7`.
+    pub sys_name: String,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    /// Whether this is the pen (tablet) node rather than the touch screen.
+    pub pen: bool,
+    /// The physical device the node belongs to; touch and pen of one digitizer share it.
+    pub physical: String,
+}
+
+/// A digitizer node and the connector of the panel it belongs to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TouchMapping {
+    pub digitizer: Digitizer,
+    pub connector: String,
+}
+
+/// Find the touch and pen nodes of the internal digitizers.
+///
+/// The digitizers are I2C HID devices and do not hang below the DRM connectors of the panels, so
+/// they are matched to the panels by [`assign`] rather than through their udev parents.
+pub fn discover_digitizers() -> Vec<Digitizer> {
+    let Ok(mut enumerator) = udev::Enumerator::new() else {
+        warn!("Failed to create udev enumerator");
+        return Vec::new();
+    };
+    if enumerator.match_subsystem("input").is_err() {
+        return Vec::new();
+    }
+    let Ok(devices) = enumerator.scan_devices() else {
+        warn!("Failed to scan input devices");
+        return Vec::new();
+    };
+
+    let digitizers: Vec<Digitizer> = devices.filter_map(|device| digitizer_from(&device)).collect();
+    for digitizer in &digitizers {
+        debug!("Found digitizer {} ({}) on {}", digitizer.name, digitizer.sys_name, digitizer.physical);
+    }
+    digitizers
+}
+
+fn digitizer_from(device: &udev::Device) -> Option<Digitizer> {
+    let sys_name = device.sysname().to_str()?;
+    if !sys_name.starts_with("event") {
+        return None;
+    }
+    let flag = |key: &str| device.property_value(key).is_some_and(|v| v == "1");
+    let touch = flag("ID_INPUT_TOUCHSCREEN");
+    let pen = !touch && flag("ID_INPUT_TABLET");
+    if !touch && !pen {
+        return None;
+    }
+
+    // eventN → inputN → HID device → I2C device
+    let input = device.parent()?;
+    let attribute = |name: &str| input.attribute_value(name).and_then(|v| v.to_str()).map(|v| v.trim().to_string());
+    let hex = |name: &str| attribute(name).and_then(|v| u16::from_str_radix(&v, 16).ok());
+    if hex("id/bustype")? != BUS_I2C {
+        return None;
+    }
+
+    let hid = input.parent()?;
+    let physical = hid.parent().unwrap_or(hid);
+    Some(Digitizer {
+        name: attribute("name")?,
+        sys_name: sys_name.to_string(),
+        vendor_id: hex("id/vendor")?,
+        product_id: hex("id/product")?,
+        pen,
+        physical: physical.sysname().to_string_lossy().into_owned(),
+    })
+}
+
+/// Match the digitizers to the panels.
+///
+/// A digitizer whose name contains the `[touch]` override for a panel goes to that panel. The
+/// others are assigned in the order of their physical devices (`ELAN9008` before `ELAN9009`), the
+/// first to the top panel. Touch and pen nodes of the same digitizer always go to the same panel.
+pub fn assign(digitizers: &[Digitizer], panels: &Panels, config: &TouchConfig) -> Vec<TouchMapping> {
+    let mut groups: BTreeMap<&str, Vec<&Digitizer>> = BTreeMap::new();
+    for digitizer in digitizers {
+        groups.entry(digitizer.physical.as_str()).or_default().push(digitizer);
+    }
+    let mut unassigned: Vec<&str> = groups.keys().copied().collect();
+
+    let mut pick = |wanted: Option<&String>| -> Option<&str> {
+        let wanted = wanted?.to_lowercase();
+        let index = unassigned.iter().position(|physical| {
+            physical.to_lowercase().contains(&wanted)
+                || groups[physical].iter().any(|d| d.name.to_lowercase().contains(&wanted))
+        });
+        if index.is_none() {
+            warn!("No digitizer matches '{}'", wanted);
+        }
+        index.map(|i| unassigned.remove(i))
+    };
+    let top = pick(config.top.as_ref());
+    let bottom = pick(config.bottom.as_ref());
+
+    let mut next = || (!unassigned.is_empty()).then(|| unassigned.remove(0));
+    let top = top.or_else(&mut next);
+    let bottom = bottom.or_else(&mut next);
+
+    [(top, &panels.top), (bottom, &panels.bottom)]
+        .into_iter()
+        .filter_map(|(physical, connector)| Some((groups.get(physical?)?, connector)))
+        .flat_map(|(group, connector)| {
+            group.iter().map(|d| TouchMapping { digitizer: (*d).clone(), connector: connector.clone() })
+        })
+        .collect()
+}
+
+fn node_path(digitizer: &Digitizer) -> PathBuf {
+    Path::new("/dev/input").join(&digitizer.sys_name)
+}
+
+/// The digitizer nodes of the bottom panel.
+pub fn bottom_digitizers(panels: &Panels, config: &TouchConfig) -> Vec<Digitizer> {
+    assign(&discover_digitizers(), panels, config)
+        .into_iter()
+        .filter(|m| m.connector == panels.bottom)
+        .map(|m| m.digitizer)
+        .collect()
+}
+
+/// Stop `digitizers` from delivering events by holding an exclusive evdev grab on them.
+///
+/// Used for the bottom panel while the keyboard lies on it, which otherwise causes ghost touches.
+/// Nodes that are already inhibited are left alone.
+pub fn inhibit(digitizers: &[Digitizer]) {
+    let mut inhibited = INHIBITED.lock().unwrap();
+    for digitizer in digitizers {
+        if inhibited.iter().any(|(sys_name, _)| *sys_name == digitizer.sys_name) {
+            continue;
+        }
+        let path = node_path(digitizer);
+        match Device::open(&path).and_then(|mut device| device.grab().map(|_| device)) {
+            Ok(device) => {
+                info!("Inhibited {} ({})", digitizer.name, digitizer.sys_name);
+                inhibited.push((digitizer.sys_name.clone(), device));
+            }
+            Err(e) => error!("Failed to inhibit {:?}: {}", path, e),
+        }
+    }
+}
+
+/// Release every grab taken by [`inhibit`].
+pub fn release() {
+    let mut inhibited = INHIBITED.lock().unwrap();
+    for (sys_name, mut device) in inhibited.drain(..) {
+        match device.ungrab() {
+            Ok(()) => info!("Released {}", sys_name),
+            // Closing the device below drops the grab anyway.
+            Err(e) => debug!("Failed to ungrab {}: {}", sys_name, e),
+        }
+    }
+}
+
+/// The digitizer nodes currently inhibited by [`inhibit`].
+pub fn inhibited() -> Vec<String> {
+    INHIBITED.lock().unwrap().iter().map(|(sys_name, _)| sys_name.clone()).collect()
+}
+
+/// Print whether the bottom panel's touch is inhibited.
+///
+/// `inhibited` are the digitizer nodes the daemon holds grabbed, `None` if no daemon runs.
+pub fn print_status(config: &Config, inhibited: Option<&[String]>) {
+    println!(
+        "Inhibit bottom touch while attached: {}",
+        if config.touch.inhibit_bottom { "enabled" } else { "disabled" }
+    );
+
+    let panels = drm::discover_panels(Path::new(drm::DRM_SYSFS), &config.panels);
+    for mapping in assign(&discover_digitizers(), &panels, &config.touch) {
+        let digitizer = &mapping.digitizer;
+        let status = match inhibited {
+            Some(inhibited) if inhibited.contains(&digitizer.sys_name) => "inhibited",
+            Some(_) => "active",
+            None => "unknown (daemon not running)",
+        };
+        println!(
+            "{} {} ({}, {}): {}",
+            digitizer.sys_name,
+            digitizer.name,
+            mapping.connector,
+            if digitizer.pen { "pen" } else { "touch" },
+            status
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digitizer(physical: &str, sys_name: &str, pen: bool) -> Digitizer {
+        Digitizer {
+            name: format!("{} 04F3:425B{}", physical.trim_start_matches("i2c-"), if pen { " Stylus" } else { "" }),
+            sys_name: sys_name.to_string(),
+            vendor_id: 0x04f3,
+            product_id: 0x425b,
+            pen,
+            physical: physical.to_string(),
+        }
+    }
+
+    fn connectors(mappings: &[TouchMapping]) -> Vec<(&str, &str)> {
+        mappings.iter().map(|m| (m.digitizer.sys_name.as_str(), m.connector.as_str())).collect()
+    }
+
+    fn sample() -> Vec<Digitizer> {
+        vec![
+            digitizer("i2c-ELAN9009:00", "event9", false),
+            digitizer("i2c-ELAN9008:00", "event7", false),
+            digitizer("i2c-ELAN9008:00", "event8", true),
+            digitizer("i2c-ELAN9009:00", "event10", true),
+        ]
+    }
+
+    #[test]
+    fn assigns_in_physical_order_keeping_pen_with_touch() {
+        let mappings = assign(&sample(), &Panels::default(), &TouchConfig::default());
+        assert_eq!(connectors(&mappings), vec![
+            ("event7", "eDP-1"), ("event8", "eDP-1"), ("event9", "eDP-2"), ("event10", "eDP-2"),
+        ]);
+    }
+
+    #[test]
+    fn config_overrides_assignment() {
+        let config = TouchConfig { bottom: Some("elan9008".to_string()), ..TouchConfig::default() };
+        let mappings = assign(&sample(), &Panels::default(), &config);
+        assert_eq!(connectors(&mappings), vec![
+            ("event9", "eDP-1"), ("event10", "eDP-1"), ("event7", "eDP-2"), ("event8", "eDP-2"),
+        ]);
+    }
+
+    #[test]
+    fn missing_nodes_are_not_inhibited() {
+        let missing = digitizer("i2c-ELAN9009:00", "does-not-exist", false);
+        inhibit(std::slice::from_ref(&missing));
+        assert!(INHIBITED.lock().unwrap().is_empty());
+        release();
+    }
+
+    #[test]
+    fn single_digitizer_goes_to_top_panel() {
+        let mappings = assign(&sample()[1..3], &Panels::default(), &TouchConfig::default());
+        assert_eq!(connectors(&mappings), vec![("event7", "eDP-1"), ("event8", "eDP-1")]);
+        assert!(assign(&[], &Panels::default(), &TouchConfig::default()).is_empty());
+    }
+}
@@ -1,6 +1,6 @@
 use udev::Event;
 use std::ffi::OsString;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use crate::config::DeviceConfig;
 
 pub fn is_device_duo_keyboard(device: &udev::Device, config: &DeviceConfig) -> bool {
@@ -17,15 +17,14 @@ pub fn is_device_duo_keyboard(device: &udev::Device, config: &DeviceConfig) -> b
 
 pub fn is_it_duo_keyboard(event: &Event, known_devpath: &Option<OsString>, config: &DeviceConfig) -> Option<OsString> {
     // If we know the devpath (from a previous ADD), and this is a REMOVE event matching that path, return it.
-    if let Some(known) = known_devpath {
-        if event.event_type() == udev::EventType::Remove {
-            let known_path = Path::new(known);
-            let event_path = Path::new(event.devpath());
-
-            // Check if paths match, or if one is a sub-path of the other (e.g. interface vs device)
-            if known_path == event_path || known_path.starts_with(event_path) || event_path.starts_with(known_path) {
-                return Some(known.clone());
-            }
+    if let Some(known) = known_devpath
+        && event.event_type() == udev::EventType::Remove {
+        let known_path = Path::new(known);
+        let event_path = Path::new(event.devpath());
+
+        // Check if paths match, or if one is a sub-path of the other (e.g. interface vs device)
+        if known_path == event_path || known_path.starts_with(event_path) || event_path.starts_with(known_path) {
+            return Some(known.clone());
         }
     }
 
@@ -45,7 +44,7 @@ pub fn is_it_duo_keyboard(event: &Event, known_devpath: &Option<OsString>, confi
     }
 }
 
-pub fn find_keyboard_event_path(config: &DeviceConfig) -> Option<std::path::PathBuf> {
+pub fn find_keyboard_event_path(config: &DeviceConfig) -> Option<PathBuf> {
     let mut enumerator = udev::Enumerator::new().ok()?;
     enumerator.match_subsystem("input").ok()?;
 
@@ -56,10 +55,9 @@ pub fn find_keyboard_event_path(config: &DeviceConfig) -> Option<std::path::Path
             if is_device_duo_keyboard(&dev, config) {
                 // If we found the keyboard parent, the original 'device'
                 // is likely the /dev/input/eventX node
-                if let Some(devnode) = device.devnode() {
-                    if devnode.to_string_lossy().contains("event") {
-                        return Some(devnode.to_path_buf());
-                    }
+                if let Some(devnode) = device.devnode()
+                    && devnode.to_string_lossy().contains("event") {
+                    return Some(devnode.to_path_buf());
                 }
             }
             current_dev = dev.parent();
@@ -68,16 +66,72 @@ pub fn find_keyboard_event_path(config: &DeviceConfig) -> Option<std::path::Path
     None
 }
 
+/// Bus types in `HID_ID`, from `<linux/input.h>`.
+const BUS_USB: u16 = 0x03;
+const BUS_BLUETOOTH: u16 = 0x05;
+
+/// The `/dev/hidraw*` node taking the keyboard's reports: the one of USB interface `interface` while
+/// the keyboard is attached, else the one of its Bluetooth connection.
+pub fn find_keyboard_hidraw(config: &DeviceConfig, interface: u8) -> Option<PathBuf> {
+    let mut enumerator = udev::Enumerator::new().ok()?;
+    enumerator.match_subsystem("hidraw").ok()?;
+
+    let mut bluetooth = None;
+    for device in enumerator.scan_devices().ok()? {
+        let Some(hid) = device.parent_with_subsystem("hid").ok().flatten() else {
+            continue;
+        };
+        if !is_device_duo_keyboard(&hid, config) {
+            continue;
+        }
+        let bus = hid.property_value("HID_ID").and_then(|id| id.to_str()).and_then(parse_hid_id).map(|(bus, _, _)| bus);
+        match bus {
+            Some(BUS_USB) => {
+                let number = device.parent_with_subsystem_devtype("usb", "usb_interface").ok().flatten()
+                    .and_then(|usb_interface| usb_interface.attribute_value("bInterfaceNumber")
+                        .and_then(|n| u8::from_str_radix(n.to_str()?.trim(), 16).ok()));
+                if number == Some(interface) {
+                    return device.devnode().map(Path::to_path_buf);
+                }
+            }
+            Some(BUS_BLUETOOTH) if bluetooth.is_none() => bluetooth = device.devnode().map(Path::to_path_buf),
+            _ => {}
+        }
+    }
+    bluetooth
+}
+
+/// Bus, vendor and product from a `HID_ID` like `0005:00000B05:00001BF2`.
+fn parse_hid_id(id: &str) -> Option<(u16, u32, u32)> {
+    let mut parts = id.trim().split(':');
+    let bus = u16::from_str_radix(parts.next()?, 16).ok()?;
+    let vendor = u32::from_str_radix(parts.next()?, 16).ok()?;
+    let product = u32::from_str_radix(parts.next()?, 16).ok()?;
+    Some((bus, vendor, product))
+}
+
+/// Whether `id` (hexadecimal, as in the config) is `value`.
+fn is_id(id: &str, value: u32) -> bool {
+    u32::from_str_radix(id.trim(), 16) == Ok(value)
+}
+
 fn check_property(name: &str, val: &str, vendor_match: &mut bool, product_match: &mut bool, config: &DeviceConfig) {
     match name {
-        "ID_VENDOR_ID" | "ID_VENDOR" => {
-            if val.trim().eq_ignore_ascii_case(&config.vendor_id) {
-                *vendor_match = true;
-            }
+        "ID_VENDOR_ID" | "ID_VENDOR" if val.trim().eq_ignore_ascii_case(&config.vendor_id) => {
+            *vendor_match = true;
+        }
+        "ID_MODEL_ID" | "ID_MODEL" if val.trim().eq_ignore_ascii_case(&config.product_id) => {
+            *product_match = true;
         }
-        "ID_MODEL_ID" | "ID_MODEL" => {
-            if val.trim().eq_ignore_ascii_case(&config.product_id) {
-                *product_match = true;
+        // HID devices, which also cover the keyboard connected over Bluetooth
+        "HID_ID" => {
+            if let Some((bus, vendor, product)) = parse_hid_id(val) {
+                let product_id = match &config.bluetooth_product_id {
+                    Some(id) if bus == BUS_BLUETOOTH => id,
+                    _ => &config.product_id,
+                };
+                *vendor_match |= is_id(&config.vendor_id, vendor);
+                *product_match |= is_id(product_id, product);
             }
         }
         "PRODUCT" => {
@@ -100,7 +154,7 @@ mod tests {
     use super::*;
 
     fn cfg() -> DeviceConfig {
-        DeviceConfig { vendor_id: "b05".into(), product_id: "1bf2".into() }
+        DeviceConfig { vendor_id: "b05".into(), product_id: "1bf2".into(), bluetooth_product_id: None }
     }
 
     #[test]
@@ -140,4 +194,28 @@ mod tests {
         assert!(!v);
         assert!(!p);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn hid_id_matches_over_usb_and_bluetooth() {
+        let mut v = false;
+        let mut p = false;
+        check_property("HID_ID", "0003:00000B05:00001BF2", &mut v, &mut p, &cfg());
+        assert!(v && p);
+
+        let mut v = false;
+        let mut p = false;
+        check_property("HID_ID", "0005:00000B05:00001BF2", &mut v, &mut p, &cfg());
+        assert!(v && p);
+
+        // A separate Bluetooth product ID only applies over Bluetooth.
+        let config = DeviceConfig { bluetooth_product_id: Some("1bf3".into()), ..cfg() };
+        let mut v = false;
+        let mut p = false;
+        check_property("HID_ID", "0005:00000B05:00001BF2", &mut v, &mut p, &config);
+        assert!(v && !p);
+        check_property("HID_ID", "0005:00000B05:00001BF3", &mut v, &mut p, &config);
+        assert!(p);
+
+        assert_eq!(parse_hid_id("0005:00000B05"), None);
+    }
+}
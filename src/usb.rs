@@ -1,29 +1,37 @@
 pub(crate) mod backlight;
+pub(crate) mod idle;
+pub(crate) mod led;
 
 use evdev::{Device};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
 use std::time::{Duration, Instant};
 use tokio::io::unix::AsyncFd;
+use tokio::sync::{mpsc, watch};
 use udev::{EventType, MonitorBuilder};
-use crate::config::{load_config, Config};
-use crate::monitor_handling::handle_if_changed;
+use crate::config::Config;
+use crate::usb::idle::IdleDimmer;
+use crate::drm;
+use crate::touch;
+use crate::layout::Rotation;
+use crate::monitor_handling::{apply_layout_named, get_display_manager, handle_if_changed, handle_orientation_change};
+use crate::orientation::monitor_orientation;
 use crate::udev_utils::{is_device_duo_keyboard, is_it_duo_keyboard};
 use crate::{udev_utils};
 use log::{info, error};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum DeviceState {
     Added,
     Removed,
 }
 
-pub async fn monitor_usb_events() {
-    let config = load_config();
-    let (mut current_state, mut keyboard_devpath) = check_initial_state(&config);
-    let mut last_processed_state: Option<DeviceState> = None;
-
-    // If we found it on startup, apply the state immediately
-    handle_if_changed(&current_state, &last_processed_state, &config);
-    last_processed_state = current_state.clone();
+/// Publish the keyboard's state on `states`: first the state found at startup, then every change
+/// reported by udev.
+pub async fn watch_keyboard(config: watch::Receiver<Config>, states: watch::Sender<Option<DeviceState>>) {
+    let (mut current_state, mut keyboard_devpath) = check_initial_state(&config.borrow());
+    states.send_replace(current_state);
 
     let builder = MonitorBuilder::new().expect("Failed to create udev monitor builder");
 
@@ -36,22 +44,18 @@ pub async fn monitor_usb_events() {
 
     // AsyncFd is required to poll the file descriptor in an async context
     let mut async_monitor = AsyncFd::new(monitor).expect("Failed to create AsyncFd");
-    // keyboard_devpath is already initialized above
 
     info!("Started monitoring USB events...");
 
     loop {
         // Wait for the monitor socket to be readable
-        let mut guard = async_monitor
-            .readable_mut()
-            .await
-            .expect("Failed to wait for readability");
+        let mut guard = async_monitor.readable_mut().await.expect("Failed to wait for readability");
 
         // We need to access the inner monitor to iterate over events
         let monitor = guard.get_inner_mut();
 
         for event in monitor.iter() {
-            if let Some(devpath) = is_it_duo_keyboard(&event, &keyboard_devpath, &config.device) {
+            if let Some(devpath) = is_it_duo_keyboard(&event, &keyboard_devpath, &config.borrow().device) {
                 if event.event_type() == EventType::Add {
                     keyboard_devpath = Some(devpath);
                 } else if event.event_type() == EventType::Remove {
@@ -68,10 +72,11 @@ pub async fn monitor_usb_events() {
             }
         }
 
-        if current_state != last_processed_state {
-            handle_if_changed(&current_state, &last_processed_state, &config);
-            last_processed_state = current_state.clone();
-        }
+        states.send_if_modified(|state| {
+            let changed = *state != current_state;
+            *state = current_state;
+            changed
+        });
 
         // Signal that we have processed the available events.
         // If we didn't read everything, the next poll would wake up immediately.
@@ -79,11 +84,96 @@ pub async fn monitor_usb_events() {
     }
 }
 
+/// Apply keyboard state changes to the devices themselves: the keyboard backlight level when the
+/// keyboard is attached, and the bottom panel's touch, which is inhibited while the keyboard lies
+/// on it if `touch.inhibit_bottom` is set.
+///
+/// This is the part that needs access to USB and input devices; the display layout is handled by
+/// [`follow_keyboard`] in the graphical session.
+pub async fn follow_devices(config: watch::Receiver<Config>, mut states: watch::Receiver<Option<DeviceState>>) {
+    let mut before = None;
+    loop {
+        let current = *states.borrow_and_update();
+        let config = config.borrow().clone();
+        match (current, before) {
+            (Some(DeviceState::Added), None) |
+            (Some(DeviceState::Added), Some(DeviceState::Removed)) => {
+                if config.touch.inhibit_bottom {
+                    let panels = drm::discover_panels(Path::new(drm::DRM_SYSFS), &config.panels);
+                    touch::inhibit(&touch::bottom_digitizers(&panels, &config.touch));
+                }
+
+                info!("Setting backlight level to {}", config.brightness);
+                if let Err(e) = backlight::set_backlight_level(config.backlight_level(), &config) {
+                    error!("Failed to set backlight level: {:?}. (Are you running as root or have udev rules set up?)", e);
+                }
+            }
+            (Some(DeviceState::Removed), Some(DeviceState::Added)) => touch::release(),
+            _ => {}
+        }
+        before = current;
+
+        if states.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Apply the display layout for every keyboard state published on `states`, and follow the
+/// accelerometer while the keyboard is detached if `auto_rotate` is set. Layouts named on `layouts`
+/// are applied on request.
+///
+/// `config` is read afresh for every change; only `auto_rotate` is fixed at the start.
+pub async fn follow_keyboard(
+    config: watch::Receiver<Config>,
+    mut states: watch::Receiver<Option<DeviceState>>,
+    mut layouts: mpsc::UnboundedReceiver<String>,
+) {
+    let mut last_processed_state: Option<DeviceState> = None;
+    let display_manager = get_display_manager();
+
+    // Orientation changes from the accelerometer, only followed if enabled
+    let (orientation_sender, mut orientation_changes) = tokio::sync::mpsc::unbounded_channel();
+    let mut orientation: Option<Rotation> = None;
+    if config.borrow().auto_rotate {
+        tokio::task::spawn_local(monitor_orientation(orientation_sender));
+    } else {
+        drop(orientation_sender);
+    }
+
+    loop {
+        let current_state = *states.borrow_and_update();
+        if current_state != last_processed_state {
+            let config = config.borrow().clone();
+            handle_if_changed(&current_state, &last_processed_state, orientation, &config, &display_manager).await;
+            last_processed_state = current_state;
+        }
+
+        // Wait for the next state, following rotations in the meantime
+        tokio::select! {
+            changed = states.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+            Some(rotation) = orientation_changes.recv() => {
+                orientation = Some(rotation);
+                let config = config.borrow().clone();
+                handle_orientation_change(&last_processed_state, rotation, &config, &display_manager).await;
+            }
+            Some(name) = layouts.recv() => {
+                let config = config.borrow().clone();
+                apply_layout_named(&name, &last_processed_state, orientation, &config, &display_manager).await;
+            }
+        }
+    }
+}
+
 fn update_if_not_yet(last_state: &mut Option<DeviceState>, new_state: DeviceState) {
     match last_state {
         None => *last_state = Some(new_state),
 
-        Some(last_state_value) => match (last_state_value, new_state.clone()) {
+        Some(last_state_value) => match (last_state_value, new_state) {
             (DeviceState::Added, DeviceState::Removed) => *last_state = Some(new_state),
             (DeviceState::Removed, DeviceState::Added) => *last_state = Some(new_state),
             _ => {}
@@ -115,45 +205,81 @@ pub fn check_initial_state(config: &Config) -> (Option<DeviceState>, Option<std:
     (Some(DeviceState::Removed), None)
 }
 
-pub async fn monitor_special_keys(config: Config) {
-    // Keep the "active" level locally; config.brightness is just the default.
-    let mut current_level: u8 = (config.brightness as u8).min(3);
+/// Step the keyboard backlight through its levels whenever its key is pressed, starting from the
+/// level last set (by anyone in this process) or `brightness`.
+///
+/// With a `[backlight_idle]` timeout the backlight also goes off while no key is used, and back to
+/// its level with the next key event.
+pub async fn monitor_special_keys(config: watch::Receiver<Config>) {
+    let level = backlight::level();
 
     // Debounce: ignore additional matching events right after a toggle.
     let mut last_toggle_at: Option<Instant> = None;
     let debounce_window = Duration::from_millis(250);
 
     loop {
-        if let Some(path) = udev_utils::find_keyboard_event_path(&config.device) {
-            if let Ok(mut device) = Device::open(&path) {
-                info!("Listening for special keys on {:?}", path);
-                loop {
-                    match device.fetch_events() {
-                        Ok(events) => {
-                            for event in events {
-                                if event.event_type() == evdev::EventType::MISC && event.value() == 458813 {
-                                    let now = Instant::now();
-                                    if last_toggle_at.is_some_and(|t| now.duration_since(t) < debounce_window) {
-                                        continue;
-                                    }
-                                    last_toggle_at = Some(now);
-
-                                    let next_level = (current_level + 1) % 4;
-                                    match backlight::set_backlight_level(next_level, &config) {
-                                        Ok(()) => {
-                                            current_level = next_level;
-                                            info!("Backlight toggled to level {}", current_level);
-                                        }
-                                        Err(e) => {
-                                            error!("Failed to set backlight level {}: {:?}", next_level, e);
-                                        }
-                                    }
+        let device_config = config.borrow().device.clone();
+        if let Some(path) = udev_utils::find_keyboard_event_path(&device_config)
+            && let Ok(device) = Device::open(&path)
+            && let Ok(mut events) = device.into_event_stream() {
+            info!("Listening for special keys on {:?}", path);
+            let mut dimmer = IdleDimmer::default();
+            loop {
+                let timeout = dimmer.timeout(&config.borrow().backlight_idle);
+                let event = match timeout {
+                    Some(timeout) => match tokio::time::timeout(timeout, events.next_event()).await {
+                        Ok(event) => event,
+                        Err(_) => {
+                            let config = config.borrow().clone();
+                            let on_ac = || idle::on_ac_power(Path::new(idle::POWER_SUPPLY_SYSFS));
+                            let current = *level.borrow();
+                            if let Some(current) = current
+                                && dimmer.idle(&config.backlight_idle, Some(current), on_ac) {
+                                info!("Keyboard idle, switching the backlight off");
+                                match backlight::set_backlight_level(0, &config) {
+                                    Ok(()) => dimmer.dimmed(current),
+                                    Err(e) => error!("Failed to switch the backlight off: {:?}", e),
                                 }
                             }
+                            continue;
+                        }
+                    },
+                    None => events.next_event().await,
+                };
+                let Ok(event) = event else { break }; // Device likely disconnected
+                if event.event_type() != evdev::EventType::KEY && event.event_type() != evdev::EventType::MISC {
+                    continue;
+                }
+
+                if let Some(previous) = dimmer.active(*level.borrow()) {
+                    // The key only wakes the backlight, including the backlight key itself.
+                    last_toggle_at = Some(Instant::now());
+                    let config = config.borrow().clone();
+                    match backlight::set_backlight_level(previous, &config) {
+                        Ok(()) => info!("Keyboard used, backlight restored to level {}", previous),
+                        Err(e) => error!("Failed to restore backlight level {}: {:?}", previous, e),
+                    }
+                    continue;
+                }
+
+                if event.event_type() == evdev::EventType::MISC && event.value() == 458813 {
+                    let now = Instant::now();
+                    if last_toggle_at.is_some_and(|t| now.duration_since(t) < debounce_window) {
+                        continue;
+                    }
+                    last_toggle_at = Some(now);
+
+                    let config = config.borrow().clone();
+                    let current_level = level.borrow().unwrap_or_else(|| config.backlight_level());
+                    let next_level = (current_level + 1) % 4;
+                    match backlight::set_backlight_level(next_level, &config) {
+                        Ok(()) => {
+                            info!("Backlight toggled to level {}", next_level);
+                        }
+                        Err(e) => {
+                            error!("Failed to set backlight level {}: {:?}", next_level, e);
                         }
-                        Err(_) => break, // Device likely disconnected
                     }
-                    tokio::time::sleep(Duration::from_millis(10)).await;
                 }
             }
         }
@@ -1,14 +1,33 @@
 use std::env;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::Path;
 use std::time::Duration;
 use rusb::{Context, UsbContext, DeviceHandle};
 use lazy_static::lazy_static;
 use std::sync::Mutex;
+use tokio::sync::watch;
+use crate::cli;
 use crate::config;
+use crate::control::Request;
 use crate::config::Config;
-use log::{error};
+use crate::udev_utils;
+use log::{debug, error};
+
+/// The keyboard's USB interface taking the backlight report.
+const BACKLIGHT_INTERFACE: u8 = 4;
+const REPORT_ID: u8 = 0x5A;
 
 lazy_static! {
     static ref USB_MUTEX: Mutex<()> = Mutex::new(());
+    /// The level last set by this process, to be toggled from and reported by the daemon.
+    static ref LEVEL: watch::Sender<Option<u8>> = watch::Sender::new(None);
+}
+
+/// The keyboard backlight level last set by this process, if any, following every change.
+pub fn level() -> watch::Receiver<Option<u8>> {
+    LEVEL.subscribe()
 }
 
 struct InterfaceGuard<'a, T: UsbContext> {
@@ -60,10 +79,25 @@ pub fn set_backlight_level(level: u8, config: &Config) -> Result<(), rusb::Error
         rusb::Error::InvalidParam
     })?;
 
+    let report = report(level);
+    match udev_utils::find_keyboard_hidraw(&config.device, BACKLIGHT_INTERFACE) {
+        Some(node) => match set_feature(&node, &report) {
+            Ok(()) => {
+                LEVEL.send_replace(Some(level));
+                return Ok(());
+            }
+            Err(e) => debug!("Failed to send the backlight report to {:?} ({}), trying libusb", node, e),
+        },
+        None => debug!("No hidraw node for the keyboard's interface {}, trying libusb", BACKLIGHT_INTERFACE),
+    }
+
     let mut retries = 3;
     while retries > 0 {
-        match set_backlight_internal(level, vendor_id, product_id) {
-            Ok(_) => return Ok(()),
+        match set_over_usb(&report, vendor_id, product_id) {
+            Ok(_) => {
+                LEVEL.send_replace(Some(level));
+                return Ok(());
+            }
             Err(rusb::Error::Busy) if retries > 1 => {
                 retries -= 1;
                 std::thread::sleep(Duration::from_millis(100));
@@ -75,31 +109,53 @@ pub fn set_backlight_level(level: u8, config: &Config) -> Result<(), rusb::Error
     Err(rusb::Error::Busy)
 }
 
-fn set_backlight_internal(level: u8, vendor_id: u16, product_id: u16) -> Result<(), rusb::Error> {
-    let context = Context::new()?;
-    let handle = context.open_device_with_vid_pid(vendor_id, product_id)
-        .ok_or(rusb::Error::NoDevice)?;
-
-    let report_id: u8 = 0x5A;
-    let w_value: u16 = 0x035A;
-    let w_index: u16 = 4;
-
+/// The feature report setting the backlight to `level`.
+fn report(level: u8) -> [u8; 16] {
     let mut data = [0u8; 16];
-    data[0] = report_id;
+    data[0] = REPORT_ID;
     data[1] = 0xBA;
     data[2] = 0xC5;
     data[3] = 0xC4;
     data[4] = level;
+    data
+}
+
+/// `HIDIOCSFEATURE(len)` from `<linux/hidraw.h>`: `_IOWR('H', 0x06, len)`.
+fn hidiocsfeature(len: usize) -> u32 {
+    (3 << 30) | ((len as u32) << 16) | ((b'H' as u32) << 8) | 0x06
+}
+
+/// Send `report` as a feature report through the hidraw node `node`, leaving the kernel driver and
+/// with it the keyboard's input alone.
+fn set_feature(node: &Path, report: &[u8]) -> io::Result<()> {
+    let file = OpenOptions::new().read(true).write(true).open(node)?;
+    // SAFETY: the ioctl reads `report.len()` bytes from `report`, which stays alive for the call.
+    let result = unsafe { libc::ioctl(file.as_raw_fd(), hidiocsfeature(report.len()) as _, report.as_ptr()) };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Send `report` with a SET_REPORT control transfer, detaching the kernel driver from the interface
+/// meanwhile. Needs root.
+fn set_over_usb(report: &[u8; 16], vendor_id: u16, product_id: u16) -> Result<(), rusb::Error> {
+    let context = Context::new()?;
+    let handle = context.open_device_with_vid_pid(vendor_id, product_id)
+        .ok_or(rusb::Error::NoDevice)?;
+
+    let w_value: u16 = 0x0300 | REPORT_ID as u16; // feature report
+    let w_index: u16 = BACKLIGHT_INTERFACE as u16;
 
     {
-        let _guard = InterfaceGuard::new(&handle, w_index as u8)?;
+        let _guard = InterfaceGuard::new(&handle, BACKLIGHT_INTERFACE)?;
 
         handle.write_control(
             0x21, // bmRequestType
             0x09, // bRequest (SET_REPORT)
             w_value,
             w_index,
-            &data,
+            report,
             Duration::from_secs(1),
         )?;
     }
@@ -114,7 +170,19 @@ pub fn run_backlight_command(args: &[String]) {
         std::process::exit(1);
     }
 
-    // 1. Handle Elevation
+    // 1. Ask the daemon, which owns the keyboard, so neither root nor the USB device is needed here
+    if env::var("USER").unwrap_or_default() != "root" {
+        let level = match args.get(2) {
+            Some(level_str) => parse_level(level_str),
+            None => config::load_config().backlight_level(),
+        };
+        if cli::ask_daemon(&Request::SetBacklight { level }).is_some() {
+            println!("Backlight successfully set to level {}", level);
+            return;
+        }
+    }
+
+    // 2. Without a daemon, handle elevation
     if env::var("USER").unwrap_or_default() != "root" {
         println!("Backlight control requires root privileges. Re-running with sudo...");
         let current_exe = env::current_exe().expect("Failed to get current executable path");
@@ -131,21 +199,15 @@ pub fn run_backlight_command(args: &[String]) {
         }
     }
 
-    // 2. Parse and Execute
+    // 3. Parse and Execute
     let config = config::load_config_interactive();
 
     // Check if a level was provided, otherwise use the one from config
     let level = if let Some(level_str) = args.get(2) {
-        match level_str.parse::<u8>() {
-            Ok(l) if l <= 3 => l,
-            _ => {
-                eprintln!("Invalid level '{}'. Please provide an integer between 0 and 3.", level_str);
-                std::process::exit(1);
-            }
-        }
+        parse_level(level_str)
     } else {
         println!("No level provided, using default from config: {}", config.brightness);
-        config.brightness as u8
+        config.backlight_level()
     };
 
     if let Err(e) = set_backlight_level(level, &config) {
@@ -155,6 +217,16 @@ pub fn run_backlight_command(args: &[String]) {
     println!("Backlight successfully set to level {}", level);
 }
 
+fn parse_level(level_str: &str) -> u8 {
+    match level_str.parse::<u8>() {
+        Ok(l) if l <= 3 => l,
+        _ => {
+            eprintln!("Invalid level '{}'. Please provide an integer between 0 and 3.", level_str);
+            std::process::exit(1);
+        }
+    }
+}
+
 fn print_backlight_error(err: rusb::Error, config: &Config) {
     match err {
         rusb::Error::NoDevice => {
@@ -166,4 +238,15 @@ fn print_backlight_error(err: rusb::Error, config: &Config) {
         },
         _ => eprintln!("USB Error: {:?}", err),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_the_feature_report() {
+        assert_eq!(hidiocsfeature(16), 0xC010_4806);
+        assert_eq!(report(2)[..6], [0x5A, 0xBA, 0xC5, 0xC4, 2, 0]);
+    }
+}
@@ -0,0 +1,120 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use crate::config::BacklightIdleConfig;
+
+pub const POWER_SUPPLY_SYSFS: &str = "/sys/class/power_supply";
+
+/// Whether the laptop runs on AC: any `Mains` supply under `root` is online. Without one there is no
+/// battery to spare, so that counts as AC too.
+pub fn on_ac_power(root: &Path) -> bool {
+    let Ok(entries) = fs::read_dir(root) else { return true };
+    let read = |path: &Path, name: &str| fs::read_to_string(path.join(name)).map(|s| s.trim().to_string()).unwrap_or_default();
+
+    let mut mains = entries.flatten().map(|entry| entry.path()).filter(|path| read(path, "type") == "Mains").peekable();
+    mains.peek().is_none() || mains.any(|path| read(&path, "online") == "1")
+}
+
+/// Switches the keyboard backlight off after `[backlight_idle]` without key events, and back to the
+/// level it had with the next one.
+#[derive(Debug, Default)]
+pub struct IdleDimmer {
+    /// The level before the backlight was switched off, while it is.
+    dimmed_from: Option<u8>,
+}
+
+impl IdleDimmer {
+    /// How long to wait for a key event before [`Self::idle`]; `None` while the timeout is disabled or
+    /// the backlight is already off.
+    pub fn timeout(&self, config: &BacklightIdleConfig) -> Option<Duration> {
+        (config.timeout_secs > 0 && self.dimmed_from.is_none()).then(|| Duration::from_secs(config.timeout_secs))
+    }
+
+    /// The keyboard was not used for the timeout: whether to switch the backlight off, which is lit
+    /// at `level`. `on_ac` is only asked if it matters.
+    pub fn idle(&self, config: &BacklightIdleConfig, level: Option<u8>, on_ac: impl FnOnce() -> bool) -> bool {
+        if level.is_none_or(|level| level == 0) {
+            return false;
+        }
+        match (config.on_ac, config.on_battery) {
+            (true, true) => true,
+            (false, false) => false,
+            (ac_only, _) => on_ac() == ac_only,
+        }
+    }
+
+    /// The backlight was switched off for being idle while lit at `level`.
+    pub fn dimmed(&mut self, level: u8) {
+        self.dimmed_from = Some(level);
+    }
+
+    /// The keyboard was used: the level to restore if the backlight is off for being idle, unless it
+    /// was set to something else meanwhile.
+    pub fn active(&mut self, level: Option<u8>) -> Option<u8> {
+        self.dimmed_from.take().filter(|_| level == Some(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn supply(root: &Path, name: &str, kind: &str, online: &str) {
+        fs::create_dir(root.join(name)).unwrap();
+        fs::write(root.join(name).join("type"), format!("{}\n", kind)).unwrap();
+        fs::write(root.join(name).join("online"), format!("{}\n", online)).unwrap();
+    }
+
+    #[test]
+    fn reads_the_power_source() {
+        let root = TempDir::new().unwrap();
+        assert!(on_ac_power(root.path()));
+        supply(root.path(), "BAT0", "Battery", "1");
+        assert!(on_ac_power(root.path()));
+        supply(root.path(), "ADP1", "Mains", "0");
+        assert!(!on_ac_power(root.path()));
+        supply(root.path(), "ucsi-source-psy-USBC000:001", "Mains", "1");
+        assert!(on_ac_power(root.path()));
+        assert!(on_ac_power(&root.path().join("missing")));
+    }
+
+    #[test]
+    fn restores_the_level_from_before() {
+        let config = BacklightIdleConfig { timeout_secs: 30, ..Default::default() };
+        let mut dimmer = IdleDimmer::default();
+        assert_eq!(dimmer.timeout(&BacklightIdleConfig::default()), None);
+        assert_eq!(dimmer.timeout(&config), Some(Duration::from_secs(30)));
+
+        // Nothing to switch off while the backlight is off or unknown.
+        assert!(!dimmer.idle(&config, Some(0), || true));
+        assert!(!dimmer.idle(&config, None, || true));
+        assert_eq!(dimmer.active(Some(0)), None);
+
+        assert!(dimmer.idle(&config, Some(2), || true));
+        assert_eq!(dimmer.timeout(&config), Some(Duration::from_secs(30)));
+        dimmer.dimmed(2);
+        assert_eq!(dimmer.timeout(&config), None);
+        assert_eq!(dimmer.active(Some(0)), Some(2));
+        assert_eq!(dimmer.active(Some(0)), None);
+
+        // Set to another level while off, it stays there.
+        assert!(dimmer.idle(&config, Some(3), || true));
+        dimmer.dimmed(3);
+        assert_eq!(dimmer.active(Some(1)), None);
+        assert_eq!(dimmer.timeout(&config), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn applies_only_on_the_configured_power_source() {
+        let battery_only = BacklightIdleConfig { timeout_secs: 30, on_ac: false, on_battery: true };
+        let dimmer = IdleDimmer::default();
+        assert!(!dimmer.idle(&battery_only, Some(2), || true));
+        assert!(dimmer.idle(&battery_only, Some(2), || false));
+
+        let neither = BacklightIdleConfig { on_battery: false, ..battery_only };
+        let dimmer = IdleDimmer::default();
+        assert!(!dimmer.idle(&neither, Some(2), || true));
+        assert!(!dimmer.idle(&neither, Some(2), || false));
+    }
+}
@@ -0,0 +1,123 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use tokio::sync::{mpsc, watch};
+use log::{error, info, warn};
+use crate::config::Config;
+use crate::usb::backlight;
+
+const ULEDS: &str = "/dev/uleds";
+const LEDS_SYSFS: &str = "/sys/class/leds";
+/// UPower offers every LED whose name contains `kbd_backlight` as a `KbdBacklight`.
+pub const LED_NAME: &str = "zenbook_duo::kbd_backlight";
+/// `LED_MAX_NAME_SIZE` in `<linux/uleds.h>`.
+const NAME_SIZE: usize = 64;
+const MAX_LEVEL: u8 = 3;
+
+/// `struct uleds_user_dev` for an LED called `name` with brightness from 0 to `max_brightness`.
+fn user_dev(name: &str, max_brightness: u8) -> Vec<u8> {
+    let mut dev = vec![0u8; NAME_SIZE];
+    let len = name.len().min(NAME_SIZE - 1);
+    dev[..len].copy_from_slice(&name.as_bytes()[..len]);
+    dev.extend_from_slice(&i32::from(max_brightness).to_ne_bytes());
+    dev
+}
+
+/// Register an LED called `name` through `uleds`; it exists for as long as the file is open.
+fn register(uleds: &Path, name: &str) -> io::Result<File> {
+    let mut file = OpenOptions::new().read(true).write(true).open(uleds)?;
+    file.write_all(&user_dev(name, MAX_LEVEL))?;
+    Ok(file)
+}
+
+/// Offer the keyboard backlight to the desktop as an LED class device, so that the backlight
+/// slider and OSD of GNOME and KDE drive it through UPower.
+///
+/// Setting the LED sets the keyboard's backlight, and every level set otherwise, e.g. by the
+/// special key, is written back to the LED. Needs root to register the LED.
+pub async fn expose_as_led(config: watch::Receiver<Config>) {
+    let mut uleds = match register(Path::new(ULEDS), LED_NAME) {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("Failed to register LED {} through {} ({}), desktops will not offer a keyboard backlight slider",
+                  LED_NAME, ULEDS, e);
+            return;
+        }
+    };
+    info!("Registered the keyboard backlight as LED {}", LED_NAME);
+    let brightness = Path::new(LEDS_SYSFS).join(LED_NAME).join("brightness");
+
+    // Reading blocks until the LED is set, so it gets a thread of its own.
+    let (sender, mut requests) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        let mut event = [0u8; 4];
+        while uleds.read_exact(&mut event).is_ok() {
+            if sender.send(i32::from_ne_bytes(event)).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut level = backlight::level();
+    level.mark_changed();
+    loop {
+        tokio::select! {
+            Some(requested) = requests.recv() => {
+                let requested = requested.clamp(0, i32::from(MAX_LEVEL)) as u8;
+                let current = *level.borrow();
+                // Also sees our own writes of the current level.
+                if current == Some(requested) {
+                    continue;
+                }
+
+                let config = config.borrow().clone();
+                let result = tokio::task::spawn_blocking(move || backlight::set_backlight_level(requested, &config))
+                    .await
+                    .map_err(|e| e.to_string())
+                    .and_then(|result| result.map_err(|e| format!("{:?}", e)));
+                match result {
+                    Ok(()) => info!("Backlight set to level {} through LED {}", requested, LED_NAME),
+                    Err(e) => {
+                        error!("Failed to set backlight level {} from LED {}: {}", requested, LED_NAME, e);
+                        if let Some(current) = current {
+                            write_brightness(&brightness, current);
+                        }
+                    }
+                }
+            }
+            Ok(()) = level.changed() => {
+                if let Some(current) = *level.borrow_and_update() {
+                    write_brightness(&brightness, current);
+                }
+            }
+            else => {
+                error!("Lost LED {}", LED_NAME);
+                return;
+            }
+        }
+    }
+}
+
+fn write_brightness(path: &Path, level: u8) {
+    if let Err(e) = std::fs::write(path, level.to_string()) {
+        warn!("Failed to update {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_uleds_user_dev() {
+        let dev = user_dev(LED_NAME, 3);
+        assert_eq!(dev.len(), NAME_SIZE + 4);
+        assert_eq!(&dev[..LED_NAME.len()], LED_NAME.as_bytes());
+        assert!(dev[LED_NAME.len()..NAME_SIZE].iter().all(|&b| b == 0));
+        assert_eq!(i32::from_ne_bytes(dev[NAME_SIZE..].try_into().unwrap()), 3);
+
+        // The name stays NUL-terminated however long it is.
+        let dev = user_dev(&"x".repeat(100), 3);
+        assert_eq!(dev[NAME_SIZE - 1], 0);
+    }
+}